@@ -0,0 +1,358 @@
+//! Access log of the proxied requests and connections
+//!
+//! Entries are written to the global log configured for the server and to the log of the service,
+//! when the service has its own one. HTTP-aware proxy logs every request, other proxies log every
+//! connection with the amount of data transferred in each direction.
+
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use once_cell::sync::OnceCell;
+
+static GLOBAL: OnceCell<Log> = OnceCell::new();
+
+/// Format of the access log lines
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+pub enum Format {
+    /// Common Log Format
+    Common,
+    /// Combined Log Format, Common one extended with referer and user agent
+    #[default]
+    Combined,
+    /// JSON object per line
+    Json,
+}
+
+/// Single line in the access log
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub client: IpAddr,
+    /// Domain of the service handling the request
+    pub service: String,
+    pub started: SystemTime,
+    pub duration: Duration,
+    /// HTTP request, missing for connection level entries
+    pub request: Option<Request>,
+    /// Status of the response, missing when no response was sent
+    pub status: Option<u16>,
+    /// Bytes received from the client, only counted for connections
+    pub received: u64,
+    /// Bytes sent to the client, for requests only the response body is counted
+    pub sent: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Entry {
+    fn new(client: SocketAddr, service: &str) -> Self {
+        Entry {
+            client: client.ip().to_canonical(),
+            service: service.into(),
+            started: SystemTime::now(),
+            duration: Duration::ZERO,
+            request: None,
+            status: None,
+            received: 0,
+            sent: 0,
+        }
+    }
+
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Common => self.common(),
+            Format::Combined => {
+                let (referer, user_agent) = match self.request {
+                    Some(ref request) => {
+                        (request.referer.as_deref(), request.user_agent.as_deref())
+                    }
+                    None => (None, None),
+                };
+                format!(
+                    "{} \"{}\" \"{}\"",
+                    self.common(),
+                    quote(referer.unwrap_or("-")),
+                    quote(user_agent.unwrap_or("-"))
+                )
+            }
+            Format::Json => {
+                let mut line = serde_json::json!({
+                    "time": humantime::format_rfc3339_millis(self.started).to_string(),
+                    "client": self.client,
+                    "service": self.service,
+                    "status": self.status,
+                    "bytes_received": self.received,
+                    "bytes_sent": self.sent,
+                    "duration_ms": self.duration.as_secs_f64() * 1000.0,
+                });
+                if let Some(ref request) = self.request {
+                    line["method"] = request.method.clone().into();
+                    line["path"] = request.path.clone().into();
+                    line["protocol"] = request.version.clone().into();
+                    line["referer"] = request.referer.clone().into();
+                    line["user_agent"] = request.user_agent.clone().into();
+                }
+                line.to_string()
+            }
+        }
+    }
+
+    fn common(&self) -> String {
+        // Connections have no request line, so describe the tunnel to the service instead
+        let request = match self.request {
+            Some(ref request) => format!("{} {} {}", request.method, request.path, request.version),
+            None => format!("CONNECT {} TLS", self.service),
+        };
+        let status = self
+            .status
+            .map_or_else(|| "-".to_owned(), |status| status.to_string());
+        let sent = match self.sent {
+            0 => "-".to_owned(),
+            sent => sent.to_string(),
+        };
+
+        format!(
+            "{} - - [{}] \"{}\" {} {}",
+            self.client,
+            clf_time(self.started),
+            quote(&request),
+            status,
+            sent
+        )
+    }
+}
+
+/// Escape string for use inside quotes
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Time in the `10/Oct/2000:13:55:36 +0000` format
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Civil date from the days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let doe = days % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Destination of the access log
+pub struct Log {
+    format: Format,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log {
+    /// Open log appending to the file, `-` stands for the standard output
+    pub fn open(path: &Path, format: Format) -> io::Result<Self> {
+        let out: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            Box::new(io::LineWriter::new(file))
+        };
+
+        Ok(Log {
+            format,
+            out: Mutex::new(out),
+        })
+    }
+
+    /// Log appending to the file opened by someone else, e.g. the runner of the service
+    pub fn from_file(file: std::fs::File, format: Format) -> Self {
+        Log {
+            format,
+            out: Mutex::new(Box::new(io::LineWriter::new(file))),
+        }
+    }
+
+    /// Log of the service configured in the options
+    ///
+    /// Only for the options given to the server directly, paths sent by the clients must not be
+    /// opened by the server.
+    pub fn configured(options: &crate::proxy::Options) -> Option<Arc<Self>> {
+        let path = options.access_log.as_ref()?;
+
+        Log::open(path, options.access_log_format)
+            .map(Arc::new)
+            .map_err(|err| tracing::error!(%err, ?path, "Cannot open access log"))
+            .ok()
+    }
+
+    pub fn write(&self, entry: &Entry) {
+        let line = entry.format(self.format);
+        if let Err(err) = writeln!(self.out.lock().unwrap(), "{}", line) {
+            tracing::warn!(%err, "Cannot write access log");
+        }
+    }
+}
+
+impl std::fmt::Debug for Log {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Log").field("format", &self.format).finish()
+    }
+}
+
+/// Set log receiving entries of all services
+pub fn init(log: Log) {
+    if GLOBAL.set(log).is_err() {
+        tracing::warn!("Global access log already set");
+    }
+}
+
+fn enabled(service: &crate::Service) -> bool {
+    GLOBAL.get().is_some() || service.access_log.is_some()
+}
+
+/// Entry that is written to the logs when dropped
+struct Pending {
+    entry: Entry,
+    /// Log of the service, written in addition to the global one
+    log: Option<Arc<Log>>,
+    started: Instant,
+}
+
+impl Pending {
+    fn new(service: &crate::Service, entry: Entry) -> Self {
+        Pending {
+            entry,
+            log: service.access_log.clone(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.entry.duration = self.started.elapsed();
+        if let Some(log) = GLOBAL.get() {
+            log.write(&self.entry);
+        }
+        if let Some(ref log) = self.log {
+            log.write(&self.entry);
+        }
+    }
+}
+
+/// HTTP request that is logged once the response is sent
+pub struct Exchange(Pending);
+
+impl Exchange {
+    /// Start logging the request, when access log is enabled for the service
+    pub fn start<B>(
+        service: &crate::Service,
+        domain: &str,
+        client: SocketAddr,
+        req: &hyper::Request<B>,
+    ) -> Option<Self> {
+        if !enabled(service) {
+            return None;
+        }
+
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        let mut entry = Entry::new(client, domain);
+        entry.request = Some(Request {
+            method: req.method().to_string(),
+            path: req
+                .uri()
+                .path_and_query()
+                .map_or("/", |path| path.as_str())
+                .into(),
+            version: format!("{:?}", req.version()),
+            referer: header(http::header::REFERER),
+            user_agent: header(http::header::USER_AGENT),
+        });
+
+        Some(Exchange(Pending::new(service, entry)))
+    }
+
+    /// Log the response once its body is sent, returns response that needs to be passed further
+    pub fn response(mut self, resp: hyper::Response<hyper::Body>) -> hyper::Response<hyper::Body> {
+        self.0.entry.status = Some(resp.status().as_u16());
+        let (parts, body) = resp.into_parts();
+
+        // Logged once the body is dropped, after it was sent or when the client went away
+        let mut exchange = self;
+        let body = crate::proxy::inspect_body(
+            body,
+            move |chunk| {
+                let exchange = &mut exchange;
+                exchange.0.entry.sent += chunk.len() as u64;
+            },
+            || (),
+        );
+
+        hyper::Response::from_parts(parts, body)
+    }
+}
+
+/// Connection that is logged when closed, with the amount of data transferred
+pub struct Connection(Option<Pending>);
+
+impl Connection {
+    pub fn start(service: &crate::Service, domain: &str, client: SocketAddr) -> Self {
+        Connection(enabled(service).then(|| Pending::new(service, Entry::new(client, domain))))
+    }
+
+    pub fn transferred(&mut self, received: u64, sent: u64) {
+        if let Some(ref mut pending) = self.0 {
+            pending.entry.received += received;
+            pending.entry.sent += sent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,173 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::{clf_time, Entry, Exchange, Format, Log, Request};
+
+fn at(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn request() -> Entry {
+    Entry {
+        client: "192.0.2.1".parse().unwrap(),
+        service: "app.test".into(),
+        started: at(971_185_736),
+        duration: Duration::from_micros(1500),
+        request: Some(Request {
+            method: "GET".into(),
+            path: "/search?q=\"dolores\"".into(),
+            version: "HTTP/1.1".into(),
+            referer: Some("https://app.test/".into()),
+            user_agent: Some("curl/7.85.0".into()),
+        }),
+        status: Some(200),
+        received: 0,
+        sent: 2326,
+    }
+}
+
+fn connection() -> Entry {
+    Entry {
+        client: "::1".parse().unwrap(),
+        service: "db.test".into(),
+        started: at(0),
+        duration: Duration::from_secs(2),
+        request: None,
+        status: None,
+        received: 100,
+        sent: 0,
+    }
+}
+
+#[test]
+fn clf_time_is_formatted() {
+    assert_eq!(clf_time(at(0)), "01/Jan/1970:00:00:00 +0000");
+    assert_eq!(clf_time(at(971_185_736)), "10/Oct/2000:13:48:56 +0000");
+    // Leap day and the last second of the year
+    assert_eq!(clf_time(at(951_782_400)), "29/Feb/2000:00:00:00 +0000");
+    assert_eq!(clf_time(at(1_704_067_199)), "31/Dec/2023:23:59:59 +0000");
+    assert_eq!(clf_time(at(4_107_542_400)), "01/Mar/2100:00:00:00 +0000");
+    // Times before the epoch are not expected, but do not panic
+    assert_eq!(
+        clf_time(SystemTime::UNIX_EPOCH - Duration::from_secs(1)),
+        "01/Jan/1970:00:00:00 +0000"
+    );
+}
+
+#[test]
+fn common_format() {
+    assert_eq!(
+        request().format(Format::Common),
+        "192.0.2.1 - - [10/Oct/2000:13:48:56 +0000] \"GET /search?q=\\\"dolores\\\" HTTP/1.1\" 200 2326"
+    );
+    assert_eq!(
+        connection().format(Format::Common),
+        "::1 - - [01/Jan/1970:00:00:00 +0000] \"CONNECT db.test TLS\" - -"
+    );
+}
+
+#[test]
+fn combined_format() {
+    let mut entry = request();
+    assert_eq!(
+        entry.format(Format::Combined),
+        "192.0.2.1 - - [10/Oct/2000:13:48:56 +0000] \"GET /search?q=\\\"dolores\\\" HTTP/1.1\" 200 2326 \"https://app.test/\" \"curl/7.85.0\""
+    );
+
+    entry.request.as_mut().unwrap().user_agent = Some("agent \"quoted\" \\ slash".into());
+    entry.request.as_mut().unwrap().referer = None;
+    assert!(entry
+        .format(Format::Combined)
+        .ends_with(" \"-\" \"agent \\\"quoted\\\" \\\\ slash\""));
+
+    assert!(connection()
+        .format(Format::Combined)
+        .ends_with("\"CONNECT db.test TLS\" - - \"-\" \"-\""));
+}
+
+#[test]
+fn json_format() {
+    let line: serde_json::Value = serde_json::from_str(&request().format(Format::Json)).unwrap();
+    assert_eq!(
+        line,
+        serde_json::json!({
+            "time": "2000-10-10T13:48:56.000Z",
+            "client": "192.0.2.1",
+            "service": "app.test",
+            "status": 200,
+            "bytes_received": 0,
+            "bytes_sent": 2326,
+            "duration_ms": 1.5,
+            "method": "GET",
+            "path": "/search?q=\"dolores\"",
+            "protocol": "HTTP/1.1",
+            "referer": "https://app.test/",
+            "user_agent": "curl/7.85.0",
+        })
+    );
+
+    let line: serde_json::Value = serde_json::from_str(&connection().format(Format::Json)).unwrap();
+    assert_eq!(line["status"], serde_json::Value::Null);
+    assert_eq!(line["bytes_received"], 100);
+    assert!(line.get("method").is_none());
+}
+
+/// Service logging to a temporary file in the common format
+fn service() -> (crate::Service, std::path::PathBuf) {
+    let path = std::env::temp_dir().join(format!("dolores-{:x}.log", rand::random::<u64>()));
+    let file = std::fs::File::create(&path).unwrap();
+    let mut service = crate::Service::new(
+        "app.test",
+        "[::1]:8000".parse().unwrap(),
+        crate::proxy::Type::Http,
+        &Default::default(),
+    );
+    service.access_log = Some(Arc::new(Log::from_file(file, Format::Common)));
+
+    (service, path)
+}
+
+fn exchange(service: &crate::Service) -> Exchange {
+    let req = hyper::Request::get("/index.html")
+        .body(hyper::Body::empty())
+        .unwrap();
+    Exchange::start(service, "app.test", "[::1]:5000".parse().unwrap(), &req).unwrap()
+}
+
+#[tokio::test]
+async fn exchange_is_logged_once_body_is_sent() {
+    let (service, path) = service();
+
+    let response = exchange(&service).response(hyper::Response::new("hello".into()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+    hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        log.starts_with("::1 - - [") && log.ends_with("] \"GET /index.html HTTP/1.1\" 200 5\n"),
+        "{}",
+        log
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn exchange_without_body_is_logged_at_once() {
+    let (service, path) = service();
+
+    let mut response = hyper::Response::new(hyper::Body::empty());
+    *response.status_mut() = hyper::StatusCode::NO_CONTENT;
+    let response = exchange(&service).response(response);
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        log.ends_with("] \"GET /index.html HTTP/1.1\" 204 -\n"),
+        "{}",
+        log
+    );
+    // Size of the body stays known
+    assert!(hyper::body::HttpBody::is_end_stream(response.body()));
+
+    std::fs::remove_file(&path).unwrap();
+}
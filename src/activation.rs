@@ -0,0 +1,80 @@
+//! Sockets passed by the service manager, as in the systemd socket activation
+//!
+//! The same protocol is used by the runner to pass sockets to the applications: `LISTEN_FDS`
+//! sockets starting at FD 3, named by colon separated `LISTEN_FDNAMES`, meant only for the process
+//! with `LISTEN_PID`.
+
+use std::io;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+/// First passed socket, following the standard streams
+pub const FIRST_FD: RawFd = 3;
+
+#[derive(Debug)]
+pub struct Socket {
+    /// Name of the socket, `unknown` when it is not given
+    pub name: String,
+    pub fd: OwnedFd,
+}
+
+/// Take sockets passed to this process
+///
+/// Variables are removed from the environment, so they are not inherited by spawned processes,
+/// which is why it should be called before any other threads are started.
+pub fn take() -> io::Result<Vec<Socket>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let count = std::env::var("LISTEN_FDS").ok();
+    let names = std::env::var("LISTEN_FDNAMES").ok();
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+
+    passed(pid, count, names, std::process::id())?
+        .into_iter()
+        .map(|(fd, name)| {
+            // Sockets are not meant for the applications started by the server
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+            Ok(Socket {
+                name,
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            })
+        })
+        .collect()
+}
+
+/// Descriptors and names of the sockets passed to the process by the variables
+fn passed(
+    pid: Option<String>,
+    count: Option<String>,
+    names: Option<String>,
+    process: u32,
+) -> io::Result<Vec<(RawFd, String)>> {
+    let (pid, count) = match (pid, count) {
+        (Some(pid), Some(count)) => (pid, count),
+        _ => return Ok(Vec::new()),
+    };
+    if pid.parse() != Ok(process) {
+        tracing::debug!(%pid, "Sockets passed to another process");
+        return Ok(Vec::new());
+    }
+    let count: RawFd = count
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid LISTEN_FDS"))?;
+    let mut names = names.as_deref().unwrap_or_default().split(':');
+
+    Ok((FIRST_FD..FIRST_FD + count)
+        .map(|fd| {
+            let name = match names.next() {
+                Some(name) if !name.is_empty() => name,
+                _ => "unknown",
+            };
+            (fd, name.into())
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests;
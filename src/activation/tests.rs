@@ -0,0 +1,53 @@
+use super::{passed, FIRST_FD};
+
+fn var(value: &str) -> Option<String> {
+    Some(value.into())
+}
+
+#[test]
+fn sockets_are_named_in_order() {
+    let sockets = passed(var("42"), var("3"), var("tls:http"), 42).unwrap();
+
+    assert_eq!(
+        sockets,
+        [
+            (FIRST_FD, "tls".to_string()),
+            (FIRST_FD + 1, "http".to_string()),
+            (FIRST_FD + 2, "unknown".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn names_are_optional() {
+    let sockets = passed(var("42"), var("2"), None, 42).unwrap();
+    assert_eq!(
+        sockets,
+        [
+            (FIRST_FD, "unknown".to_string()),
+            (FIRST_FD + 1, "unknown".to_string()),
+        ]
+    );
+
+    let sockets = passed(var("42"), var("2"), var(":dashboard"), 42).unwrap();
+    assert_eq!(sockets[0].1, "unknown");
+    assert_eq!(sockets[1].1, "dashboard");
+}
+
+#[test]
+fn sockets_of_other_processes_are_ignored() {
+    assert!(passed(var("43"), var("1"), var("tls"), 42)
+        .unwrap()
+        .is_empty());
+    assert!(passed(var("self"), var("1"), None, 42).unwrap().is_empty());
+    assert!(passed(None, var("1"), None, 42).unwrap().is_empty());
+    assert!(passed(var("42"), None, None, 42).unwrap().is_empty());
+}
+
+#[test]
+fn invalid_count_is_an_error() {
+    let err = passed(var("42"), var("many"), None, 42).unwrap_err();
+
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(err.to_string(), "invalid LISTEN_FDS");
+}
@@ -0,0 +1,328 @@
+//! Recording of the HTTP traffic passing through the HTTP-aware proxy
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub mod har;
+mod replay;
+
+pub use replay::{diff, find, replay, Edit};
+
+#[cfg(test)]
+mod tests;
+
+/// Amount of exchanges kept per service
+pub const CAPACITY: usize = 100;
+
+/// Maximal amount of bytes recorded from each body
+pub const BODY_LIMIT: usize = 64 * 1024;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Format in which the captured traffic can be exported
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+pub enum Format {
+    /// HTTP Archive 1.2, supported by browser developer tools
+    #[default]
+    Har,
+}
+
+impl Format {
+    pub fn export(self, exchanges: &[Arc<Exchange>]) -> String {
+        match self {
+            Format::Har => serde_json::to_string_pretty(&har::export(exchanges)).unwrap(),
+        }
+    }
+}
+
+/// Single request together with its response
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Exchange {
+    /// Identifier unique across all services
+    pub id: u64,
+    /// Domain of the service which handled the request
+    pub service: String,
+    pub started: SystemTime,
+    /// Time until the whole response was received
+    pub duration: Duration,
+    pub request: Request,
+    pub response: Option<Response>,
+    /// Reason why the response was not received
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Request {
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Response {
+    pub status: u16,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+/// Recorded message body, possibly truncated to [`BODY_LIMIT`]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Body {
+    pub data: Vec<u8>,
+    /// Total size of the body
+    pub size: usize,
+}
+
+impl Exchange {
+    /// Path and query of the request
+    pub fn path(&self) -> String {
+        match self.request.uri.parse::<http::Uri>() {
+            Ok(uri) => uri
+                .path_and_query()
+                .map_or("/", |path| path.as_str())
+                .into(),
+            Err(_) => self.request.uri.clone(),
+        }
+    }
+
+    pub fn started_at(&self) -> humantime::Rfc3339Timestamp {
+        humantime::format_rfc3339_millis(self.started)
+    }
+
+    pub fn duration_ms(&self) -> u128 {
+        self.duration.as_millis()
+    }
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+}
+
+fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn headers(map: &http::HeaderMap) -> Vec<(String, String)> {
+    map.iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.as_str().to_owned(), value)
+        })
+        .collect()
+}
+
+impl Body {
+    pub fn new(data: &[u8]) -> Self {
+        let mut body = Body::default();
+        body.extend(data);
+        body
+    }
+
+    fn extend(&mut self, chunk: &[u8]) {
+        let available = BODY_LIMIT.saturating_sub(self.data.len());
+        self.data
+            .extend_from_slice(&chunk[..chunk.len().min(available)]);
+        self.size += chunk.len();
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.size > self.data.len()
+    }
+
+    /// Complete body as text, if it is one
+    pub fn text(&self) -> Option<&str> {
+        if self.is_truncated() {
+            return None;
+        }
+        std::str::from_utf8(&self.data).ok()
+    }
+
+    /// Human readable representation of the body, depending on the content type
+    pub fn pretty(&self, content_type: Option<&str>) -> String {
+        let content_type = content_type.unwrap_or_default();
+        let mut out = String::new();
+
+        if content_type.contains("json") {
+            if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&self.data) {
+                out = serde_json::to_string_pretty(&value).unwrap_or_default();
+            }
+        } else if content_type.starts_with("application/x-www-form-urlencoded") {
+            for (key, value) in form_urlencoded::parse(&self.data) {
+                let _ = writeln!(out, "{key} = {value}");
+            }
+        }
+
+        if out.is_empty() {
+            out = match std::str::from_utf8(&self.data) {
+                Ok(text) => text.to_owned(),
+                Err(_) => format!("({} bytes of binary data)", self.data.len()),
+            };
+        }
+
+        if self.is_truncated() {
+            let _ = write!(out, "\n… (truncated, {} bytes in total)", self.size);
+        }
+
+        out
+    }
+}
+
+/// Ring buffer of the recent exchanges of single service
+#[derive(Debug)]
+pub struct Buffer {
+    exchanges: Mutex<VecDeque<Arc<Exchange>>>,
+    capacity: usize,
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::new(CAPACITY)
+    }
+}
+
+impl Buffer {
+    pub fn new(capacity: usize) -> Self {
+        Buffer {
+            exchanges: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, exchange: Exchange) {
+        let mut exchanges = self.exchanges.lock().unwrap();
+        if exchanges.len() >= self.capacity {
+            exchanges.pop_front();
+        }
+        exchanges.push_back(Arc::new(exchange));
+    }
+
+    /// Recorded exchanges, the most recent first
+    pub fn list(&self) -> Vec<Arc<Exchange>> {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Exchange>> {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|exchange| exchange.id == id)
+            .cloned()
+    }
+}
+
+/// Exchange that is being recorded while passing through the proxy
+///
+/// Bodies are recorded as they are streamed, so the exchange is added to the buffer once the
+/// response body is fully received.
+#[derive(Clone)]
+pub struct Recorder {
+    buffer: Arc<Buffer>,
+    exchange: Arc<Mutex<Exchange>>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Start recording the request, returns request that needs to be passed further
+    pub fn start(
+        buffer: Arc<Buffer>,
+        service: &str,
+        req: hyper::Request<hyper::Body>,
+    ) -> (Self, hyper::Request<hyper::Body>) {
+        let (parts, body) = req.into_parts();
+        let exchange = Exchange {
+            id: next_id(),
+            service: service.into(),
+            started: SystemTime::now(),
+            duration: Duration::ZERO,
+            request: Request {
+                method: parts.method.to_string(),
+                uri: parts.uri.to_string(),
+                version: format!("{:?}", parts.version),
+                headers: headers(&parts.headers),
+                body: Body::default(),
+            },
+            response: None,
+            error: None,
+        };
+
+        let recorder = Recorder {
+            buffer,
+            exchange: Arc::new(Mutex::new(exchange)),
+            started: Instant::now(),
+        };
+
+        let exchange = recorder.exchange.clone();
+        let body = crate::proxy::inspect_body(
+            body,
+            move |chunk| exchange.lock().unwrap().request.body.extend(chunk),
+            || (),
+        );
+
+        (recorder, hyper::Request::from_parts(parts, body))
+    }
+
+    /// Record response, returns response that needs to be passed further
+    pub fn response(self, resp: hyper::Response<hyper::Body>) -> hyper::Response<hyper::Body> {
+        let (parts, body) = resp.into_parts();
+        self.exchange.lock().unwrap().response = Some(Response {
+            status: parts.status.as_u16(),
+            version: format!("{:?}", parts.version),
+            headers: headers(&parts.headers),
+            body: Body::default(),
+        });
+
+        let exchange = self.exchange.clone();
+        let body = crate::proxy::inspect_body(
+            body,
+            move |chunk| {
+                if let Some(ref mut response) = exchange.lock().unwrap().response {
+                    response.body.extend(chunk);
+                }
+            },
+            move || self.finish(),
+        );
+
+        hyper::Response::from_parts(parts, body)
+    }
+
+    /// Finish recording with the reason why there is no response
+    pub fn error(self, error: &dyn std::fmt::Display) {
+        self.exchange.lock().unwrap().error = Some(error.to_string());
+        self.finish();
+    }
+
+    fn finish(self) {
+        let mut exchange = self.exchange.lock().unwrap().clone();
+        exchange.duration = self.started.elapsed();
+        tracing::trace!(id = exchange.id, "Exchange captured");
+        self.buffer.push(exchange);
+    }
+}
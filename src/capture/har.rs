@@ -0,0 +1,165 @@
+//! Export of the captured exchanges in [HTTP Archive 1.2][har] format
+//!
+//! [har]: http://www.softwareishard.com/blog/har-12-spec/
+
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use super::{Body, Exchange};
+
+/// HAR document with the exchanges, ordered from the oldest one
+pub fn export(exchanges: &[Arc<Exchange>]) -> Value {
+    let mut entries: Vec<_> = exchanges.iter().map(|exchange| entry(exchange)).collect();
+    entries.sort_by_key(|(started, _)| *started);
+
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "entries": entries.into_iter().map(|(_, entry)| entry).collect::<Vec<_>>(),
+        }
+    })
+}
+
+fn entry(exchange: &Exchange) -> (std::time::SystemTime, Value) {
+    let request = &exchange.request;
+    let time = exchange.duration.as_secs_f64() * 1000.0;
+
+    let url = url(exchange);
+    let query: Vec<_> = form_urlencoded::parse(url.query().unwrap_or_default().as_bytes())
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect();
+
+    let mut har_request = json!({
+        "method": request.method,
+        "url": url.to_string(),
+        "httpVersion": request.version,
+        "cookies": [],
+        "headers": headers(&request.headers),
+        "queryString": query,
+        "headersSize": -1,
+        "bodySize": request.body.size,
+    });
+    if request.body.size > 0 {
+        let mime_type = request.header("content-type").unwrap_or_default();
+        har_request["postData"] = post_data(&request.body, mime_type);
+    }
+
+    let har_response = match exchange.response {
+        Some(ref response) => {
+            let mime_type = response.header("content-type").unwrap_or_default();
+            json!({
+                "status": response.status,
+                "statusText": http::StatusCode::from_u16(response.status)
+                    .ok()
+                    .and_then(|status| status.canonical_reason())
+                    .unwrap_or_default(),
+                "httpVersion": response.version,
+                "cookies": [],
+                "headers": headers(&response.headers),
+                "content": content(&response.body, mime_type),
+                "redirectURL": response.header("location").unwrap_or_default(),
+                "headersSize": -1,
+                "bodySize": response.body.size,
+            })
+        }
+        // Same as browsers do for requests that failed
+        None => json!({
+            "status": 0,
+            "statusText": "",
+            "httpVersion": "",
+            "cookies": [],
+            "headers": [],
+            "content": { "size": 0, "mimeType": "" },
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": -1,
+            "_error": exchange.error,
+        }),
+    };
+
+    let entry = json!({
+        "startedDateTime": exchange.started_at().to_string(),
+        "time": time,
+        "request": har_request,
+        "response": har_response,
+        "cache": {},
+        "timings": { "send": 0, "wait": time, "receive": 0 },
+        "comment": format!("#{}", exchange.id),
+    });
+
+    (exchange.started, entry)
+}
+
+/// Absolute URL of the request as requested by the client
+fn url(exchange: &Exchange) -> http::Uri {
+    let uri = exchange
+        .request
+        .uri
+        .parse::<http::Uri>()
+        .unwrap_or_default();
+    let authority = uri
+        .authority()
+        .map(|authority| authority.as_str())
+        .or_else(|| exchange.request.header("host"))
+        .unwrap_or(&exchange.service);
+
+    http::Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query(exchange.path())
+        .build()
+        .unwrap_or(uri)
+}
+
+fn headers(headers: &[(String, String)]) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+/// Body of the request, as the parameters when it is a submitted form
+///
+/// HAR does not allow both the text and the parameters, so only one of them is given.
+fn post_data(body: &Body, mime_type: &str) -> Value {
+    let mut post_data = content(body, mime_type);
+    let fields = post_data.as_object_mut().unwrap();
+    fields.remove("size");
+
+    if mime_type.starts_with("application/x-www-form-urlencoded") {
+        fields.remove("text");
+        fields.remove("encoding");
+        let params: Vec<_> = form_urlencoded::parse(&body.data)
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect();
+        post_data["params"] = params.into();
+    }
+
+    post_data
+}
+
+/// Body of the message, binary data are encoded in Base64
+fn content(body: &Body, mime_type: &str) -> Value {
+    let mut content = json!({
+        "size": body.size,
+        "mimeType": mime_type,
+    });
+
+    match std::str::from_utf8(&body.data) {
+        Ok(text) => content["text"] = text.into(),
+        Err(_) => {
+            content["text"] = base64::encode(&body.data).into();
+            content["encoding"] = "base64".into();
+        }
+    }
+    if body.is_truncated() {
+        content["comment"] = format!("truncated to {} bytes", body.data.len()).into();
+    }
+
+    content
+}
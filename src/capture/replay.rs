@@ -0,0 +1,204 @@
+//! Sending captured requests to the application again
+
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use hyper::body::HttpBody;
+use tokio::net::TcpStream;
+
+use super::{Body, Exchange, Request, Response};
+use crate::registry::RegistryStore;
+use crate::service::Service;
+
+/// Headers describing the message framing, these are recomputed for the replayed body
+const FRAMING: &[&str] = &[
+    "connection",
+    "content-length",
+    "keep-alive",
+    "transfer-encoding",
+];
+
+/// Changes applied to the captured request before it is replayed
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Edit {
+    /// Replace all headers of the request
+    pub headers: Option<Vec<(String, String)>>,
+    /// Headers to set, replacing all headers of the same name
+    pub set: Vec<(String, String)>,
+    /// Names of the headers to remove
+    pub remove: Vec<String>,
+    /// Replace body of the request
+    pub body: Option<Vec<u8>>,
+}
+
+impl Edit {
+    pub fn apply(&self, original: &Request) -> Request {
+        let mut headers = self
+            .headers
+            .clone()
+            .unwrap_or_else(|| original.headers.clone());
+        headers.retain(|(name, _)| {
+            let matches = |other: &String| other.eq_ignore_ascii_case(name);
+            !self.remove.iter().any(matches) && !self.set.iter().map(|(name, _)| name).any(matches)
+        });
+        headers.extend(self.set.iter().cloned());
+
+        Request {
+            method: original.method.clone(),
+            uri: original.uri.clone(),
+            version: original.version.clone(),
+            headers,
+            body: match self.body {
+                Some(ref data) => Body::new(data),
+                None => original.body.clone(),
+            },
+        }
+    }
+}
+
+/// Find captured exchange with given ID together with the service that handled it
+pub async fn find(services: &RegistryStore, id: u64) -> Option<(Service, Arc<Exchange>)> {
+    services.read().await.values().find_map(|service| {
+        let exchange = service.capture.as_ref()?.get(id)?;
+        Some((service.clone(), exchange))
+    })
+}
+
+/// Send edited request of the exchange to the service again
+///
+/// The new exchange is recorded in the capture buffer of the service, so it can be inspected the
+/// same way as the original one. Failure to receive response is recorded in the exchange, error is
+/// returned only when the request cannot be replayed at all.
+pub async fn replay(service: &Service, original: &Exchange, edit: &Edit) -> io::Result<Exchange> {
+    let request = edit.apply(&original.request);
+    if request.body.is_truncated() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "request body was not captured completely, it needs to be replaced",
+        ));
+    }
+
+    let started = Instant::now();
+    let mut exchange = Exchange {
+        id: super::next_id(),
+        service: original.service.clone(),
+        started: SystemTime::now(),
+        duration: Duration::ZERO,
+        request,
+        response: None,
+        error: None,
+    };
+    tracing::debug!(original = original.id, id = exchange.id, "Replay");
+
+    match send(service, &exchange.service, &exchange.request).await {
+        Ok(response) => exchange.response = Some(response),
+        Err(err) => exchange.error = Some(err.to_string()),
+    }
+    exchange.duration = started.elapsed();
+
+    if let Some(ref buffer) = service.capture {
+        buffer.push(exchange.clone());
+    }
+
+    Ok(exchange)
+}
+
+async fn send(service: &Service, domain: &str, request: &Request) -> io::Result<Response> {
+    let h2 = request.version == format!("{:?}", http::Version::HTTP_2);
+
+    let mut req = hyper::Request::builder()
+        .method(request.method.as_str())
+        .uri(request.uri.as_str())
+        .version(if h2 {
+            http::Version::HTTP_2
+        } else {
+            http::Version::HTTP_11
+        });
+    for (name, value) in &request.headers {
+        if !FRAMING
+            .iter()
+            .any(|framing| framing.eq_ignore_ascii_case(name))
+        {
+            req = req.header(name, value);
+        }
+    }
+    let req = req
+        .body(hyper::Body::from(request.body.data.clone()))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let mut stream = TcpStream::connect(service.addr).await?;
+    if let Some(version) = service.options.proxy_protocol {
+        let header = crate::proxy::protocol::Header {
+            source: stream.local_addr()?,
+            destination: stream.peer_addr()?,
+            sni: Some(domain),
+            alpn: None,
+        };
+        header.write_to(version, &mut stream).await?;
+    }
+
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(h2)
+        .handshake(stream)
+        .await
+        .map_err(io::Error::other)?;
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            tracing::debug!(%err, "Replay connection failed");
+        }
+    });
+
+    let (parts, mut body) = sender
+        .send_request(req)
+        .await
+        .map_err(io::Error::other)?
+        .into_parts();
+
+    let mut recorded = Body::default();
+    while let Some(chunk) = body.data().await {
+        recorded.extend(&chunk.map_err(io::Error::other)?);
+    }
+
+    Ok(Response {
+        status: parts.status.as_u16(),
+        version: format!("{:?}", parts.version),
+        headers: super::headers(&parts.headers),
+        body: recorded,
+    })
+}
+
+/// Unified diff between responses of the original and the replayed exchange
+pub fn diff(original: &Exchange, replayed: &Exchange) -> String {
+    let old = describe(original);
+    let new = describe(replayed);
+
+    similar::TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(&format!("#{}", original.id), &format!("#{}", replayed.id))
+        .to_string()
+}
+
+/// Textual representation of the response used for comparison
+fn describe(exchange: &Exchange) -> String {
+    let response = match exchange.response {
+        Some(ref response) => response,
+        None => {
+            let error = exchange.error.as_deref().unwrap_or("no response");
+            return format!("error: {}\n", error);
+        }
+    };
+
+    let mut out = format!("{} {}\n", response.version, response.status);
+    let mut headers = response.headers.clone();
+    headers.sort();
+    for (name, value) in headers {
+        let _ = writeln!(out, "{}: {}", name, value);
+    }
+    out.push('\n');
+    out.push_str(&response.body.pretty(response.header("content-type")));
+    out.push('\n');
+
+    out
+}
@@ -0,0 +1,357 @@
+use std::sync::Arc;
+
+use hyper::body::HttpBody;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use super::{Body, Buffer, Edit, Exchange, Format, Recorder, Request, BODY_LIMIT};
+use crate::proxy::{Options, Type};
+use crate::service::Service;
+
+const DOMAIN: &str = "foo.localhost";
+
+/// Pass the request with the response through the recorder, as the proxy does
+async fn record(
+    buffer: &Arc<Buffer>,
+    req: hyper::Request<hyper::Body>,
+    resp: hyper::Response<hyper::Body>,
+) {
+    let (recorder, req) = Recorder::start(buffer.clone(), DOMAIN, req);
+    hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let resp = recorder.response(resp);
+    hyper::body::to_bytes(resp.into_body()).await.unwrap();
+}
+
+fn export(buffer: &Buffer) -> Value {
+    serde_json::from_str(&Format::Har.export(&buffer.list())).unwrap()
+}
+
+#[tokio::test]
+async fn recorded_exchange_is_exported_to_har() {
+    let buffer = Arc::new(Buffer::default());
+    let req = hyper::Request::post("/submit?page=2&q=a+b")
+        .header("host", DOMAIN)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(hyper::Body::from("name=J%C3%BAlia&tags=a&tags=b"))
+        .unwrap();
+    let resp = hyper::Response::builder()
+        .status(201)
+        .header("content-type", "application/json")
+        .header("location", "/submit/1")
+        .body(hyper::Body::from(r#"{"id":1}"#))
+        .unwrap();
+    record(&buffer, req, resp).await;
+
+    let har = export(&buffer);
+    let log = &har["log"];
+    assert_eq!(log["version"], "1.2");
+    assert_eq!(log["creator"]["name"], "dolores");
+    let entries = log["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let request = &entries[0]["request"];
+    assert_eq!(request["method"], "POST");
+    assert_eq!(request["url"], "https://foo.localhost/submit?page=2&q=a+b");
+    assert_eq!(request["httpVersion"], "HTTP/1.1");
+    assert_eq!(
+        request["queryString"],
+        json!([{ "name": "page", "value": "2" }, { "name": "q", "value": "a b" }])
+    );
+    assert_eq!(request["bodySize"], 29);
+    assert_eq!(
+        request["postData"],
+        json!({
+            "mimeType": "application/x-www-form-urlencoded",
+            "params": [
+                { "name": "name", "value": "Júlia" },
+                { "name": "tags", "value": "a" },
+                { "name": "tags", "value": "b" },
+            ],
+        })
+    );
+
+    let response = &entries[0]["response"];
+    assert_eq!(response["status"], 201);
+    assert_eq!(response["statusText"], "Created");
+    assert_eq!(response["redirectURL"], "/submit/1");
+    assert_eq!(
+        response["content"],
+        json!({ "size": 8, "mimeType": "application/json", "text": r#"{"id":1}"# })
+    );
+    assert!(response["headers"]
+        .as_array()
+        .unwrap()
+        .contains(&json!({ "name": "content-type", "value": "application/json" })));
+}
+
+#[tokio::test]
+async fn har_entries_are_ordered_from_oldest() {
+    let buffer = Arc::new(Buffer::default());
+    for path in ["/first", "/second"] {
+        let req = hyper::Request::get(path)
+            .header("host", DOMAIN)
+            .body(hyper::Body::empty())
+            .unwrap();
+        record(&buffer, req, hyper::Response::new(hyper::Body::empty())).await;
+    }
+
+    let har = export(&buffer);
+    let urls: Vec<_> = har["log"]["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["request"]["url"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(
+        urls,
+        [
+            "https://foo.localhost/first",
+            "https://foo.localhost/second"
+        ]
+    );
+    // Request without body has no `postData`
+    assert!(har["log"]["entries"][0]["request"]
+        .get("postData")
+        .is_none());
+}
+
+#[tokio::test]
+async fn binary_and_truncated_bodies_in_har() {
+    let buffer = Arc::new(Buffer::default());
+    let req = hyper::Request::put("/upload")
+        .header("host", DOMAIN)
+        .header("content-type", "application/octet-stream")
+        .body(hyper::Body::from(vec![0xff, 0x00, 0xfe]))
+        .unwrap();
+    let resp = hyper::Response::new(hyper::Body::from("x".repeat(BODY_LIMIT + 10)));
+    record(&buffer, req, resp).await;
+
+    let har = export(&buffer);
+    let entry = &har["log"]["entries"][0];
+    assert_eq!(
+        entry["request"]["postData"],
+        json!({
+            "mimeType": "application/octet-stream",
+            "text": "/wD+",
+            "encoding": "base64",
+        })
+    );
+    let content = &entry["response"]["content"];
+    assert_eq!(content["size"], BODY_LIMIT + 10);
+    assert_eq!(content["text"].as_str().unwrap().len(), BODY_LIMIT);
+    assert_eq!(
+        content["comment"],
+        format!("truncated to {} bytes", BODY_LIMIT)
+    );
+}
+
+#[test]
+fn failed_exchange_in_har() {
+    let buffer = Arc::new(Buffer::default());
+    let (recorder, _) = Recorder::start(
+        buffer.clone(),
+        DOMAIN,
+        hyper::Request::get("/").body(hyper::Body::empty()).unwrap(),
+    );
+    recorder.error(&"connection reset");
+
+    let har = export(&buffer);
+    let response = &har["log"]["entries"][0]["response"];
+    assert_eq!(response["status"], 0);
+    assert_eq!(response["bodySize"], -1);
+    assert_eq!(response["_error"], "connection reset");
+    // URL falls back to the domain of the service without `Host`
+    assert_eq!(
+        har["log"]["entries"][0]["request"]["url"],
+        "https://foo.localhost/"
+    );
+}
+
+fn request(headers: &[(&str, &str)], body: &str) -> Request {
+    Request {
+        method: "POST".into(),
+        uri: "/echo?x=1".into(),
+        version: "HTTP/1.1".into(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        body: Body::new(body.as_bytes()),
+    }
+}
+
+#[test]
+fn edit_removes_then_sets_headers() {
+    let original = request(
+        &[
+            ("Host", DOMAIN),
+            ("Accept", "text/html"),
+            ("Cookie", "a=1"),
+            ("X-Old", "1"),
+        ],
+        "body",
+    );
+    let edit = Edit {
+        set: vec![("accept".into(), "application/json".into())],
+        remove: vec!["cookie".into()],
+        ..Edit::default()
+    };
+
+    let edited = edit.apply(&original);
+    assert_eq!(
+        edited.headers,
+        [
+            ("Host".to_owned(), DOMAIN.to_owned()),
+            ("X-Old".to_owned(), "1".to_owned()),
+            ("accept".to_owned(), "application/json".to_owned()),
+        ]
+    );
+    assert_eq!(edited.body.data, b"body");
+    assert_eq!(edited.uri, original.uri);
+
+    let edit = Edit {
+        headers: Some(vec![("Host".into(), DOMAIN.into())]),
+        body: Some(b"new".to_vec()),
+        ..Edit::default()
+    };
+    let edited = edit.apply(&original);
+    assert_eq!(edited.headers, [("Host".to_owned(), DOMAIN.to_owned())]);
+    assert_eq!(edited.body.data, b"new");
+    assert_eq!(edited.body.size, 3);
+}
+
+/// Application responding with the request it received, as JSON
+async fn echo_app() -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = hyper::service::service_fn(|req: hyper::Request<hyper::Body>| async {
+                let (parts, mut body) = req.into_parts();
+                let mut data = Vec::new();
+                while let Some(chunk) = body.data().await {
+                    data.extend_from_slice(&chunk?);
+                }
+                let header = |name| {
+                    parts
+                        .headers
+                        .get(name)
+                        .map(|value: &http::HeaderValue| value.to_str().unwrap().to_owned())
+                };
+                let echo = json!({
+                    "uri": parts.uri.to_string(),
+                    "accept": header("accept"),
+                    "cookie": header("cookie"),
+                    "length": header("content-length"),
+                    "body": String::from_utf8(data).unwrap(),
+                });
+                let resp = hyper::Response::builder()
+                    .header("content-type", "application/json")
+                    .body(hyper::Body::from(echo.to_string()))
+                    .unwrap();
+                Ok::<_, hyper::Error>(resp)
+            });
+            tokio::spawn(hyper::server::conn::Http::new().serve_connection(stream, service));
+        }
+    });
+
+    addr
+}
+
+fn service(addr: std::net::SocketAddr) -> Service {
+    let options = Options {
+        capture: true,
+        ..Options::default()
+    };
+    Service::new(DOMAIN, addr, Type::Http, &options)
+}
+
+#[tokio::test]
+async fn replay_sends_edited_request_and_records_it() {
+    let service = service(echo_app().await);
+    let buffer = service.capture.clone().unwrap();
+    let original = Exchange {
+        id: super::next_id(),
+        service: DOMAIN.into(),
+        started: std::time::SystemTime::now(),
+        duration: std::time::Duration::ZERO,
+        request: request(
+            &[
+                ("host", DOMAIN),
+                ("accept", "text/html"),
+                ("cookie", "session=1"),
+                ("content-length", "8"),
+            ],
+            "original",
+        ),
+        response: None,
+        error: Some("connection reset".into()),
+    };
+    buffer.push(original.clone());
+
+    let services = Arc::new(tokio::sync::RwLock::new(
+        [(DOMAIN.to_owned(), service)].into_iter().collect(),
+    ));
+    let (service, found) = super::find(&services, original.id).await.unwrap();
+    assert_eq!(found.id, original.id);
+    assert!(super::find(&services, u64::MAX).await.is_none());
+
+    let edit = Edit {
+        set: vec![("accept".into(), "application/json".into())],
+        remove: vec!["cookie".into()],
+        body: Some(b"replayed body".to_vec()),
+        ..Edit::default()
+    };
+    let replayed = super::replay(&service, &found, &edit).await.unwrap();
+
+    let response = replayed.response.as_ref().unwrap();
+    assert_eq!(response.status, 200);
+    let echo: Value = serde_json::from_slice(&response.body.data).unwrap();
+    assert_eq!(
+        echo,
+        json!({
+            "uri": "/echo?x=1",
+            "accept": "application/json",
+            "cookie": null,
+            // Framing of the original body is not reused
+            "length": "13",
+            "body": "replayed body",
+        })
+    );
+
+    // Replayed exchange is captured next to the original one
+    assert_ne!(replayed.id, original.id);
+    assert_eq!(buffer.list()[0].id, replayed.id);
+
+    let diff = super::diff(&original, &replayed);
+    assert!(diff.contains("-error: connection reset\n"), "{diff}");
+    assert!(diff.contains("+HTTP/1.1 200\n"), "{diff}");
+}
+
+#[tokio::test]
+async fn replay_of_truncated_body_needs_replacement() {
+    let service = service(echo_app().await);
+    let mut original = Exchange {
+        id: super::next_id(),
+        service: DOMAIN.into(),
+        started: std::time::SystemTime::now(),
+        duration: std::time::Duration::ZERO,
+        request: request(&[("host", DOMAIN)], ""),
+        response: None,
+        error: None,
+    };
+    original.request.body = Body::new(&vec![b'x'; BODY_LIMIT + 1]);
+
+    let err = super::replay(&service, &original, &Edit::default())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    let edit = Edit {
+        body: Some(b"short".to_vec()),
+        ..Edit::default()
+    };
+    let replayed = super::replay(&service, &original, &edit).await.unwrap();
+    assert_eq!(replayed.response.unwrap().status, 200);
+}
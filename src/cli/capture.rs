@@ -0,0 +1,60 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// Work with the HTTP traffic captured by the proxy
+#[derive(clap::Args, Debug)]
+pub(crate) struct Command {
+    #[command(subcommand)]
+    command: Subcommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Subcommand {
+    /// Export captured requests and responses of the service
+    Export {
+        /// Name of the service
+        #[arg(short, long)]
+        service: String,
+
+        /// Format of the exported data
+        #[arg(short, long, value_enum, default_value_t)]
+        format: crate::capture::Format,
+
+        /// Write to the file instead of standard output
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+impl Command {
+    pub(crate) fn run(self, path: &std::path::Path) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        match self.command {
+            Subcommand::Export {
+                service,
+                format,
+                output,
+            } => runtime.block_on(async {
+                let client = crate::registry::Client::open(path)?;
+                let resp = client
+                    .call(crate::registry::Command::Export {
+                        name: service.into(),
+                        format,
+                    })
+                    .await?;
+
+                if let Some(err) = resp.strip_prefix("error: ") {
+                    return Err(eyre!("{}", err.trim_end()));
+                }
+                match output {
+                    Some(output) => std::fs::write(output, resp)?,
+                    None => println!("{}", resp),
+                }
+
+                Ok(())
+            }),
+        }
+    }
+}
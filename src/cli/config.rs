@@ -0,0 +1,251 @@
+//! Configuration file of the server, `dolores.conf` in the TOML format
+//!
+//! ```toml
+//! domain = "test"
+//! listen = ["[::]:443", "http=[::]:80"]
+//!
+//! [log]
+//! filter = "info,dolores::proxy=debug"
+//!
+//! [services.api]
+//! addr = "127.0.0.1:4000"
+//! proxy = "http"
+//!
+//! [[headers]]
+//! service = "api"
+//! request.set = { "X-Environment" = "development" }
+//! response.remove = ["Server"]
+//! ```
+//!
+//! Header rules apply only to the services with `http` proxy, others pass the requests through
+//! without parsing them. Services use `terminating` proxy by default, so it has to be given as
+//! above.
+//!
+//! Options given on the command line take precedence over the file.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, Result, WrapErr};
+use http::header::{HeaderName, HeaderValue};
+use serde::{de, Deserialize, Deserializer};
+
+use super::serve::listen::Listen;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// TLD that will be used for handling the applications
+    pub domain: Option<String>,
+    /// Listeners given as in `dolores serve --listen`
+    #[serde(deserialize_with = "listeners")]
+    pub listen: Vec<Listen>,
+    /// Certificate Authority, as in `dolores serve --ca-cert` and `--ca-key`
+    pub ca_cert: Option<PathBuf>,
+    pub ca_key: Option<PathBuf>,
+    pub log: Log,
+    /// Services registered without the runner, by their names
+    pub services: BTreeMap<String, Service>,
+    /// Header rules, applied in order
+    pub headers: Vec<Headers>,
+}
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Cannot read configuration {}", path.display()))?;
+        let config: Config = toml::from_str(&text)
+            .wrap_err_with(|| format!("Invalid configuration {}", path.display()))?;
+        if config.ca_cert.is_some() != config.ca_key.is_some() {
+            bail!(
+                "Both `ca_cert` and `ca_key` need to be given in {}",
+                path.display()
+            );
+        }
+        if let Some(name) = config
+            .services
+            .keys()
+            .find(|name| !crate::service::is_valid_name(name))
+        {
+            bail!(
+                "Invalid service name {:?} in {}, only lowercase letters, digits and `-` are allowed",
+                name,
+                path.display()
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Configuration file used when none is given, if it exists
+    ///
+    /// That is `/etc/dolores.conf` for root, `$XDG_CONFIG_HOME/dolores.conf` for other users.
+    pub(crate) fn default_path() -> Option<PathBuf> {
+        let path = if nix::unistd::geteuid().is_root() {
+            PathBuf::from("/etc/dolores.conf")
+        } else {
+            let dir = std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| Path::new(&home).join(".config"))
+                })?;
+            dir.join("dolores.conf")
+        };
+
+        path.exists().then_some(path)
+    }
+
+    /// Header rules for the services under the domain
+    pub(crate) fn header_rules(&self, domain: &str) -> Vec<crate::headers::Rule> {
+        self.headers
+            .iter()
+            .map(|headers| crate::headers::Rule {
+                domain: headers
+                    .service
+                    .as_ref()
+                    .map(|name| format!("{}.{}", name, domain)),
+                request: headers.request.edit(),
+                response: headers.response.edit(),
+            })
+            .collect()
+    }
+}
+
+/// Logs of Dolores itself, as the `--log-*` options
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Log {
+    pub filter: Option<String>,
+    #[serde(deserialize_with = "value_enum")]
+    pub format: Option<super::logging::Format>,
+    pub file: Option<PathBuf>,
+    #[serde(deserialize_with = "size")]
+    pub file_size: Option<u64>,
+    pub file_keep: Option<usize>,
+}
+
+/// Service registered from the configuration, with the options of `dolores run`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Service {
+    /// Address at which the application listens
+    pub addr: SocketAddr,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub proxy: Option<crate::proxy::Type>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub downstream: Option<crate::proxy::Downstream>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub proxy_protocol: Option<crate::proxy::protocol::Version>,
+    #[serde(default)]
+    pub capture: bool,
+    #[serde(default)]
+    pub access_log: Option<PathBuf>,
+    #[serde(default)]
+    pub share: bool,
+    #[serde(default)]
+    pub plain_http: bool,
+}
+
+impl Service {
+    /// Type of the proxy, `terminating` by default as in `dolores run`
+    pub(crate) fn proxy(&self) -> crate::proxy::Type {
+        self.proxy.unwrap_or(crate::proxy::Type::Terminating)
+    }
+
+    pub(crate) fn options(&self) -> crate::proxy::Options {
+        crate::proxy::Options {
+            proxy_protocol: self.proxy_protocol,
+            downstream: self.downstream.unwrap_or_default(),
+            capture: self.capture,
+            access_log: self.access_log.clone(),
+            share: self.share,
+            plain_http: self.plain_http,
+            ..Default::default()
+        }
+    }
+}
+
+/// Headers adjusted for the service, or all of them, only those with `http` proxy are affected
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Headers {
+    /// Name of the service, as given to `dolores run --name`
+    pub service: Option<String>,
+    pub request: HeaderEdit,
+    pub response: HeaderEdit,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct HeaderEdit {
+    /// Headers to set, replacing all headers of the same name
+    #[serde(deserialize_with = "header_values")]
+    pub set: Vec<(HeaderName, HeaderValue)>,
+    /// Names of the headers to remove
+    #[serde(deserialize_with = "header_names")]
+    pub remove: Vec<HeaderName>,
+}
+
+impl HeaderEdit {
+    fn edit(&self) -> crate::headers::Edit {
+        crate::headers::Edit {
+            set: self.set.clone(),
+            remove: self.remove.clone(),
+        }
+    }
+}
+
+fn listeners<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Listen>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|listen| listen.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+/// Value of the enum, as given on the command line
+fn value_enum<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: clap::ValueEnum,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => T::from_str(&value, true)
+            .map(Some)
+            .map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(size) => super::logging::parse_size(&size)
+            .map(Some)
+            .map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn header_values<'de, D>(deserializer: D) -> Result<Vec<(HeaderName, HeaderValue)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, value)| {
+            let name = HeaderName::try_from(name).map_err(de::Error::custom)?;
+            let value = HeaderValue::try_from(value).map_err(de::Error::custom)?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+fn header_names<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<HeaderName>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|name| HeaderName::try_from(name).map_err(de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use http::header::{HeaderName, HeaderValue};
+
+use super::{Config, HeaderEdit, Headers};
+use crate::cli::serve::listen::{Listen, Role};
+
+fn parse(text: &str) -> Result<Config, toml::de::Error> {
+    toml::from_str(text)
+}
+
+/// Write the configuration to a temporary file and load it from there
+fn load(text: &str) -> color_eyre::eyre::Result<Config> {
+    let path = std::env::temp_dir().join(format!("dolores-{:x}.conf", rand::random::<u64>()));
+    std::fs::write(&path, text).unwrap();
+    let config = Config::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    config
+}
+
+#[test]
+fn empty_configuration_is_default() {
+    assert_eq!(parse("").unwrap(), Config::default());
+}
+
+#[test]
+fn listeners_are_parsed_as_on_command_line() {
+    let config = parse(r#"listen = ["127.0.0.1:8443", "http=[::1]", "lan=0.0.0.0"]"#).unwrap();
+
+    assert_eq!(
+        config.listen,
+        vec![
+            Listen {
+                role: Role::Tls,
+                addrs: vec!["127.0.0.1:8443".parse().unwrap()],
+            },
+            Listen {
+                role: Role::Http,
+                addrs: vec!["[::1]:80".parse().unwrap()],
+            },
+            Listen {
+                role: Role::Lan,
+                addrs: vec!["0.0.0.0:443".parse().unwrap()],
+            },
+        ]
+    );
+    assert!(parse(r#"listen = ["ftp=127.0.0.1"]"#).is_err());
+}
+
+#[test]
+fn enums_are_parsed_as_on_command_line() {
+    let config = parse(
+        r#"
+        [log]
+        format = "json"
+
+        [services.api]
+        addr = "127.0.0.1:4000"
+        proxy = "http"
+        downstream = "h2c"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.log.format, Some(crate::cli::logging::Format::Json));
+    let service = &config.services["api"];
+    assert_eq!(service.proxy(), crate::proxy::Type::Http);
+    assert_eq!(service.options().downstream, crate::proxy::Downstream::H2c);
+
+    // Values are case insensitive, as on the command line
+    let config = parse("[log]\nformat = \"Pretty\"").unwrap();
+    assert_eq!(config.log.format, Some(crate::cli::logging::Format::Pretty));
+    assert!(parse("[log]\nformat = \"xml\"").is_err());
+}
+
+#[test]
+fn services_use_terminating_proxy_by_default() {
+    let config = parse("[services.api]\naddr = \"127.0.0.1:4000\"").unwrap();
+
+    assert_eq!(
+        config.services["api"].proxy(),
+        crate::proxy::Type::Terminating
+    );
+    assert!(config.services["api"].options().access_log.is_none());
+}
+
+#[test]
+fn sizes_are_parsed_with_units() {
+    let size =
+        |value: &str| parse(&format!("[log]\nfile_size = {:?}", value)).map(|c| c.log.file_size);
+
+    assert_eq!(size("512").unwrap(), Some(512));
+    assert_eq!(size("10M").unwrap(), Some(10 << 20));
+    assert_eq!(size("1 GiB").unwrap(), Some(1 << 30));
+    assert!(size("10 parsecs").is_err());
+    assert!(size("").is_err());
+}
+
+#[test]
+fn header_rules_are_parsed() {
+    let config = parse(
+        r#"
+        [[headers]]
+        service = "api"
+        request.set = { "X-Environment" = "development" }
+        response.remove = ["Server"]
+
+        [[headers]]
+        response.set = { "X-Frame-Options" = "DENY" }
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.headers,
+        vec![
+            Headers {
+                service: Some("api".into()),
+                request: HeaderEdit {
+                    set: vec![(
+                        HeaderName::from_static("x-environment"),
+                        HeaderValue::from_static("development")
+                    )],
+                    remove: vec![],
+                },
+                response: HeaderEdit {
+                    set: vec![],
+                    remove: vec![HeaderName::from_static("server")],
+                },
+            },
+            Headers {
+                service: None,
+                request: HeaderEdit::default(),
+                response: HeaderEdit {
+                    set: vec![(
+                        HeaderName::from_static("x-frame-options"),
+                        HeaderValue::from_static("DENY")
+                    )],
+                    remove: vec![],
+                },
+            },
+        ]
+    );
+
+    let rules = config.header_rules("test");
+    assert_eq!(rules[0].domain.as_deref(), Some("api.test"));
+    assert_eq!(rules[1].domain, None);
+}
+
+#[test]
+fn invalid_headers_are_rejected() {
+    assert!(parse("[[headers]]\nrequest.set = { \"X Bad\" = \"value\" }").is_err());
+    assert!(parse("[[headers]]\nrequest.set = { \"X-Bad\" = \"line\\nbreak\" }").is_err());
+    assert!(parse("[[headers]]\nresponse.remove = [\"\"]").is_err());
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    assert!(parse("domian = \"test\"").is_err());
+    assert!(parse("[log]\nfilters = \"debug\"").is_err());
+    assert!(parse("[services.api]\naddr = \"127.0.0.1:4000\"\nport = 80").is_err());
+    assert!(parse("[[headers]]\nrequest.add = { \"X-A\" = \"a\" }").is_err());
+}
+
+#[test]
+fn load_requires_both_ca_files() {
+    let config = load("ca_cert = \"/ca.pem\"\nca_key = \"/ca.key\"").unwrap();
+    assert_eq!(config.ca_cert, Some(PathBuf::from("/ca.pem")));
+
+    assert!(load("ca_cert = \"/ca.pem\"").is_err());
+    assert!(load("ca_key = \"/ca.key\"").is_err());
+}
+
+#[test]
+fn load_rejects_invalid_service_names() {
+    assert!(load("[services.api-v2]\naddr = \"127.0.0.1:4000\"").is_ok());
+    assert!(load("[services.\"Api\"]\naddr = \"127.0.0.1:4000\"").is_err());
+    assert!(load("[services.\"api\\n127.0.0.1 bank\"]\naddr = \"127.0.0.1:4000\"").is_err());
+}
+
+#[test]
+fn load_reports_missing_file() {
+    let path = std::env::temp_dir().join(format!("dolores-{:x}.conf", rand::random::<u64>()));
+
+    let err = Config::load(&path).unwrap_err();
+    assert!(err.to_string().contains("Cannot read configuration"));
+}
@@ -3,6 +3,8 @@ use color_eyre::eyre::Result;
 mod cert;
 mod completion;
 mod man;
+mod resolver;
+mod systemd;
 
 /// Utilities for generating multiple files useful for working with Dolores
 #[derive(clap::Args, Debug)]
@@ -16,14 +18,18 @@ enum Generator {
     Cert(cert::Command),
     Completion(completion::Command),
     Man(man::Command),
+    Resolver(resolver::Command),
+    Systemd(systemd::Command),
 }
 
 impl Command {
-    pub(crate) fn run(self) -> Result<()> {
+    pub(crate) fn run(self, socket: &std::path::Path) -> Result<()> {
         match self.command {
             Generator::Cert(cmd) => cmd.run(),
             Generator::Completion(cmd) => cmd.run(),
             Generator::Man(cmd) => cmd.run(),
+            Generator::Resolver(cmd) => cmd.run(),
+            Generator::Systemd(cmd) => cmd.run(socket),
         }
     }
 }
@@ -0,0 +1,61 @@
+use std::net::SocketAddr;
+
+use color_eyre::eyre::Result;
+
+use indoc::printdoc;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// Drop-in for `/etc/systemd/resolved.conf.d/`
+    SystemdResolved,
+    /// Drop-in for `/etc/dnsmasq.d/`
+    Dnsmasq,
+}
+
+/// Generate resolver configuration forwarding the domain to `dolores serve --dns`
+#[derive(clap::Args, Debug)]
+pub(crate) struct Command {
+    /// Resolver for which the configuration should be generated
+    #[arg(value_enum)]
+    format: Format,
+
+    /// TLD used by the server
+    #[arg(short, long, default_value = "localhost")]
+    domain: String,
+
+    /// Address passed to `--dns` of the server
+    #[arg(long, default_value = "127.0.0.1:53")]
+    dns: SocketAddr,
+}
+
+impl Command {
+    pub(crate) fn run(self) -> Result<()> {
+        let domain = self.domain.trim_matches('.');
+        let (ip, port) = (self.dns.ip(), self.dns.port());
+
+        match self.format {
+            Format::SystemdResolved => {
+                // Port can be given only since systemd 246, so skip it when it is not needed
+                let server = match port {
+                    53 => ip.to_string(),
+                    _ => self.dns.to_string(),
+                };
+                printdoc! {"
+                    # Save as /etc/systemd/resolved.conf.d/dolores.conf
+                    # and run `systemctl restart systemd-resolved`
+                    [Resolve]
+                    DNS={server}
+                    Domains=~{domain}
+                "};
+            }
+            Format::Dnsmasq => {
+                printdoc! {"
+                    # Save as /etc/dnsmasq.d/dolores.conf and restart dnsmasq
+                    server=/{domain}/{ip}#{port}
+                "};
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Result;
+use indoc::formatdoc;
+
+use crate::cli::serve::listen::{Listen, Role};
+
+/// Generate systemd units, where systemd binds the sockets and starts the server on demand
+///
+/// `dolores.socket` holds the control socket and TLS listeners, listeners with other roles get
+/// their own `dolores-<role>.socket`, as socket names are set per unit. As the ports are bound by
+/// systemd, the server itself can run unprivileged.
+#[derive(clap::Args, Debug)]
+pub(crate) struct Command {
+    /// Directory in which the units are written, e.g. `/etc/systemd/system`
+    #[arg(long, default_value = ".")]
+    dir: PathBuf,
+
+    /// Addresses which Dolores should listen at, as in `dolores serve --listen`
+    #[arg(short, long, value_name = "[ROLE=]ADDR", default_value = "[::]:443")]
+    listen: Vec<Listen>,
+
+    /// User running the server, root by default
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Additional arguments of `dolores serve`, e.g. `-- --domain test`
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+impl Command {
+    pub(crate) fn run(self, socket: &Path) -> Result<()> {
+        let roles = [Role::Tls, Role::Http, Role::Dashboard, Role::Lan];
+        let mut units = Vec::new();
+        for role in roles {
+            let addrs: Vec<_> = self
+                .listen
+                .iter()
+                .filter(|listen| listen.role == role)
+                .flat_map(|listen| &listen.addrs)
+                .map(|addr| format!("ListenStream={}\n", addr))
+                .collect();
+
+            let unit = match role {
+                Role::Tls => formatdoc! {"
+                    [Unit]
+                    Description=Dolores control socket and TLS listeners
+
+                    [Socket]
+                    ListenDatagram={socket}
+                    SocketMode=0666
+                    {listen}BindIPv6Only=both
+
+                    [Install]
+                    WantedBy=sockets.target
+                    ",
+                    socket = socket.display(),
+                    listen = addrs.concat(),
+                },
+                _ if addrs.is_empty() => continue,
+                _ => formatdoc! {"
+                    [Unit]
+                    Description=Dolores {role} listeners
+
+                    [Socket]
+                    {listen}BindIPv6Only=both
+                    FileDescriptorName={role}
+                    Service=dolores.service
+
+                    [Install]
+                    WantedBy=sockets.target
+                    ",
+                    listen = addrs.concat(),
+                },
+            };
+            let name = match role {
+                Role::Tls => "dolores.socket".to_owned(),
+                _ => format!("dolores-{}.socket", role),
+            };
+            units.push((name, unit));
+        }
+
+        let sockets: Vec<_> = units.iter().map(|(name, _)| name.as_str()).collect();
+        let sockets = sockets.join(" ");
+        let exec = std::iter::once(std::env::current_exe()?.display().to_string())
+            .chain([
+                "--socket".into(),
+                socket.display().to_string(),
+                "serve".into(),
+            ])
+            .chain(self.args)
+            .map(|arg| quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let user = self
+            .user
+            .map(|user| format!("User={}\n", user))
+            .unwrap_or_default();
+        let service = formatdoc! {"
+            [Unit]
+            Description=Dolores development proxy
+            Requires={sockets}
+            After={sockets}
+
+            [Service]
+            ExecStart={exec}
+            {user}"
+        };
+        units.push(("dolores.service".into(), service));
+
+        for (name, unit) in units {
+            let path = self.dir.join(name);
+            fs::write(&path, unit)?;
+            println!("{}", path.display());
+        }
+        println!("Run `systemctl daemon-reload && systemctl enable --now {sockets}` to start");
+
+        Ok(())
+    }
+}
+
+/// Quote argument of `ExecStart=` when needed
+fn quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "\"'\\;$%".contains(c)) {
+        let escaped = arg
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "$$")
+            .replace('%', "%%");
+        format!("\"{}\"", escaped)
+    } else {
+        arg.into()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,28 @@
+use super::quote;
+
+#[test]
+fn plain_arguments_are_kept() {
+    assert_eq!(quote("/usr/bin/dolores"), "/usr/bin/dolores");
+    assert_eq!(quote("--listen=[::]:443"), "--listen=[::]:443");
+}
+
+#[test]
+fn spaces_and_empty_arguments_are_quoted() {
+    assert_eq!(quote("/opt/my apps/dolores"), "\"/opt/my apps/dolores\"");
+    assert_eq!(quote("a\tb"), "\"a\tb\"");
+    assert_eq!(quote(""), "\"\"");
+}
+
+#[test]
+fn specifiers_and_variables_are_escaped() {
+    assert_eq!(quote("100%"), "\"100%%\"");
+    assert_eq!(quote("$HOME"), "\"$$HOME\"");
+    assert_eq!(quote("a;b"), "\"a;b\"");
+}
+
+#[test]
+fn quotes_and_backslashes_are_escaped() {
+    assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+    assert_eq!(quote("it's"), "\"it's\"");
+    assert_eq!(quote("C:\\dir"), "\"C:\\\\dir\"");
+}
@@ -0,0 +1,229 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use color_eyre::eyre::Result;
+use once_cell::sync::OnceCell;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, Registry};
+
+/// Filter that can be replaced with the configured one, with its default level
+static FILTER: OnceCell<(reload::Handle<EnvFilter, Registry>, LevelFilter)> = OnceCell::new();
+
+/// Format of the logs of Dolores itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Format {
+    /// Human readable, single line per event
+    #[default]
+    Full,
+    /// Human readable, spread over multiple lines
+    Pretty,
+    /// Human readable, shortened
+    Compact,
+    /// JSON object per line
+    Json,
+}
+
+/// Options of the logs, ones not given fall back to the `[log]` section of the configuration
+#[derive(clap::Args, Debug)]
+pub(crate) struct Options {
+    /// Format of the logs, `full` by default
+    #[arg(long, value_enum)]
+    log_format: Option<Format>,
+
+    /// Filter of the logs in the `RUST_LOG` syntax, for example `info,dolores::proxy=trace`
+    #[arg(long, env = "RUST_LOG", value_name = "FILTER")]
+    log_filter: Option<String>,
+
+    /// Write logs to the file instead of standard output
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Size after which the log file is rotated, accepts `K`, `M` and `G` suffixes, `10M` by
+    /// default
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    log_file_size: Option<u64>,
+
+    /// Amount of rotated log files to keep, as `<PATH>.1` up to `<PATH>.<N>`, 5 by default
+    #[arg(long, value_name = "N")]
+    log_file_keep: Option<usize>,
+}
+
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => value.split_at(index),
+        None => (value, ""),
+    };
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        unit => return Err(format!("unknown unit {:?}", unit)),
+    };
+
+    number
+        .parse::<u64>()
+        .map_err(|err| err.to_string())?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size {:?} is too large", value))
+}
+
+fn env_filter(filter: Option<&str>, level: LevelFilter) -> Result<EnvFilter> {
+    Ok(EnvFilter::builder()
+        .with_default_directive(level.into())
+        .parse(filter.unwrap_or_default())?)
+}
+
+impl Options {
+    /// Install global subscriber, `debug` lowers the default level to debug
+    pub(crate) fn init(&self, debug: bool, config: &super::config::Log) -> Result<()> {
+        let level = if debug {
+            LevelFilter::DEBUG
+        } else {
+            LevelFilter::INFO
+        };
+        let filter = match self.log_filter {
+            Some(ref filter) => env_filter(Some(filter), level)?,
+            None => env_filter(config.filter.as_deref(), level)?,
+        };
+        let (filter, handle) = reload::Layer::new(filter);
+        // Filter given on the command line or in the environment wins over the configured one
+        if self.log_filter.is_none() {
+            let _ = FILTER.set((handle, level));
+        }
+
+        let (writer, ansi) = match self.log_file.as_ref().or(config.file.as_ref()) {
+            Some(path) => {
+                let size = self.log_file_size.or(config.file_size).unwrap_or(10 << 20);
+                let file = Rotating::open(path, size, self.keep(config))?;
+                (BoxMakeWriter::new(Mutex::new(file)), false)
+            }
+            None => (BoxMakeWriter::new(io::stdout), true),
+        };
+
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi);
+        let layer = match self.log_format.or(config.format).unwrap_or_default() {
+            Format::Full => layer.boxed(),
+            Format::Pretty => layer.pretty().boxed(),
+            Format::Compact => layer.compact().boxed(),
+            Format::Json => layer.json().boxed(),
+        };
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(layer)
+            .init();
+
+        Ok(())
+    }
+
+    fn keep(&self, config: &super::config::Log) -> usize {
+        self.log_file_keep.or(config.file_keep).unwrap_or(5)
+    }
+
+    /// Log file with its rotated copies, if logs are written to the file
+    pub(crate) fn files(&self, config: &super::config::Log) -> Vec<PathBuf> {
+        let path = match self.log_file.as_ref().or(config.file.as_ref()) {
+            Some(path) => path,
+            None => return Vec::new(),
+        };
+        let rotated = (1..=self.keep(config)).map(|index| rotated(path, index));
+
+        std::iter::once(path.clone()).chain(rotated).collect()
+    }
+}
+
+/// Replace the filter of the logs with the configured one
+///
+/// Filter given on the command line is kept, the configured one is only reported as ignored.
+pub(crate) fn reload_filter(filter: Option<&str>) -> Result<()> {
+    match FILTER.get() {
+        Some((handle, level)) => handle.reload(env_filter(filter, *level)?)?,
+        None => {
+            tracing::warn!("Log filter is given on the command line, configured one is ignored")
+        }
+    }
+
+    Ok(())
+}
+
+/// Log file that is moved aside once it grows over the limit
+#[derive(Debug)]
+struct Rotating {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl Rotating {
+    fn open(path: &Path, max_size: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Rotating {
+            path: path.into(),
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        rotated(&self.path, index)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let ignore_missing = |result: io::Result<()>| match result {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+
+        if self.keep == 0 {
+            ignore_missing(fs::remove_file(&self.path))?;
+        } else {
+            for index in (1..self.keep).rev() {
+                ignore_missing(fs::rename(self.rotated(index), self.rotated(index + 1)))?;
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        *self = Rotating::open(&self.path, self.max_size, self.keep)?;
+        Ok(())
+    }
+}
+
+impl Write for Rotating {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Single event larger than the limit still goes to the file, just alone
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Path of the log file moved aside, `1` is the most recent one
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut path = path.to_owned().into_os_string();
+    path.push(format!(".{}", index));
+    path.into()
+}
+
+#[cfg(test)]
+mod tests;
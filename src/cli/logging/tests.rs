@@ -0,0 +1,119 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::{parse_size, rotated, Rotating};
+
+#[test]
+fn parse_size_units() {
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("512B"), Ok(512));
+    assert_eq!(parse_size("4K"), Ok(4 << 10));
+    assert_eq!(parse_size("4kb"), Ok(4 << 10));
+    assert_eq!(parse_size("10M"), Ok(10 << 20));
+    assert_eq!(parse_size(" 10 MiB "), Ok(10 << 20));
+    assert_eq!(parse_size("2G"), Ok(2 << 30));
+}
+
+#[test]
+fn parse_size_rejects_invalid() {
+    assert!(parse_size("").is_err());
+    assert!(parse_size("M").is_err());
+    assert!(parse_size("-1K").is_err());
+    assert!(parse_size("1.5M").is_err());
+    assert_eq!(parse_size("10T"), Err("unknown unit \"T\"".to_owned()));
+}
+
+#[test]
+fn parse_size_rejects_overflow() {
+    assert_eq!(
+        parse_size("99999999999G"),
+        Err("size \"99999999999G\" is too large".to_owned())
+    );
+    assert!(parse_size("99999999999999999999").is_err());
+    assert_eq!(parse_size("17179869183G"), Ok(17179869183 << 30));
+}
+
+/// Directory with the log file, removed when dropped
+struct LogDir(PathBuf);
+
+impl LogDir {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("dolores-logs-{:x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        LogDir(dir)
+    }
+
+    fn log(&self) -> PathBuf {
+        self.0.join("dolores.log")
+    }
+}
+
+impl Drop for LogDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+#[test]
+fn rotates_when_size_is_exceeded() {
+    let dir = LogDir::new();
+    let path = dir.log();
+    let mut file = Rotating::open(&path, 10, 2).unwrap();
+
+    for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+
+    assert_eq!(read(&path).as_deref(), Some("fourth\n"));
+    assert_eq!(read(&rotated(&path, 1)).as_deref(), Some("third\n"));
+    assert_eq!(read(&rotated(&path, 2)).as_deref(), Some("second\n"));
+    // Only `keep` rotated files are kept
+    assert_eq!(read(&rotated(&path, 3)), None);
+}
+
+#[test]
+fn appends_to_existing_file_and_counts_its_size() {
+    let dir = LogDir::new();
+    let path = dir.log();
+    fs::write(&path, "old\n").unwrap();
+
+    let mut file = Rotating::open(&path, 10, 1).unwrap();
+    file.write_all(b"new\n").unwrap();
+    assert_eq!(read(&path).as_deref(), Some("old\nnew\n"));
+
+    file.write_all(b"newer\n").unwrap();
+    assert_eq!(read(&path).as_deref(), Some("newer\n"));
+    assert_eq!(read(&rotated(&path, 1)).as_deref(), Some("old\nnew\n"));
+}
+
+#[test]
+fn oversized_event_is_written_alone() {
+    let dir = LogDir::new();
+    let path = dir.log();
+    let mut file = Rotating::open(&path, 4, 1).unwrap();
+
+    file.write_all(b"very long line\n").unwrap();
+    assert_eq!(read(&path).as_deref(), Some("very long line\n"));
+
+    file.write_all(b"next\n").unwrap();
+    assert_eq!(read(&path).as_deref(), Some("next\n"));
+    assert_eq!(read(&rotated(&path, 1)).as_deref(), Some("very long line\n"));
+}
+
+#[test]
+fn without_keep_rotated_file_is_removed() {
+    let dir = LogDir::new();
+    let path = dir.log();
+    let mut file = Rotating::open(&path, 4, 0).unwrap();
+
+    file.write_all(b"first\n").unwrap();
+    file.write_all(b"second\n").unwrap();
+
+    assert_eq!(read(&path).as_deref(), Some("second\n"));
+    assert_eq!(read(&rotated(&path, 1)), None);
+}
@@ -0,0 +1,66 @@
+use std::io::Write;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+
+use crate::logs::{Line, Stream};
+
+/// Interval in which new lines are polled when following the output
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Show output of the application run by `dolores run`
+#[derive(clap::Args, Debug)]
+pub(crate) struct Command {
+    /// Name of the service
+    name: String,
+
+    /// Keep printing new lines as they appear
+    #[arg(short, long)]
+    follow: bool,
+
+    /// Amount of recent lines to show
+    #[arg(short = 'n', long, default_value_t = 100)]
+    lines: usize,
+}
+
+impl Command {
+    pub(crate) fn run(self, path: &std::path::Path) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        runtime.block_on(async {
+            let client = crate::registry::Client::open(path)?;
+            let mut since = None;
+            let mut limit = self.lines;
+
+            loop {
+                let resp = client
+                    .call(crate::registry::Command::Logs {
+                        name: self.name.as_str().into(),
+                        since,
+                        limit,
+                    })
+                    .await?;
+                if let Some(err) = resp.strip_prefix("error: ") {
+                    return Err(eyre!("{}", err.trim_end()));
+                }
+
+                let lines: Vec<Line> = serde_json::from_str(&resp)?;
+                for line in &lines {
+                    let _ = match line.stream {
+                        Stream::Stdout => writeln!(std::io::stdout(), "{}", line.text),
+                        Stream::Stderr => writeln!(std::io::stderr(), "{}", line.text),
+                    };
+                }
+
+                if !self.follow {
+                    return Ok(());
+                }
+                since = lines.last().map(|line| line.seq).or(since).or(Some(0));
+                limit = crate::logs::CAPACITY;
+                tokio::time::sleep(FOLLOW_INTERVAL).await;
+            }
+        })
+    }
+}
@@ -1,5 +1,11 @@
 use color_eyre::eyre::Result;
 
+mod replay;
+mod capture;
+mod config;
+mod logging;
+mod logs;
+mod reload;
 mod run;
 mod serve;
 mod status;
@@ -12,43 +18,106 @@ pub struct App {
     #[arg(short, long)]
     pub debug: bool,
 
+    #[command(flatten)]
+    logging: logging::Options,
+
     #[command(subcommand)]
     command: Command,
 
-    /// Path for UNIX socket used for communicating with Dolores server
-    #[arg(
-        long = "socket",
-        env = "DOLORES_SOCKET",
-        default_value = "/var/run/dolores.sock"
-    )]
-    socket_path: std::path::PathBuf,
+    /// Path for UNIX socket used for communicating with Dolores server. By default root server
+    /// uses `/var/run/dolores.sock` and others `$XDG_RUNTIME_DIR/dolores.sock`, clients use the
+    /// latter when such server is running. Generated systemd units use the former
+    #[arg(long = "socket", env = "DOLORES_SOCKET")]
+    socket_path: Option<std::path::PathBuf>,
+}
+
+/// Control socket of the server running as root
+const SYSTEM_SOCKET: &str = "/var/run/dolores.sock";
+
+impl Default for App {
+    fn default() -> Self { Self::new() }
 }
 
 impl App {
     pub fn new() -> Self { clap::Parser::parse() }
 
+    /// Set up logging of Dolores itself according to the options
+    pub fn init_logging(&self) -> Result<()> {
+        let config = match self.command {
+            Command::Serve(ref cmd) => cmd.config()?.1.log.clone(),
+            _ => Default::default(),
+        };
+
+        self.logging.init(self.debug, &config)
+    }
+
     pub fn run(self) -> Result<()> {
         tracing::debug!(?self);
 
-        self.command.run(&self.socket_path)
+        let path = self.socket_path();
+        tracing::debug!(?path, "Control socket");
+
+        self.command.run(&path, &self.logging)
+    }
+
+    /// Control socket given explicitly, or the one of the server run by this user
+    ///
+    /// Server started by other users than root cannot create the system wide socket, so it uses
+    /// its runtime directory. Clients look for such server first and fall back to the system one.
+    /// Generated units are run by the system service manager, so they use the system socket.
+    fn socket_path(&self) -> std::path::PathBuf {
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .filter(|_| !nix::unistd::geteuid().is_root())
+            .filter(|_| !matches!(self.command, Command::Gen(_)))
+            .map(std::path::PathBuf::from);
+        let server = matches!(self.command, Command::Serve(_));
+
+        socket_path(self.socket_path.as_deref(), runtime_dir.as_deref(), server)
+    }
+}
+
+/// Control socket given explicitly, in the runtime directory of the user or the system one
+fn socket_path(
+    explicit: Option<&std::path::Path>,
+    runtime_dir: Option<&std::path::Path>,
+    server: bool,
+) -> std::path::PathBuf {
+    if let Some(path) = explicit {
+        return path.into();
+    }
+
+    match runtime_dir.map(|dir| dir.join("dolores.sock")) {
+        Some(path) if server || path.exists() => path,
+        _ => SYSTEM_SOCKET.into(),
     }
 }
 
 #[derive(clap::Subcommand, Debug)]
 enum Command {
     Run(run::Command),
-    Serve(serve::Command),
+    Serve(Box<serve::Command>),
     Status(status::Command),
+    Replay(replay::Command),
+    Capture(capture::Command),
+    Logs(logs::Command),
+    Reload(reload::Command),
     Gen(gen::Command),
 }
 
 impl Command {
-    fn run(self, path: &std::path::Path) -> Result<()> {
+    fn run(self, path: &std::path::Path, logging: &logging::Options) -> Result<()> {
         match self {
             Command::Run(cmd) => cmd.run(path),
-            Command::Serve(cmd) => cmd.run(path),
+            Command::Serve(cmd) => cmd.run(path, logging),
             Command::Status(cmd) => cmd.run(path),
-            Command::Gen(cmd) => cmd.run(),
+            Command::Replay(cmd) => cmd.run(path),
+            Command::Capture(cmd) => cmd.run(path),
+            Command::Logs(cmd) => cmd.run(path),
+            Command::Reload(cmd) => cmd.run(path),
+            Command::Gen(cmd) => cmd.run(path),
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
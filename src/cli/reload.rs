@@ -0,0 +1,24 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// Make the server read its configuration file again
+#[derive(clap::Args, Debug)]
+pub(crate) struct Command {}
+
+impl Command {
+    pub(crate) fn run(self, path: &std::path::Path) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        runtime.block_on(async {
+            let client = crate::registry::Client::open(path)?;
+            let resp = client.call(crate::registry::Command::Reload).await?;
+            if let Some(err) = resp.strip_prefix("error: ") {
+                return Err(eyre!("{}", err.trim_end()));
+            }
+            println!("Configuration reloaded");
+
+            Ok(())
+        })
+    }
+}
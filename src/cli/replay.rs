@@ -0,0 +1,67 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// Send captured request to the service again and show how the response changed
+#[derive(clap::Args, Debug)]
+pub(crate) struct Command {
+    /// ID of the captured request, as shown in the dashboard
+    id: u64,
+
+    /// Set header, replacing all headers of the same name
+    #[arg(short = 'H', long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Remove all headers with given name
+    #[arg(long = "remove-header", value_name = "NAME")]
+    remove: Vec<String>,
+
+    /// Replace body of the request
+    #[arg(short, long, conflicts_with = "data_file")]
+    data: Option<String>,
+
+    /// Replace body of the request with content of the file
+    #[arg(long, value_name = "PATH")]
+    data_file: Option<std::path::PathBuf>,
+}
+
+fn parse_header(header: &str) -> Result<(String, String)> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| eyre!("header needs to be in `Name: value` format"))?;
+
+    Ok((name.trim().into(), value.trim().into()))
+}
+
+impl Command {
+    pub(crate) fn run(self, path: &std::path::Path) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let body = match (self.data, self.data_file) {
+            (Some(data), _) => Some(data.into_bytes()),
+            (None, Some(path)) => Some(std::fs::read(path)?),
+            (None, None) => None,
+        };
+        let edit = crate::capture::Edit {
+            headers: None,
+            set: self.headers,
+            remove: self.remove,
+            body,
+        };
+
+        runtime.block_on(async {
+            let client = crate::registry::Client::open(path)?;
+            let resp = client
+                .call(crate::registry::Command::Replay { id: self.id, edit })
+                .await?;
+
+            match resp.strip_prefix("error: ") {
+                Some(err) => Err(eyre!("{}", err.trim_end())),
+                None => {
+                    print!("{}", resp);
+                    Ok(())
+                }
+            }
+        })
+    }
+}
@@ -1,11 +1,24 @@
-use std::io;
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::net;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::process::CommandExt;
+use std::pin::Pin;
 use std::process;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
+use color_eyre::eyre::{eyre, Result};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use nix::sys::socket::{self, socket};
-use nix::unistd::{dup2, fork, ForkResult, Pid};
-use color_eyre::eyre::Result;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{close, dup2, fork, pipe2, ForkResult, Pid};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncBufReadExt, AsyncRead, ReadBuf};
+
+use crate::logs::Stream;
+use crate::registry::Client;
+use crate::service::State;
 
 /// Run given command and pass sockets to listen on incoming connections
 #[derive(clap::Args, Debug)]
@@ -18,6 +31,19 @@ pub(crate) struct Command {
     #[arg(long, default_value = "terminating")]
     proxy: crate::proxy::Type,
 
+    #[command(flatten)]
+    options: crate::proxy::Options,
+
+    /// Restart the program whenever it exits, connections are held until it is back
+    #[arg(long)]
+    restart: bool,
+
+    /// Forward output of the program to the server, to be viewed with `dolores logs` or on the
+    /// dashboard. Output is still printed here, but the program writes it to pipes instead of the
+    /// terminal, so it may e.g. disable colors
+    #[arg(long)]
+    forward_output: bool,
+
     #[arg(name = "PROG")]
     prog_name: String,
 
@@ -27,9 +53,20 @@ pub(crate) struct Command {
 
 const FD_START: i32 = 3;
 
+/// Descriptor of the socket for the metrics, passed after the main one
+const FD_METRICS: i32 = FD_START + 1;
+
+/// Delay between exit of the program and its restart, to avoid busy loop on crashes
+const RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Time for which the output is still forwarded after the program exits, as its children may
+/// keep the pipes open
+const OUTPUT_DRAIN: std::time::Duration = std::time::Duration::from_secs(1);
+
 // TODO: Support more socket types and allow using other socket types, not only TCP
-fn open_socket() -> io::Result<net::SocketAddr> {
-    let addr: socket::SockaddrIn6 = net::SocketAddrV6::new(net::Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 0, 0, 0).into();
+fn open_socket(target: i32) -> io::Result<net::SocketAddr> {
+    let addr: socket::SockaddrIn6 =
+        net::SocketAddrV6::new(net::Ipv6Addr::LOCALHOST, 0, 0, 0).into();
 
     let fd = socket(
         socket::AddressFamily::Inet6,
@@ -41,79 +78,372 @@ fn open_socket() -> io::Result<net::SocketAddr> {
     socket::bind(fd, &addr)?;
     socket::listen(fd, 10)?;
 
-    dup2(fd, FD_START as i32)?;
-
-    match socket::getsockname(fd)? {
-        socket::SockAddr::Inet(addr) => Ok(addr.to_std()),
-        _ => unreachable!(),
+    if fd != target {
+        dup2(fd, target)?;
+        close(fd)?;
     }
+
+    let addr: socket::SockaddrIn6 = socket::getsockname(target)?;
+
+    Ok(net::SocketAddrV6::from(addr).into())
 }
 
 impl Command {
-    pub(crate) fn run(self, path: &std::path::Path) -> Result<()> {
+    pub(crate) fn run(mut self, path: &std::path::Path) -> Result<()> {
         let name = self.name.as_ref().unwrap_or(&self.prog_name);
+        if !crate::service::is_valid_name(name) {
+            return Err(eyre!(
+                "Invalid service name {:?}, only lowercase letters, digits and `-` are allowed, \
+                choose another one with --name",
+                name
+            ));
+        }
         let span = tracing::span!(tracing::Level::DEBUG, "run");
         let _guard = span.enter();
 
         tracing::debug!("Starting");
 
-        let addr = open_socket()?;
+        let addr = open_socket(FD_START)?;
+        if self.options.metrics_socket {
+            self.options.metrics_addr = Some(open_socket(FD_METRICS)?);
+        }
+
+        // Server does not open paths of its clients, so it gets the opened file instead. Path is
+        // only shown by the server, which runs in another working directory. Opened after the
+        // sockets, so its descriptor is not replaced by them.
+        let mut access_log = None;
+        if let Some(ref mut path) = self.options.access_log {
+            if path.as_path() != std::path::Path::new("-") {
+                *path = std::path::absolute(&path)?;
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|err| eyre!("Cannot open access log {}: {}", path.display(), err))?;
+                access_log = Some(file);
+            }
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        runtime.block_on(async {
+            use crate::registry;
+            let client = Arc::new(Client::open(path)?);
+
+            // Server marks the service as starting until it is told that the program runs
+            let register = registry::Command::Register {
+                name: name.into(),
+                addr,
+                proxy: self.proxy,
+                options: self.options.clone(),
+            };
+            match access_log {
+                Some(ref file) => client.send_with_file(register, file).await?,
+                None => client.send(register).await?,
+            }
+            tracing::debug!(?addr, "Registered");
+
+            let (mut child, output) = match self.spawn() {
+                Ok(spawned) => spawned,
+                Err(err) => {
+                    deregister(&client, name).await;
+                    return Err(err);
+                }
+            };
+            tracing::debug!(child = ?child.as_raw(), "Started");
+
+            let mut forwarders = Vec::new();
+            // Cleared once the server is gone, there is no one to tell about the program then
+            let mut connected = true;
+            // Set while the exited program is not restarted yet, so its PID is not reused
+            let mut exited = false;
+
+            let result: Result<()> = async {
+                if let Some(output) = output {
+                    forwarders.extend(output.forward(&client, name)?);
+                }
+                set_state(&client, name, State::Running).await;
+
+                let mut watcher =
+                    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::child())?;
+                let mut stopping = false;
+                loop {
+                    tokio::select! {
+                        _ = tokio::signal::ctrl_c() => {
+                            stopping = true;
+                            nix::sys::signal::kill(child, nix::sys::signal::SIGINT)?
+                        }
+                        notification = client.notification(), if connected => {
+                            match notification {
+                                Ok(registry::Notification::Shutdown) => tracing::warn!(
+                                    "Server is shutting down, the program is no longer reachable"
+                                ),
+                                Err(err) => tracing::debug!(%err, "Server is unreachable"),
+                            }
+                            connected = false;
+                        }
+                        _ = watcher.recv() => {
+                            let status = waitpid(child, Some(WaitPidFlag::WNOHANG))?;
+                            if status == WaitStatus::StillAlive {
+                                continue;
+                            }
+                            tracing::debug!(?status, "Exited");
+                            exited = true;
+
+                            if stopping || !self.restart {
+                                return Ok(());
+                            }
+
+                            if connected {
+                                set_state(&client, name, State::Restarting).await;
+                            }
+                            // Socket stays open, so new connections wait in its backlog until
+                            // the program is back
+                            tokio::time::sleep(RESTART_DELAY).await;
+                            let (restarted, output) = self.spawn()?;
+                            child = restarted;
+                            exited = false;
+                            if let Some(output) = output {
+                                forwarders.extend(output.forward(&client, name)?);
+                            }
+                            tracing::info!(child = ?child.as_raw(), "Restarted");
+                            if connected {
+                                set_state(&client, name, State::Running).await;
+                            }
+                        }
+                    }
+                }
+            }
+            .await;
+            tracing::debug!("Shutting down");
+
+            if result.is_err() && !exited {
+                let _ = nix::sys::signal::kill(child, nix::sys::signal::SIGTERM);
+            }
+
+            let drained = tokio::time::timeout(OUTPUT_DRAIN, async {
+                for forwarder in forwarders {
+                    let _ = forwarder.await;
+                }
+            });
+            if drained.await.is_err() {
+                tracing::debug!("Output still open, stop forwarding it");
+            }
+
+            if connected {
+                deregister(&client, name).await;
+            }
+
+            result
+        })
+    }
+
+    /// Start the program with socket passed to it, returns PID of the new process and its output
+    /// when it is forwarded
+    fn spawn(&self) -> Result<(Pid, Option<Output>)> {
+        let names = if self.options.metrics_socket {
+            "http:metrics"
+        } else {
+            "http"
+        };
+
+        // Otherwise the program inherits our output, keeping the terminal
+        let (output, output_write) = if self.forward_output {
+            let (output, write) = Output::pipes()?;
+            (Some(output), Some(write))
+        } else {
+            (None, None)
+        };
+
+        // Closed by successful `exec`, otherwise the child writes the error number to it
+        let (status, status_write) = pipe2(OFlag::O_CLOEXEC)?;
+        let (mut status, mut status_write) =
+            unsafe { (File::from_raw_fd(status), File::from_raw_fd(status_write)) };
 
         match unsafe { fork() }? {
             ForkResult::Child => {
-                let error = process::Command::new(&self.prog_name)
-                    .args(&self.prog_args)
-                    // Use systemd-like interface to pass the sockets to the new process
-                    .env("LISTEN_FDS", "1")
-                    .env("LISTEN_PID", Pid::this().to_string())
-                    .env("LISTEN_FDNAMES", "http")
-                    .exec();
-
-                // If we reach that, then `exec` above failed, so we just return error directly
-                Err(error.into())
+                let redirected = match output_write {
+                    Some((ref stdout, ref stderr)) => dup2(stdout.as_raw_fd(), 1)
+                        .and_then(|_| dup2(stderr.as_raw_fd(), 2))
+                        .map(drop),
+                    None => Ok(()),
+                };
+                let error = match redirected {
+                    Ok(_) => process::Command::new(&self.prog_name)
+                        .args(&self.prog_args)
+                        // Use systemd-like interface to pass the sockets to the new process
+                        .env("LISTEN_FDS", names.split(':').count().to_string())
+                        .env("LISTEN_PID", Pid::this().to_string())
+                        .env("LISTEN_FDNAMES", names)
+                        .exec(),
+                    Err(err) => err.into(),
+                };
+
+                // If we reach that, then `exec` above failed and there is nothing more to do.
+                // Exit immediately, without running any cleanup of the parent in the copy of it.
+                let errno = error.raw_os_error().unwrap_or(0);
+                let _ = status_write.write_all(&errno.to_ne_bytes());
+                unsafe { nix::libc::_exit(127) }
             }
+            // Write ends are closed here, so reading ends once the program exits
             ForkResult::Parent { child, .. } => {
-                let runtime = tokio::runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()?;
-                let span = tracing::span!(tracing::Level::DEBUG, "run", child = ?child.as_raw());
-                let _guard = span.enter();
-
-                runtime
-                    .block_on(async {
-                        use crate::registry;
-                        let client = registry::Client::open(path)?;
-                        let mut watcher =
-                            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::child())?;
-
-                        client
-                            .send(registry::Command::Register {
-                                name: name.into(),
-                                addr,
-                                proxy: self.proxy,
-                            })
-                            .await?;
-
-                        tracing::debug!(?addr, "Registered");
-                        loop {
-                            tokio::select! {
-                                _ = tokio::signal::ctrl_c() =>
-                                    nix::sys::signal::kill(child, nix::sys::signal::SIGINT)?,
-                                _ = watcher.recv() => break,
-                            }
-                        }
-                        tracing::debug!("Shutting down");
-
-                        client
-                            .send(registry::Command::Deregister { name: name.into() })
-                            .await?;
-
-                        Ok(())
-                    })
-                    .or_else(|err| {
-                        nix::sys::signal::kill(child, nix::sys::signal::SIGTERM)?;
-                        Err(err)
-                    })
+                drop(status_write);
+                drop(output_write);
+                let mut errno = Vec::new();
+                status.read_to_end(&mut errno)?;
+                if errno.is_empty() {
+                    return Ok((child, output));
+                }
+
+                waitpid(child, None)?;
+                let errno = errno.try_into().map_or(0, i32::from_ne_bytes);
+                let error = io::Error::from_raw_os_error(errno);
+                Err(eyre!("Cannot execute {}: {}", self.prog_name, error))
+            }
+        }
+    }
+}
+
+/// Inform the server about the state of the program, failure is not fatal for the program
+async fn set_state(client: &Client, name: &str, state: State) {
+    let command = crate::registry::Command::SetState {
+        name: name.into(),
+        state,
+    };
+    if let Err(err) = client.send(command).await {
+        tracing::warn!(%err, %state, "Cannot update state of the service");
+    }
+}
+
+async fn deregister(client: &Client, name: &str) {
+    let command = crate::registry::Command::Deregister { name: name.into() };
+    if let Err(err) = client.send(command).await {
+        tracing::warn!(%err, "Cannot deregister the service");
+    }
+}
+
+/// Read ends of the pipes connected to the output of the program
+struct Output {
+    stdout: File,
+    stderr: File,
+}
+
+impl Output {
+    /// Pipes for the output, returns their read ends and the write ends for the program
+    fn pipes() -> io::Result<(Self, (File, File))> {
+        let (stdout, stdout_write) = pipe2(OFlag::O_CLOEXEC)?;
+        let (stderr, stderr_write) = pipe2(OFlag::O_CLOEXEC)?;
+        // Both ends are owned from now on, so they are closed on early return
+        unsafe {
+            Ok((
+                Output {
+                    stdout: File::from_raw_fd(stdout),
+                    stderr: File::from_raw_fd(stderr),
+                },
+                (
+                    File::from_raw_fd(stdout_write),
+                    File::from_raw_fd(stderr_write),
+                ),
+            ))
+        }
+    }
+
+    /// Start forwarding both streams, returns handles of the forwarding tasks
+    fn forward(
+        self,
+        client: &Arc<Client>,
+        name: &str,
+    ) -> io::Result<Vec<tokio::task::JoinHandle<()>>> {
+        let mut tasks = Vec::new();
+        for (stream, file) in [(Stream::Stdout, self.stdout), (Stream::Stderr, self.stderr)] {
+            let pipe = Pipe::new(file)?;
+            tasks.push(tokio::spawn(forward(
+                pipe,
+                stream,
+                client.clone(),
+                name.to_owned(),
+            )));
+        }
+
+        Ok(tasks)
+    }
+}
+
+/// Copy lines of the output to our own one and send them to the daemon
+async fn forward(pipe: Pipe, stream: Stream, client: Arc<Client>, name: String) {
+    let mut reader = tokio::io::BufReader::new(pipe);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(err) => {
+                tracing::warn!(%err, ?stream, "Cannot read output");
+                break;
+            }
+        }
+
+        let _ = match stream {
+            Stream::Stdout => io::stdout().write_all(&line),
+            Stream::Stderr => io::stderr().write_all(&line),
+        };
+
+        let text = String::from_utf8_lossy(&line);
+        for part in crate::logs::split(text.trim_end_matches(['\n', '\r'])) {
+            // Output is still shown locally, so losing it in the daemon is not fatal
+            let result = client
+                .send(crate::registry::Command::Log {
+                    name: name.as_str().into(),
+                    stream,
+                    text: part.into(),
+                })
+                .await;
+            if let Err(err) = result {
+                tracing::debug!(%err, "Cannot forward output");
+            }
+        }
+    }
+}
+
+/// Non-blocking read end of the pipe
+struct Pipe(AsyncFd<File>);
+
+impl Pipe {
+    fn new(file: File) -> io::Result<Self> {
+        let flags = OFlag::from_bits_truncate(fcntl(file.as_raw_fd(), FcntlArg::F_GETFL)?);
+        fcntl(
+            file.as_raw_fd(),
+            FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK),
+        )?;
+
+        Ok(Pipe(AsyncFd::new(file)?))
+    }
+}
+
+impl AsyncRead for Pipe {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = match self.0.poll_read_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|file| file.get_ref().read(unfilled)) {
+                Ok(Ok(len)) => {
+                    buf.advance(len);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => continue,
             }
         }
     }
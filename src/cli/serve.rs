@@ -1,45 +1,345 @@
+use std::os::unix::io::AsRawFd;
 use std::sync::Arc;
+use std::time::Duration;
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, Result};
+use nix::sys::socket::{getsockopt, sockopt, SockType};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::activation::Socket;
+use crate::cli::config::Config;
+use crate::privileges::Credentials;
+use listen::{Listen, Role};
+
+pub(crate) mod listen;
+mod reload;
 
 /// Start master process listening for connections
 #[derive(clap::Args, Debug)]
 pub(crate) struct Command {
-    /// TLD that will be used for handling the applications
-    #[arg(short, long, default_value = "localhost")]
-    domain: String,
+    /// Configuration file, `/etc/dolores.conf` (or `~/.config/dolores.conf` when not run as root)
+    /// is used by default if it exists. Options given on the command line take precedence, the
+    /// file is read again on `SIGHUP` or `dolores reload`
+    #[arg(short, long, env = "DOLORES_CONFIG", value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+
+    /// TLD that will be used for handling the applications, `localhost` by default
+    #[arg(short, long)]
+    domain: Option<String>,
+
+    /// Address which Dolores should listen at, can be repeated, `[::]:443` when none is given.
+    /// Prefix it with `http=` for plain HTTP, `dashboard=` to serve only the dashboard or `lan=` to
+    /// serve only the shared services. Port is 443 by default (80 for plain HTTP), any IPv6
+    /// address, `[::]`, accepts IPv4 clients too.
+    ///
+    /// Sockets can be also passed by the service manager, e.g. systemd, with `LISTEN_FDS`. The
+    /// UNIX datagram socket is used as the control socket, TCP ones are named by their role.
+    #[arg(short, long, value_name = "[ROLE=]ADDR")]
+    listen: Vec<listen::Listen>,
 
-    /// Address which Dolores should listen at
-    #[arg(short, long, default_value = "0.0.0.0:443")]
-    listen: std::net::SocketAddr,
+    /// Listen for plain HTTP at given address too, `[::]:80` when no address is given, same as
+    /// `--listen http=ADDR` next to the other listeners. Requests are redirected to HTTPS, unless
+    /// the service is run with `--plain-http`
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        default_missing_value = "[::]:80",
+        value_parser = listen::http
+    )]
+    http: Option<listen::Listen>,
 
-    /// Path to the PEM encoded Certificate Authority key
+    /// Answer ACME HTTP-01 challenges on the plain HTTP listeners with files stored by the ACME
+    /// client in `<DIR>/.well-known/acme-challenge`
+    #[arg(long, value_name = "DIR")]
+    acme_webroot: Option<std::path::PathBuf>,
+
+    /// Path to the PEM encoded Certificate Authority certificate, which signs the certificates
+    /// generated for the services instead of self-signing them
     #[arg(long, requires("ca_key"))]
     ca_cert: Option<std::path::PathBuf>,
 
-    /// Path to the PEM encoded Certificate Authority private certificate
+    /// Path to the PEM encoded private key of the Certificate Authority
     #[arg(long, requires("ca_cert"))]
     ca_key: Option<std::path::PathBuf>,
+
+    /// Maximal time, in seconds, for which connections are held while service is (re)starting
+    #[arg(long, default_value_t = 30)]
+    wait: u64,
+
+    /// Maximal time, in seconds, for which active connections can finish on shutdown, after
+    /// that they are closed
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    shutdown_timeout: u64,
+
+    /// Write access log of all services to the file, `-` stands for standard output
+    #[arg(long, value_name = "PATH")]
+    access_log: Option<std::path::PathBuf>,
+
+    /// Format of the access log
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    access_log_format: crate::access_log::Format,
+
+    /// Export traces of the proxied HTTP requests to the OTLP/HTTP collector, e.g. `http://localhost:4318`
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Answer DNS queries for the domain over UDP and TCP at given address, e.g. `127.0.0.1:5353`
+    #[arg(long, value_name = "ADDR")]
+    dns: Option<std::net::SocketAddr>,
+
+    /// Address returned in DNS answers, can be repeated, defaults to `127.0.0.1` and `::1`
+    #[arg(long, value_name = "IP", requires = "dns")]
+    dns_answer: Vec<std::net::IpAddr>,
+
+    /// Keep the registered services in the hosts file, `/etc/hosts` when no path is given.
+    /// Entries are kept in a marked block, which is removed on shutdown
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = "/etc/hosts"
+    )]
+    hosts_file: Option<std::path::PathBuf>,
+
+    /// Share services in the local network as `<name>.<host>.local`, announced over mDNS.
+    /// Without value all services are shared, with `opt-in` only ones run with `--share`
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        default_missing_value = "all"
+    )]
+    share: Option<crate::share::Mode>,
+
+    /// Host label in the names of the shared services, host name of the machine by default
+    #[arg(long, value_name = "NAME", requires = "share")]
+    share_host: Option<String>,
+
+    /// Address of the server in the local network, detected by default
+    ///
+    /// mDNS announcements are sent through the interface with that address.
+    #[arg(long, value_name = "IP", requires = "share")]
+    share_address: Option<std::net::Ipv4Addr>,
+
+    /// Switch to the user, given by name or ID, once the ports and the control socket are bound.
+    /// The control socket, the log file and the access log are given to the user, other files
+    /// written later (e.g. `--hosts-file`) must be writable by it
+    #[arg(long, value_name = "USER")]
+    user: Option<String>,
+
+    /// Group to switch to, primary group of the `--user` by default
+    #[arg(long, value_name = "GROUP", requires = "user")]
+    group: Option<String>,
+
+    /// Configuration read on the first use, so logging and the server use the same file content
+    #[arg(skip)]
+    loaded: once_cell::sync::OnceCell<(Option<std::path::PathBuf>, Config)>,
 }
 
 impl Command {
-    pub(crate) fn run(self, path: &std::path::Path) -> Result<()> {
+    /// Path of the configuration file, if any, and its content, read only once
+    pub(crate) fn config(&self) -> Result<&(Option<std::path::PathBuf>, Config)> {
+        self.loaded.get_or_try_init(|| {
+            let path = self.config.clone().or_else(Config::default_path);
+            let config = match path {
+                Some(ref path) => Config::load(path)?,
+                None => Config::default(),
+            };
+
+            Ok((path, config))
+        })
+    }
+
+    /// Domain given on the command line, then in the file, `localhost` by default
+    fn domain(&self, config: &Config) -> String {
+        self.domain
+            .clone()
+            .or_else(|| config.domain.clone())
+            .unwrap_or_else(|| "localhost".into())
+    }
+
+    /// Listeners given on the command line replace those in the file
+    fn listen(&self, config: &Config) -> Vec<Listen> {
+        if self.listen.is_empty() {
+            config.listen.clone()
+        } else {
+            self.listen.clone()
+        }
+    }
+
+    /// Certificate Authority given on the command line, then in the file
+    fn ca<'a>(&'a self, config: &'a Config) -> Option<(&'a std::path::Path, &'a std::path::Path)> {
+        match (&self.ca_cert, &self.ca_key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            _ => config.ca_cert.as_deref().zip(config.ca_key.as_deref()),
+        }
+    }
+
+    pub(crate) fn run(
+        self,
+        path: &std::path::Path,
+        logging: &super::logging::Options,
+    ) -> Result<()> {
+        // Before the runtime starts any threads, as it modifies the environment
+        let sockets = crate::activation::take()?;
         let runtime = tokio::runtime::Runtime::new()?;
 
         let span = tracing::span!(tracing::Level::DEBUG, "serve");
         let _guard = span.enter();
 
-        let result = runtime.block_on(self.serve(path));
+        runtime.block_on(self.serve(path, sockets, logging))
+    }
+
+    async fn serve(
+        &self,
+        path: &std::path::Path,
+        mut sockets: Vec<Socket>,
+        logging: &super::logging::Options,
+    ) -> Result<()> {
+        // Unknown user is reported before anything is bound
+        let credentials = match self.user {
+            Some(ref user) => Some(Credentials::lookup(user, self.group.as_deref())?),
+            None => None,
+        };
+        let (config_path, config) = self.config()?;
+        if let Some(ref path) = config_path {
+            tracing::info!(?path, "Configuration");
+        }
+        let domain = self.domain(config);
+        if !crate::service::is_valid_domain(&domain) {
+            bail!("Invalid domain {:?}", domain);
+        }
+        if let Some((cert, key)) = self.ca(config) {
+            crate::proxy::init_ca(crate::proxy::load_ca(cert, key)?);
+        }
+        let control = control_socket(&mut sockets)?;
+        let mut listeners = listen::inherited(sockets)?;
+        let share = match self.share {
+            Some(mode) => Some(crate::share::Share {
+                host: match self.share_host {
+                    Some(ref host) => host.to_ascii_lowercase(),
+                    None => crate::share::hostname()?,
+                },
+                mode,
+                addr: match self.share_address {
+                    Some(addr) => addr,
+                    None => crate::share::lan_address()?,
+                },
+            }),
+            None => None,
+        };
+        let mut listen = self.listen(config);
+        let default = listen.is_empty() && listeners.is_empty();
+        if default {
+            listen.push(Listen::default());
+        }
+        listen.extend(self.http.clone());
+        if let Some(ref share) = share {
+            listen.extend(lan_listeners(&listen, share.addr));
+        }
+        let bound = match listen::bind(&listen) {
+            // Port 80 needs the same privileges, so there is no fallback with `--http`
+            Err(err)
+                if default
+                    && self.http.is_none()
+                    && err.kind() == std::io::ErrorKind::PermissionDenied =>
+            {
+                let fallback = Listen::unprivileged();
+                tracing::warn!(%err, "Listening on a high port instead");
+                listen::bind(&[fallback])?
+            }
+            result => result?,
+        };
+        listeners.extend(bound);
+        let https_port = https_port(&listeners);
+
+        let owned = control.is_none();
+        let registry = match control {
+            Some(socket) => {
+                tracing::info!("Using control socket passed by the service manager");
+                crate::registry::Registry::from_socket(socket, &domain)?
+            }
+            None => crate::registry::Registry::open(path, &domain)?,
+        };
+        let registry = registry.with_share(share.clone());
 
-        tracing::info!("Shutting down");
+        crate::metrics::init();
 
-        result
-    }
+        if let Some(ref endpoint) = self.otlp_endpoint {
+            crate::telemetry::init(endpoint)?;
+        }
 
-    async fn serve(&self, path: &std::path::Path) -> Result<()> {
-        let listener = TcpListener::bind(self.listen).await?;
-        let registry = crate::registry::Registry::open(path, &self.domain)?;
+        if let Some(ref path) = self.access_log {
+            crate::access_log::init(crate::access_log::Log::open(path, self.access_log_format)?);
+        }
+
+        if let Some(addr) = self.dns {
+            let answers = if self.dns_answer.is_empty() {
+                crate::dns::loopback()
+            } else {
+                self.dns_answer.clone()
+            };
+            let server = crate::dns::Server::new(&domain, answers, registry.services.clone());
+            Arc::new(server).listen(addr).await?;
+        }
+
+        let hosts = self.hosts_file.as_ref().map(|path| {
+            Arc::new(crate::hosts::HostsFile::new(
+                path,
+                &domain,
+                crate::dns::loopback(),
+            ))
+        });
+        if let Some(ref hosts) = hosts {
+            hosts.sync(&registry.services).await?;
+        }
+
+        let reloader = Arc::new(reload::Reloader::new(
+            config_path.clone(),
+            &domain,
+            share.clone(),
+            registry.services.clone(),
+            hosts.clone(),
+        ));
+        reloader.apply(config.clone()).await;
+        let registry = registry.with_reload(reloader.clone());
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if let Err(err) = crate::registry::Reload::reload(&*reloader).await {
+                    tracing::error!(%err, "Cannot reload configuration");
+                }
+            }
+        });
+
+        let _announcer = match share {
+            Some(ref share) => {
+                tracing::info!(host = %share.host, addr = %share.addr, mode = ?share.mode, "Sharing");
+                let services = registry.services.clone();
+                let announcer =
+                    crate::share::mdns::Announcer::start(share.addr, https_port, services)?;
+                Some(announcer)
+            }
+            None => None,
+        };
+
+        if let Some(ref credentials) = credentials {
+            let mut files = logging.files(&config.log);
+            if owned {
+                files.push(path.to_owned());
+            }
+            match self.access_log {
+                Some(ref path) if path.as_os_str() != "-" => files.push(path.clone()),
+                _ => (),
+            }
+            for file in files {
+                credentials.chown(&file)?;
+            }
+            credentials.switch()?;
+        }
 
         // Use self signed certificate to make the `rustls` happy (it is not really used right
         // now). In future it may be used for https://localhost or other pages to show list of the
@@ -61,27 +361,198 @@ impl Command {
             config.clone(),
         ));
 
-        tracing::info!(%self.listen, "TCP request");
+        let plain = Arc::new(crate::plain::Server::new(
+            registry.services.clone(),
+            https_port,
+            self.acme_webroot.clone(),
+            Duration::from_secs(self.wait),
+        ));
+
+        // All listeners share the registry, which is handled below
+        let mut accepting = Vec::new();
+        for (role, addr, listener) in listeners {
+            tracing::info!(%addr, %role, "Listening");
+            let task = match role {
+                Role::Tls | Role::Lan => {
+                    let services = registry.services.clone();
+                    let wait = Duration::from_secs(self.wait);
+                    let dashboard = (role == Role::Tls).then(|| dashboard.clone());
+                    tokio::spawn(accept(listener, services, config.clone(), dashboard, wait))
+                }
+                Role::Http => tokio::spawn(plain.clone().serve(listener)),
+                Role::Dashboard => tokio::spawn(accept_dashboard(listener, dashboard.clone())),
+            };
+            accepting.push(task);
+        }
         tracing::info!(?path, "Controller");
 
+        let mut terminate = signal(SignalKind::terminate())?;
         loop {
             tokio::select! {
                 // Control socket
-                _ = registry.handle() => (),
-                // Frontend socket
-                Ok((stream, addr)) = listener.accept() => {
-                    let span = tracing::span!(tracing::Level::DEBUG, "Connection", addr = %addr);
-                    let _guard = span.enter();
+                _ = registry.handle() => {
+                    if let Some(ref hosts) = hosts {
+                        if let Err(err) = hosts.sync(&registry.services).await {
+                            tracing::warn!(%err, "Cannot update hosts file");
+                        }
+                    }
+                }
+                _ = tokio::signal::ctrl_c() => break,
+                _ = terminate.recv() => break,
+            }
+        }
 
-                    let connection = rustls::ServerConnection::new(config.clone())?;
-                    let services = registry.services.clone();
+        tracing::info!("Shutting down");
+        // Listeners are closed once their tasks are gone
+        for task in accepting {
+            task.abort();
+        }
+        // Idle keep-alive connections close right away, busy ones after their current requests
+        crate::shutdown::stop();
+        registry.shutdown().await;
+        self.drain(Duration::from_secs(self.shutdown_timeout)).await;
 
-                    let handler = handle_request(services, stream, connection, dashboard.clone());
+        Ok(())
+    }
 
-                    tokio::spawn(handler);
-                }
-                _ = tokio::signal::ctrl_c() => return Ok(()),
+    /// Let the active connections finish, until the timeout or another interrupt
+    async fn drain(&self, timeout: Duration) {
+        let active = crate::shutdown::active();
+        if active == 0 {
+            return;
+        }
+        tracing::info!(
+            connections = active,
+            ?timeout,
+            "Waiting for connections to finish"
+        );
+
+        tokio::select! {
+            _ = crate::shutdown::idle() => {
+                tracing::info!(connections = active, "All connections finished");
+            }
+            _ = tokio::time::sleep(timeout) => {
+                let closed = crate::shutdown::active();
+                tracing::warn!(connections = closed, "Timed out, closing connections forcibly");
+            }
+            _ = tokio::signal::ctrl_c() => {
+                let closed = crate::shutdown::active();
+                tracing::warn!(connections = closed, "Interrupted, closing connections forcibly");
+            }
+        }
+    }
+}
+
+/// Take the control socket, the UNIX datagram one, from the sockets passed by the service manager
+fn control_socket(sockets: &mut Vec<Socket>) -> Result<Option<std::os::unix::net::UnixDatagram>> {
+    let mut control = None;
+    for index in (0..sockets.len()).rev() {
+        let socket = &sockets[index];
+        match getsockopt(socket.fd.as_raw_fd(), sockopt::SockType)? {
+            SockType::Stream => (),
+            SockType::Datagram if control.is_none() => {
+                control = Some(sockets.remove(index).fd.into());
+            }
+            kind => bail!("Unexpected {:?} socket {}", kind, socket.name),
+        }
+    }
+
+    Ok(control)
+}
+
+/// Port of the first TLS listener, where plain HTTP is redirected and shared services announced
+fn https_port(listeners: &[(Role, std::net::SocketAddr, TcpListener)]) -> u16 {
+    listeners
+        .iter()
+        .find(|(role, _, _)| *role == Role::Tls)
+        .map_or(443, |(_, addr, _)| addr.port())
+}
+
+/// Listeners on the same ports in the local network, for the ones bound only to loopback
+///
+/// Otherwise shared services would be unreachable from other devices. They serve only the shared
+/// services, not everything the loopback ones do.
+fn lan_listeners(listen: &[Listen], lan: std::net::Ipv4Addr) -> Vec<Listen> {
+    let addrs: Vec<_> = listen.iter().flat_map(|listen| &listen.addrs).collect();
+    let mut ports: Vec<_> = listen
+        .iter()
+        .filter(|listen| listen.role == Role::Tls)
+        .flat_map(|listen| &listen.addrs)
+        .filter(|addr| addr.ip().is_loopback())
+        .map(|addr| addr.port())
+        .filter(|&port| {
+            !addrs
+                .iter()
+                .any(|addr| addr.port() == port && (addr.ip().is_unspecified() || addr.ip() == lan))
+        })
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+
+    ports
+        .into_iter()
+        .map(|port| Listen {
+            role: Role::Lan,
+            addrs: vec![(lan, port).into()],
+        })
+        .collect()
+}
+
+/// Accept TLS connections, routed to the services by the SNI
+///
+/// Without the dashboard, connections come from the local network and only the shared services
+/// are reachable.
+async fn accept(
+    listener: TcpListener,
+    services: crate::registry::RegistryStore,
+    config: Arc<rustls::ServerConfig>,
+    dashboard: Option<Arc<crate::dashboard::Server>>,
+    wait: Duration,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!(%err, "Cannot accept connection");
+                continue;
             }
+        };
+        let span = tracing::span!(tracing::Level::DEBUG, "Connection", addr = %addr);
+        let _guard = span.enter();
+
+        let connection = match rustls::ServerConnection::new(config.clone()) {
+            Ok(connection) => connection,
+            Err(err) => {
+                tracing::error!(%err, "Cannot start TLS connection");
+                continue;
+            }
+        };
+        let handler = handle_request(
+            services.clone(),
+            stream,
+            connection,
+            dashboard.clone(),
+            wait,
+        );
+
+        tokio::spawn(crate::shutdown::track(handler));
+    }
+}
+
+/// Accept connections to the dashboard, regardless of the requested name
+async fn accept_dashboard(listener: TcpListener, dashboard: Arc<crate::dashboard::Server>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tracing::debug!(%addr, "Dashboard connection");
+                let dashboard = dashboard.clone();
+                tokio::spawn(crate::shutdown::track(async move {
+                    if let Err(err) = dashboard.handle(stream).await {
+                        tracing::error!(%err);
+                    }
+                }));
+            }
+            Err(err) => tracing::warn!(%err, "Cannot accept dashboard connection"),
         }
     }
 }
@@ -90,36 +561,71 @@ async fn handle_request(
     services: crate::registry::RegistryStore,
     up: TcpStream,
     mut connection: rustls::ServerConnection,
-    dashboard: Arc<crate::dashboard::Server>,
+    dashboard: Option<Arc<crate::dashboard::Server>>,
+    wait: Duration,
 ) {
     let mut buf = [0; 1024];
     // Peek into the 1 MiB of the data and try to check if there is SNI information
-    let len = up.peek(&mut buf).await.unwrap();
+    let len = match up.peek(&mut buf).await {
+        Ok(len) => len,
+        Err(err) => {
+            tracing::debug!(%err, "Connection closed before handshake");
+            return;
+        }
+    };
     if let Some(sni) = crate::service::parse_handshake(&mut connection, &buf[..len]) {
         let span = tracing::span!(tracing::Level::DEBUG, "Request", sni = %sni);
         let _guard = span.enter();
 
         tracing::info!("Request");
 
-        let service = match services.read().await.get(&*sni) {
-            Some(service) => service.clone(),
+        let host = connection.sni_hostname().unwrap_or(&sni).to_owned();
+
+        let found = {
+            let services = services.read().await;
+            match dashboard {
+                Some(_) => crate::service::lookup(&services, &sni),
+                None => crate::service::lookup_shared(&services, &sni),
+            }
+            .map(|(domain, service)| (domain.clone(), service.clone()))
+        };
+        let (domain, service) = match found {
+            Some(found) => found,
+            // Page listing the services is not shown in the local network
+            None if dashboard.is_none() => {
+                tracing::debug!("Unknown shared service");
+                return;
+            }
             None => {
-                // TODO: Redirect to page for service selection
                 tracing::warn!("Unknown service");
+                if let Err(err) = crate::dashboard::unknown_service(up, &host, services).await {
+                    tracing::debug!(%err, "Unknown service page failed");
+                }
                 return;
             }
         };
 
-        tracing::debug!(%service.addr);
-
-        let down = TcpStream::connect(service.addr).await.unwrap();
+        tracing::debug!(%service.addr, %service.state);
 
+        let _connection = crate::metrics::Connection::start(&domain);
         let proxy = service.proxy.clone();
-        proxy.run(up, down).await.unwrap();
-    } else {
+        let down = crate::proxy::Connector::new(&domain, service, services, wait);
+        let ctx = crate::proxy::Context {
+            sni: Some(host),
+            lan: dashboard.is_none(),
+        };
+        if let Err(err) = proxy.run(up, down, ctx).await {
+            tracing::debug!(%err, "Connection closed with error");
+        }
+    } else if let Some(dashboard) = dashboard {
         tracing::info!("Dashboard");
         if let Err(err) = dashboard.handle(up).await {
             tracing::error!(%err);
         }
+    } else {
+        tracing::debug!("Dashboard is not served in the local network");
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,248 @@
+//! Addresses at which the server listens, each with the role of the listener
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+
+use nix::errno::Errno;
+use nix::sys::socket::{setsockopt, sockopt};
+use tokio::net::{TcpListener, TcpSocket};
+
+/// What is served by the listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    /// Services over TLS routed by the SNI, the dashboard for other names
+    Tls,
+    /// Plain HTTP, redirected to HTTPS unless the service opts in
+    Http,
+    /// Only the dashboard, over TLS
+    Dashboard,
+    /// Only services shared in the local network, over TLS by their names there
+    Lan,
+}
+
+impl Role {
+    fn default_port(self) -> u16 {
+        match self {
+            Role::Http => 80,
+            Role::Tls | Role::Dashboard | Role::Lan => 443,
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "tls" => Ok(Role::Tls),
+            "http" => Ok(Role::Http),
+            "dashboard" => Ok(Role::Dashboard),
+            "lan" => Ok(Role::Lan),
+            _ => Err(format!(
+                "unknown role {:?}, expected `tls`, `http`, `dashboard` or `lan`",
+                role
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Tls => "tls",
+            Role::Http => "http",
+            Role::Dashboard => "dashboard",
+            Role::Lan => "lan",
+        })
+    }
+}
+
+/// Listener given as `[ROLE=]ADDR`
+///
+/// Address can be a host name, like `localhost`, in which case the server listens at all its
+/// addresses, or only the port, `:8443`, for any address. Port can be omitted, it is 443 by
+/// default and 80 for plain HTTP.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Listen {
+    pub role: Role,
+    pub addrs: Vec<SocketAddr>,
+}
+
+impl Default for Listen {
+    /// Any address on port 443
+    fn default() -> Self {
+        Listen {
+            role: Role::Tls,
+            addrs: vec![(Ipv6Addr::UNSPECIFIED, Role::Tls.default_port()).into()],
+        }
+    }
+}
+
+impl Listen {
+    /// Any address on port 8443, used by default when the server cannot bind privileged ports
+    pub fn unprivileged() -> Self {
+        Listen {
+            role: Role::Tls,
+            addrs: vec![(Ipv6Addr::UNSPECIFIED, UNPRIVILEGED_PORT).into()],
+        }
+    }
+}
+
+/// Port used instead of 443 by the server run without root or `CAP_NET_BIND_SERVICE`
+const UNPRIVILEGED_PORT: u16 = 8443;
+
+impl std::str::FromStr for Listen {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (role, addr) = match spec.split_once('=') {
+            Some((role, addr)) => (role.parse()?, addr),
+            None => (Role::Tls, spec),
+        };
+
+        Ok(Listen {
+            role,
+            addrs: resolve(addr, role.default_port())?,
+        })
+    }
+}
+
+/// Plain HTTP listener at the address, as given to `--http`
+pub(crate) fn http(addr: &str) -> Result<Listen, String> {
+    Ok(Listen {
+        role: Role::Http,
+        addrs: resolve(addr, Role::Http.default_port())?,
+    })
+}
+
+fn resolve(addr: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return Ok(vec![addr]);
+    }
+    let ip = addr
+        .strip_prefix('[')
+        .and_then(|ip| ip.strip_suffix(']'))
+        .unwrap_or(addr);
+    if let Ok(ip) = ip.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    if let Some(port) = addr.strip_prefix(':') {
+        let port = port.parse().map_err(|_| "invalid port")?;
+        return Ok(vec![(Ipv6Addr::UNSPECIFIED, port).into()]);
+    }
+
+    let (host, port) = match addr.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| "invalid port")?),
+        None => (addr, port),
+    };
+    let mut addrs: Vec<_> = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| format!("cannot resolve {}: {}", host, err))?
+        .collect();
+    addrs.sort();
+    addrs.dedup();
+
+    if addrs.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+
+    Ok(addrs)
+}
+
+/// Bind all listeners
+///
+/// Sockets bound to any IPv6 address accept IPv4 connections too, unless there is a separate
+/// listener for any IPv4 address on the same port. When IPv6 is not available at all, they are
+/// bound to any IPv4 address instead.
+pub(crate) fn bind(listens: &[Listen]) -> io::Result<Vec<(Role, SocketAddr, TcpListener)>> {
+    let addrs: Vec<_> = listens
+        .iter()
+        .flat_map(|listen| listen.addrs.iter().map(|addr| (listen.role, *addr)))
+        .collect();
+
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for &(role, addr) in &addrs {
+        let ipv4: SocketAddr = (Ipv4Addr::UNSPECIFIED, addr.port()).into();
+        let dual_stack = dual_stack(addr, &addrs);
+
+        let listener = match bind_one(addr, dual_stack) {
+            Err(err) if dual_stack && ipv6_unavailable(&err) => {
+                tracing::warn!(%addr, "IPv6 is not available, listening on IPv4 only");
+                bind_one(ipv4, false).map_err(|err| bind_error(ipv4, err))?
+            }
+            result => result.map_err(|err| bind_error(addr, err))?,
+        };
+        listeners.push((role, listener.local_addr()?, listener));
+    }
+
+    Ok(listeners)
+}
+
+/// Whether the socket at the address should accept IPv4 clients too
+fn dual_stack(addr: SocketAddr, addrs: &[(Role, SocketAddr)]) -> bool {
+    let ipv4: SocketAddr = (Ipv4Addr::UNSPECIFIED, addr.port()).into();
+
+    addr.ip().is_unspecified() && addr.is_ipv6() && !addrs.iter().any(|&(_, other)| other == ipv4)
+}
+
+/// Listeners passed by the service manager, their roles are given by the socket names
+///
+/// Sockets with names other than roles, like the default name of the socket unit, serve TLS.
+pub(crate) fn inherited(
+    sockets: Vec<crate::activation::Socket>,
+) -> io::Result<Vec<(Role, SocketAddr, TcpListener)>> {
+    sockets
+        .into_iter()
+        .map(|socket| {
+            let role = socket.name.parse().unwrap_or(Role::Tls);
+            let listener = std::net::TcpListener::from(socket.fd);
+            listener.set_nonblocking(true)?;
+            let listener = TcpListener::from_std(listener)?;
+
+            Ok((role, listener.local_addr()?, listener))
+        })
+        .collect()
+}
+
+/// Add the address to the error, with a hint for the privileged ports
+fn bind_error(addr: SocketAddr, err: io::Error) -> io::Error {
+    let hint = match err.kind() {
+        io::ErrorKind::PermissionDenied if addr.port() < 1024 => {
+            ", ports below 1024 need root or CAP_NET_BIND_SERVICE"
+        }
+        _ => "",
+    };
+
+    io::Error::new(
+        err.kind(),
+        format!("cannot listen at {}: {}{}", addr, err, hint),
+    )
+}
+
+/// IPv6 is disabled in the kernel, or has no addresses configured
+fn ipv6_unavailable(err: &io::Error) -> bool {
+    let unavailable = [Errno::EAFNOSUPPORT, Errno::EADDRNOTAVAIL];
+
+    unavailable
+        .iter()
+        .any(|&errno| err.raw_os_error() == Some(errno as i32))
+}
+
+fn bind_one(addr: SocketAddr, dual_stack: bool) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => {
+            let socket = TcpSocket::new_v6()?;
+            setsockopt(socket.as_raw_fd(), sockopt::Ipv6V6Only, &!dual_stack)?;
+            socket
+        }
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+
+    socket.listen(1024)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,125 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::{bind, dual_stack, http, Listen, Role};
+
+fn listen(spec: &str) -> Listen {
+    spec.parse().unwrap()
+}
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn role_defaults_to_tls_on_port_443() {
+    assert_eq!(
+        listen("127.0.0.1"),
+        Listen {
+            role: Role::Tls,
+            addrs: vec![addr("127.0.0.1:443")],
+        }
+    );
+    assert_eq!(listen("127.0.0.1:8443").addrs, vec![addr("127.0.0.1:8443")]);
+}
+
+#[test]
+fn plain_http_defaults_to_port_80() {
+    let listen = listen("http=127.0.0.1");
+
+    assert_eq!(listen.role, Role::Http);
+    assert_eq!(listen.addrs, vec![addr("127.0.0.1:80")]);
+}
+
+#[test]
+fn port_alone_listens_at_any_address() {
+    assert_eq!(listen("http=:8080").addrs, vec![addr("[::]:8080")]);
+    assert_eq!(listen(":8443").addrs, vec![addr("[::]:8443")]);
+    assert!(":https".parse::<Listen>().is_err());
+}
+
+#[test]
+fn ipv6_with_and_without_brackets() {
+    assert_eq!(listen("[::1]").addrs, vec![addr("[::1]:443")]);
+    assert_eq!(listen("::1").addrs, vec![addr("[::1]:443")]);
+    assert_eq!(
+        listen("dashboard=[::1]:9000").addrs,
+        vec![addr("[::1]:9000")]
+    );
+}
+
+#[test]
+fn host_names_listen_at_all_addresses() {
+    let listen = listen("lan=localhost:8443");
+
+    assert_eq!(listen.role, Role::Lan);
+    assert!(listen.addrs.contains(&addr("127.0.0.1:8443")));
+    assert!(listen.addrs.iter().all(|addr| addr.ip().is_loopback()));
+    assert!(listen.addrs.iter().all(|addr| addr.port() == 8443));
+
+    assert!("localhost:https".parse::<Listen>().is_err());
+}
+
+#[test]
+fn unknown_roles_are_rejected() {
+    let err = "ftp=127.0.0.1".parse::<Listen>().unwrap_err();
+
+    assert!(err.contains("unknown role \"ftp\""), "{}", err);
+}
+
+#[test]
+fn roles_are_shown_as_parsed() {
+    for role in [Role::Tls, Role::Http, Role::Dashboard, Role::Lan] {
+        assert_eq!(role.to_string().parse::<Role>().unwrap(), role);
+    }
+}
+
+#[test]
+fn http_option_is_plain_http_listener() {
+    assert_eq!(http("[::]:80").unwrap(), listen("http=[::]:80"));
+    assert_eq!(http("127.0.0.1").unwrap(), listen("http=127.0.0.1:80"));
+}
+
+#[test]
+fn any_ipv6_address_accepts_ipv4_without_own_listener() {
+    let any: SocketAddr = (Ipv6Addr::UNSPECIFIED, 443).into();
+    let ipv4: SocketAddr = (Ipv4Addr::UNSPECIFIED, 443).into();
+    let other_port: SocketAddr = (Ipv4Addr::UNSPECIFIED, 80).into();
+    let loopback = addr("[::1]:443");
+
+    assert!(dual_stack(any, &[(Role::Tls, any)]));
+    assert!(dual_stack(
+        any,
+        &[(Role::Tls, any), (Role::Http, other_port)]
+    ));
+    assert!(!dual_stack(any, &[(Role::Tls, any), (Role::Tls, ipv4)]));
+    assert!(!dual_stack(ipv4, &[(Role::Tls, ipv4)]));
+    assert!(!dual_stack(loopback, &[(Role::Tls, loopback)]));
+}
+
+#[tokio::test]
+async fn bound_listeners_keep_roles_and_report_addresses() {
+    let bound = bind(&[listen("127.0.0.1:0"), listen("http=127.0.0.1:0")]).unwrap();
+
+    let roles: Vec<_> = bound.iter().map(|(role, _, _)| *role).collect();
+    assert_eq!(roles, [Role::Tls, Role::Http]);
+    for (_, addr, listener) in &bound {
+        assert_ne!(addr.port(), 0);
+        assert_eq!(*addr, listener.local_addr().unwrap());
+    }
+}
+
+#[tokio::test]
+async fn bind_error_names_address() {
+    let bound = bind(&[listen("127.0.0.1:0")]).unwrap();
+    let taken = bound[0].1;
+
+    let err = bind(&[listen(&taken.to_string())]).unwrap_err();
+
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+    assert!(
+        err.to_string()
+            .starts_with(&format!("cannot listen at {}", taken)),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,136 @@
+//! Applying the configuration file again, on `SIGHUP` or `dolores reload`
+//!
+//! Static services, header rules and the log filter are updated in place, so registrations of the
+//! runners and open connections are kept. Other settings need the server to be restarted.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::cli::config::Config;
+use crate::registry::RegistryStore;
+
+#[derive(Debug)]
+pub(crate) struct Reloader {
+    /// Configuration file, if the server was started with one
+    path: Option<PathBuf>,
+    domain: String,
+    share: Option<crate::share::Share>,
+    services: RegistryStore,
+    hosts: Option<Arc<crate::hosts::HostsFile>>,
+    /// Configuration currently in effect
+    current: Mutex<Config>,
+}
+
+impl Reloader {
+    pub(crate) fn new(
+        path: Option<PathBuf>,
+        domain: &str,
+        share: Option<crate::share::Share>,
+        services: RegistryStore,
+        hosts: Option<Arc<crate::hosts::HostsFile>>,
+    ) -> Self {
+        Reloader {
+            path,
+            domain: domain.into(),
+            share,
+            services,
+            hosts,
+            current: Mutex::new(Config::default()),
+        }
+    }
+
+    /// Bring the running server in line with the configuration
+    pub(crate) async fn apply(&self, config: Config) {
+        let mut current = self.current.lock().await;
+
+        // Before the services are registered, so they are checked against the new rules
+        crate::headers::set(config.header_rules(&self.domain));
+        for name in config
+            .headers
+            .iter()
+            .filter_map(|headers| headers.service.as_ref())
+        {
+            // Changed services are checked once they are registered below
+            let unchanged = config
+                .services
+                .get(name)
+                .filter(|service| current.services.get(name) == Some(*service));
+            if unchanged.is_some_and(|service| service.proxy() != crate::proxy::Type::Http) {
+                tracing::warn!(%name, "Header rules of the service apply only with `http` proxy");
+            }
+        }
+
+        for name in current.services.keys() {
+            if !config.services.contains_key(name) {
+                crate::registry::deregister_static(&self.services, &self.domain, name).await;
+            }
+        }
+        for (name, service) in &config.services {
+            if current.services.get(name) != Some(service) {
+                let (proxy, options) = (service.proxy(), service.options());
+                let share = self.share.as_ref();
+                let mut service =
+                    crate::registry::build(&self.domain, share, name, service.addr, proxy, options);
+                // Paths in the configuration are trusted, unlike those sent by the clients
+                service.access_log = crate::access_log::Log::configured(&service.options);
+                crate::registry::register_static(&self.services, service).await;
+            }
+        }
+        if let Some(ref hosts) = self.hosts {
+            if let Err(err) = hosts.sync(&self.services).await {
+                tracing::warn!(%err, "Cannot update hosts file");
+            }
+        }
+
+        if config.log.filter != current.log.filter {
+            if let Err(err) = crate::cli::logging::reload_filter(config.log.filter.as_deref()) {
+                tracing::warn!(%err, "Cannot change log filter");
+            }
+        }
+
+        *current = config;
+    }
+}
+
+#[async_trait]
+impl crate::registry::Reload for Reloader {
+    async fn reload(&self) -> Result<(), String> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or("server was started without configuration file")?;
+        let config = Config::load(path).map_err(|err| format!("{:#}", err))?;
+
+        {
+            let current = self.current.lock().await;
+            let restart = [
+                ("domain", config.domain != current.domain),
+                ("listen", config.listen != current.listen),
+                (
+                    "ca_cert",
+                    config.ca_cert != current.ca_cert || config.ca_key != current.ca_key,
+                ),
+                (
+                    "log",
+                    config.log.file != current.log.file
+                        || config.log.format != current.log.format
+                        || config.log.file_size != current.log.file_size
+                        || config.log.file_keep != current.log.file_keep,
+                ),
+            ];
+            for (setting, _) in restart.iter().filter(|(_, changed)| *changed) {
+                tracing::warn!(
+                    setting,
+                    "Setting is changed, restart the server to apply it"
+                );
+            }
+        }
+
+        tracing::info!(?path, "Reloading configuration");
+        self.apply(config).await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::handle_request;
+use crate::proxy::{Options, Type};
+use crate::registry::RegistryStore;
+use crate::service::Service;
+
+/// Registry with `app` shared in the local network and `private` only on this machine
+fn registry(addr: SocketAddr) -> RegistryStore {
+    let shared = Service::with_aliases(
+        "app.localhost",
+        vec!["app.host.local".into()],
+        addr,
+        Type::Passthrough,
+        &Options::default(),
+    );
+    let private = Service::new(
+        "private.localhost",
+        addr,
+        Type::Passthrough,
+        &Options::default(),
+    );
+
+    Arc::new(tokio::sync::RwLock::new(HashMap::from([
+        ("app.localhost".to_owned(), shared),
+        ("private.localhost".to_owned(), private),
+    ])))
+}
+
+fn server_config() -> Arc<rustls::ServerConfig> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![rustls::Certificate(cert.serialize_der().unwrap())],
+            rustls::PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+
+    Arc::new(config)
+}
+
+/// First flight of the client, with the name in the SNI
+fn client_hello(name: &str) -> Vec<u8> {
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(rustls::RootCertStore::empty())
+        .with_no_client_auth();
+    let mut connection =
+        rustls::ClientConnection::new(Arc::new(config), name.try_into().unwrap()).unwrap();
+    let mut hello = Vec::new();
+    connection.write_tls(&mut hello).unwrap();
+
+    hello
+}
+
+/// Send the data over connection accepted by the LAN listener
+async fn connect_lan(services: &RegistryStore, data: &[u8]) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.write_all(data).await.unwrap();
+
+    let (up, _) = listener.accept().await.unwrap();
+    let connection = rustls::ServerConnection::new(server_config()).unwrap();
+    let wait = Duration::from_secs(1);
+    tokio::spawn(handle_request(services.clone(), up, connection, None, wait));
+
+    client
+}
+
+/// Connection is closed without any response, with reset as the client data was not read
+async fn assert_closed(mut client: TcpStream) {
+    let mut received = Vec::new();
+    let result = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+        .await
+        .expect("connection is not closed");
+    match result {
+        Err(err) if err.kind() == std::io::ErrorKind::ConnectionReset => (),
+        result => {
+            result.unwrap();
+        }
+    }
+    assert!(received.is_empty(), "received {:?}", received);
+}
+
+#[tokio::test]
+async fn lan_reaches_shared_services_by_their_aliases() {
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let services = registry(app.local_addr().unwrap());
+
+    let hello = client_hello("app.host.local");
+    let _client = connect_lan(&services, &hello).await;
+
+    let (mut down, _) = tokio::time::timeout(Duration::from_secs(5), app.accept())
+        .await
+        .expect("shared service is not reached")
+        .unwrap();
+    let mut received = vec![0; hello.len()];
+    down.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, hello);
+}
+
+#[tokio::test]
+async fn lan_does_not_reach_services_by_their_domains() {
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let services = registry(app.local_addr().unwrap());
+
+    for name in ["app.localhost", "private.localhost", "private.host.local"] {
+        assert_closed(connect_lan(&services, &client_hello(name)).await).await;
+    }
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), app.accept())
+            .await
+            .is_err(),
+        "service is reached"
+    );
+}
+
+#[tokio::test]
+async fn lan_does_not_serve_dashboard() {
+    let services = registry("[::1]:1".parse().unwrap());
+
+    // Single label names and missing SNI are meant for the dashboard
+    assert_closed(connect_lan(&services, &client_hello("localhost")).await).await;
+    assert_closed(connect_lan(&services, b"GET / HTTP/1.1\r\n\r\n").await).await;
+}
+
+#[tokio::test]
+async fn connection_reset_before_handshake_is_closed() {
+    let services = registry("[::1]:1".parse().unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (up, _) = listener.accept().await.unwrap();
+    // Without lingering, closing sends reset instead of the end of the stream
+    client.set_linger(Some(Duration::ZERO)).unwrap();
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let connection = rustls::ServerConnection::new(server_config()).unwrap();
+    let wait = Duration::from_secs(1);
+    tokio::spawn(handle_request(services, up, connection, None, wait))
+        .await
+        .expect("connection task failed");
+}
+
+#[test]
+fn shared_lookup_ignores_domains() {
+    let services = registry("[::1]:1".parse().unwrap());
+    let services = services.try_read().unwrap();
+
+    let found = |host| crate::service::lookup_shared(&services, host).map(|(domain, _)| domain);
+    assert_eq!(found("app.host.local").unwrap(), "app.localhost");
+    assert_eq!(found("api.app.host.local").unwrap(), "app.localhost");
+    assert_eq!(found("app.localhost"), None);
+    assert_eq!(found("private.localhost"), None);
+
+    let found = |host| crate::service::lookup(&services, host).map(|(domain, _)| domain);
+    assert_eq!(found("app.host.local").unwrap(), "app.localhost");
+    assert_eq!(found("private.localhost").unwrap(), "private.localhost");
+}
+
+/// Parse the options of `dolores serve`
+fn command(args: &[&str]) -> super::Command {
+    #[derive(clap::Parser)]
+    struct Serve {
+        #[command(flatten)]
+        command: super::Command,
+    }
+
+    let args = std::iter::once("serve").chain(args.iter().copied());
+    <Serve as clap::Parser>::parse_from(args).command
+}
+
+/// Configuration file with the domain and the listener
+fn config_file() -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("dolores-{:x}.conf", rand::random::<u64>()));
+    std::fs::write(&path, "domain = \"file\"\nlisten = [\"127.0.0.1:1443\"]\n").unwrap();
+    path
+}
+
+#[test]
+fn file_is_used_without_command_line_options() {
+    let path = config_file();
+    let cmd = command(&["--config", path.to_str().unwrap()]);
+    let (config_path, config) = cmd.config().unwrap();
+
+    assert_eq!(config_path.as_deref(), Some(path.as_path()));
+    assert_eq!(cmd.domain(config), "file");
+    assert_eq!(
+        cmd.listen(config),
+        vec!["127.0.0.1:1443".parse::<super::Listen>().unwrap()]
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn command_line_takes_precedence_over_file() {
+    let path = config_file();
+    let cmd = command(&[
+        "--config",
+        path.to_str().unwrap(),
+        "--domain",
+        "cli",
+        "--listen",
+        "http=127.0.0.1:8080",
+    ]);
+    let (_, config) = cmd.config().unwrap();
+
+    assert_eq!(cmd.domain(config), "cli");
+    assert_eq!(
+        cmd.listen(config),
+        vec!["http=127.0.0.1:8080".parse::<super::Listen>().unwrap()]
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn domain_is_localhost_by_default() {
+    let cmd = command(&[]);
+
+    assert_eq!(cmd.domain(&Default::default()), "localhost");
+    assert!(cmd.listen(&Default::default()).is_empty());
+}
+
+#[test]
+fn configuration_is_read_once() {
+    let path = config_file();
+    let cmd = command(&["--config", path.to_str().unwrap()]);
+    assert_eq!(cmd.config().unwrap().1.domain.as_deref(), Some("file"));
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(cmd.config().unwrap().1.domain.as_deref(), Some("file"));
+}
+
+#[test]
+fn http_option_defaults_to_any_address() {
+    let cmd = command(&["--http"]);
+    assert_eq!(cmd.http, Some("http=[::]:80".parse().unwrap()));
+
+    let cmd = command(&["--http", "127.0.0.1:8080"]);
+    assert_eq!(cmd.http, Some("http=127.0.0.1:8080".parse().unwrap()));
+
+    assert_eq!(command(&[]).http, None);
+}
+
+#[test]
+fn loopback_tls_listeners_are_shared_in_local_network() {
+    let lan = std::net::Ipv4Addr::new(192, 168, 1, 2);
+    let listen = |specs: &[&str]| -> Vec<super::Listen> {
+        specs.iter().map(|spec| spec.parse().unwrap()).collect()
+    };
+
+    assert_eq!(
+        super::lan_listeners(&listen(&["127.0.0.1:1443", "[::1]:1443"]), lan),
+        listen(&["lan=192.168.1.2:1443"])
+    );
+    assert_eq!(
+        super::lan_listeners(&listen(&["127.0.0.1:2443", "127.0.0.1:1443"]), lan),
+        listen(&["lan=192.168.1.2:1443", "lan=192.168.1.2:2443"])
+    );
+}
+
+#[test]
+fn reachable_listeners_are_not_shared_again() {
+    let lan = std::net::Ipv4Addr::new(192, 168, 1, 2);
+    let listen = |specs: &[&str]| -> Vec<super::Listen> {
+        specs.iter().map(|spec| spec.parse().unwrap()).collect()
+    };
+
+    assert!(super::lan_listeners(&listen(&["[::]:443"]), lan).is_empty());
+    assert!(super::lan_listeners(&listen(&["127.0.0.1:443", "0.0.0.0:443"]), lan).is_empty());
+    assert!(super::lan_listeners(&listen(&["127.0.0.1:443", "192.168.1.2:443"]), lan).is_empty());
+    assert!(super::lan_listeners(&listen(&["http=127.0.0.1:80"]), lan).is_empty());
+    assert!(super::lan_listeners(&listen(&["dashboard=127.0.0.1:9000"]), lan).is_empty());
+}
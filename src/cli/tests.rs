@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+
+use super::{socket_path, App, SYSTEM_SOCKET};
+
+/// Runtime directory with the socket of a running server
+fn runtime_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dolores-run-{:x}", rand::random::<u64>()));
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("dolores.sock"), "").unwrap();
+    dir
+}
+
+#[test]
+fn explicit_path_is_always_used() {
+    let explicit = Path::new("/tmp/explicit.sock");
+    let dir = runtime_dir();
+
+    assert_eq!(socket_path(Some(explicit), Some(&dir), true), explicit);
+    assert_eq!(socket_path(Some(explicit), Some(&dir), false), explicit);
+    assert_eq!(socket_path(Some(explicit), None, false), explicit);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn server_uses_runtime_directory() {
+    let dir = Path::new("/nonexistent/run/user/1000");
+
+    assert_eq!(socket_path(None, Some(dir), true), dir.join("dolores.sock"));
+    assert_eq!(socket_path(None, None, true), Path::new(SYSTEM_SOCKET));
+}
+
+#[test]
+fn client_uses_runtime_directory_only_with_server_there() {
+    let dir = runtime_dir();
+    let empty = Path::new("/nonexistent/run/user/1000");
+
+    assert_eq!(
+        socket_path(None, Some(&dir), false),
+        dir.join("dolores.sock")
+    );
+    assert_eq!(
+        socket_path(None, Some(empty), false),
+        Path::new(SYSTEM_SOCKET)
+    );
+    assert_eq!(socket_path(None, None, false), Path::new(SYSTEM_SOCKET));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn generated_units_use_system_socket() {
+    let app: App = clap::Parser::parse_from(["dolores", "gen", "systemd"]);
+    assert_eq!(app.socket_path(), Path::new(SYSTEM_SOCKET));
+
+    let app: App = clap::Parser::parse_from([
+        "dolores",
+        "--socket",
+        "/tmp/explicit.sock",
+        "gen",
+        "systemd",
+    ]);
+    assert_eq!(app.socket_path(), Path::new("/tmp/explicit.sock"));
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::io;
+
+use askama::Template;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Response, StatusCode};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use super::handlers::filters;
+use crate::proxy::ConnectError;
+use crate::registry::RegistryStore;
+
+#[derive(Template)]
+#[template(path = "unknown.html")]
+struct UnknownTemplate<'a> {
+    host: &'a str,
+    name: &'a str,
+    port: u16,
+    registry: &'a HashMap<String, crate::service::Service>,
+}
+
+/// Amount of the recent output lines of the application shown on the bad gateway page
+const BAD_GATEWAY_LOG_LINES: usize = 20;
+
+#[derive(Template)]
+#[template(path = "bad_gateway.html")]
+struct BadGatewayTemplate<'a> {
+    error: &'a ConnectError,
+    lines: Vec<crate::logs::Line>,
+}
+
+#[derive(Template)]
+#[template(path = "waiting.html")]
+struct WaitingTemplate<'a> {
+    service: &'a crate::service::Service,
+    refresh: u64,
+}
+
+/// Respond with list of registered services to client that requested unknown domain
+pub async fn unknown_service(
+    stream: TcpStream,
+    host: &str,
+    registry: RegistryStore,
+) -> io::Result<()> {
+    let port = stream.local_addr()?.port();
+    let page = {
+        let registry = registry.read().await;
+        let name = host.split('.').next().unwrap_or(host);
+
+        UnknownTemplate {
+            host,
+            name,
+            port,
+            registry: &registry,
+        }
+        .render()
+        .map_err(io::Error::other)?
+    };
+
+    // Terminate TLS using certificate generated for the requested host
+    let tls = crate::proxy::TlsTerminating::generated(host.to_owned().into());
+    let stream = tls.accept(stream).await?;
+
+    respond(stream, page_response(StatusCode::NOT_FOUND, page)).await
+}
+
+/// Respond on already established connection that the application cannot be reached
+pub async fn bad_gateway<S>(stream: S, error: &ConnectError, lan: bool) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    respond(stream, bad_gateway_response(error, lan)).await
+}
+
+/// Page informing that the application of the service cannot be reached
+///
+/// Recent output of the application is not shown to the clients from the local network, as it
+/// often contains secrets.
+pub fn bad_gateway_response(error: &ConnectError, lan: bool) -> Response<Body> {
+    let lines = match lan {
+        true => Vec::new(),
+        false => error.service.logs.since(0, BAD_GATEWAY_LOG_LINES),
+    };
+
+    match (BadGatewayTemplate { error, lines }).render() {
+        Ok(page) => page_response(StatusCode::BAD_GATEWAY, page),
+        Err(err) => {
+            tracing::error!(%err, "Cannot render page");
+            page_response(StatusCode::BAD_GATEWAY, error.to_string())
+        }
+    }
+}
+
+/// Auto refreshing page shown while waiting for the application to start
+pub fn waiting_response(service: &crate::service::Service) -> Response<Body> {
+    let refresh = 1;
+    let mut response = match (WaitingTemplate { service, refresh }).render() {
+        Ok(page) => page_response(StatusCode::SERVICE_UNAVAILABLE, page),
+        Err(err) => {
+            tracing::error!(%err, "Cannot render page");
+            page_response(StatusCode::SERVICE_UNAVAILABLE, service.state.to_string())
+        }
+    };
+    response
+        .headers_mut()
+        .insert(http::header::RETRY_AFTER, refresh.into());
+
+    response
+}
+
+fn page_response(status: StatusCode, page: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(page));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("text/html"),
+    );
+    headers.insert(
+        http::header::CACHE_CONTROL,
+        http::HeaderValue::from_static("no-store"),
+    );
+
+    response
+}
+
+/// Respond to all requests on the connection with copy of the `response`
+async fn respond<S>(stream: S, response: Response<Body>) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(io::Error::other)?;
+
+    let service = service_fn(move |req| {
+        tracing::debug!(?req, status = %parts.status, "Error page");
+        let mut response = Response::new(Body::from(body.clone()));
+        *response.status_mut() = parts.status;
+        *response.headers_mut() = parts.headers.clone();
+
+        async move { Ok::<_, std::convert::Infallible>(response) }
+    });
+
+    Http::new()
+        .serve_connection(stream, service)
+        .await
+        .map_err(io::Error::other)
+}
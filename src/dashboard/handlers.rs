@@ -25,7 +25,7 @@ impl super::Handler for Home {
     ) -> Result<Response<Body>> {
         let registry = ctx.registry.read().await;
 
-        let view = HomeTemplate { req, registry: &*registry };
+        let view = HomeTemplate { req, registry: &registry };
 
         Ok(Response::builder()
             .header("content-type", "text/html")
@@ -46,7 +46,411 @@ impl super::Handler for Health {
     }
 }
 
-mod filters {
+pub struct Metrics;
+
+#[async_trait]
+impl super::Handler for Metrics {
+    async fn handle(
+        self: Arc<Self>,
+        _req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        let scrapes = crate::metrics::federation::scrape_all(&ctx.registry).await;
+        let mut body = crate::metrics::federation::render(&scrapes);
+        body.push_str(&crate::metrics::render());
+
+        Ok(Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Body::from(body))?)
+    }
+}
+
+pub struct ServiceMetrics;
+
+#[derive(Template)]
+#[template(path = "metrics.html")]
+struct ServiceMetricsTemplate {
+    scrape: crate::metrics::federation::Scrape,
+}
+
+#[async_trait]
+impl super::Handler for ServiceMetrics {
+    async fn handle(
+        self: Arc<Self>,
+        _req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        let domain = ctx.param("service")?;
+        let service = ctx.registry.read().await.get(domain).cloned();
+
+        match service {
+            Some(service) if service.metrics_url().is_some() => {
+                let scrape = crate::metrics::federation::scrape(domain, &service).await;
+                html(ServiceMetricsTemplate { scrape })
+            }
+            _ => not_found(),
+        }
+    }
+}
+
+pub struct NotFound;
+
+#[async_trait]
+impl super::Handler for NotFound {
+    async fn handle(
+        self: Arc<Self>,
+        _req: Request<Body>,
+        _ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        not_found()
+    }
+}
+
+fn not_found() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("Not found\n"))?)
+}
+
+fn html(page: impl Template) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .header("content-type", "text/html")
+        .body(Body::from(page.render()?))?)
+}
+
+/// Whether the request comes from a page of the dashboard itself
+///
+/// Browsers send `Sec-Fetch-Site`, older ones at least `Origin` with forms, requests without
+/// both do not come from a browser.
+fn same_origin(req: &Request<Body>) -> bool {
+    if let Some(site) = req.headers().get("sec-fetch-site") {
+        return site == "same-origin" || site == "none";
+    }
+    match req.headers().get("origin") {
+        Some(origin) => {
+            let origin = origin.to_str().ok().and_then(|origin| origin.parse::<hyper::Uri>().ok());
+            match (origin, req.uri().authority()) {
+                (Some(origin), Some(host)) => {
+                    origin.scheme_str() == Some("https") && origin.authority() == Some(host)
+                }
+                _ => false,
+            }
+        }
+        None => true,
+    }
+}
+
+/// Service given in the route, if the user can see its traffic and output
+async fn service(ctx: &super::Context) -> Result<Option<crate::service::Service>> {
+    let name = ctx.param("service")?;
+    let service = ctx.registry.read().await.get(name).cloned();
+
+    Ok(service.filter(|service| {
+        let permitted = service.permits(ctx.user);
+        if !permitted {
+            tracing::debug!(user = ?ctx.user, "Service of another user");
+        }
+        permitted
+    }))
+}
+
+/// Capture buffer of the service given in the route
+async fn capture(ctx: &super::Context) -> Result<Option<Arc<crate::capture::Buffer>>> {
+    Ok(service(ctx).await?.and_then(|service| service.capture))
+}
+
+pub struct Requests;
+
+#[derive(Template)]
+#[template(path = "requests.html")]
+struct RequestsTemplate<'a> {
+    service: &'a str,
+    exchanges: Vec<Arc<crate::capture::Exchange>>,
+}
+
+#[async_trait]
+impl super::Handler for Requests {
+    async fn handle(
+        self: Arc<Self>,
+        _req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        let buffer = match capture(&ctx).await? {
+            Some(buffer) => buffer,
+            None => return not_found(),
+        };
+
+        html(RequestsTemplate {
+            service: ctx.param("service")?,
+            exchanges: buffer.list(),
+        })
+    }
+}
+
+pub struct Har;
+
+#[async_trait]
+impl super::Handler for Har {
+    async fn handle(
+        self: Arc<Self>,
+        _req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        let buffer = match capture(&ctx).await? {
+            Some(buffer) => buffer,
+            None => return not_found(),
+        };
+        let service = ctx.param("service")?;
+
+        Ok(Response::builder()
+            .header("content-type", "application/json")
+            .header(
+                "content-disposition",
+                format!("attachment; filename=\"{}.har\"", service),
+            )
+            .body(Body::from(crate::capture::Format::Har.export(&buffer.list())))?)
+    }
+}
+
+pub struct RequestDetails;
+
+#[derive(Template)]
+#[template(path = "request.html")]
+struct RequestTemplate {
+    exchange: Arc<crate::capture::Exchange>,
+}
+
+#[async_trait]
+impl super::Handler for RequestDetails {
+    async fn handle(
+        self: Arc<Self>,
+        _req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        let id = ctx.param("id")?.parse().ok();
+        let exchange = capture(&ctx)
+            .await?
+            .zip(id)
+            .and_then(|(buffer, id)| buffer.get(id));
+
+        match exchange {
+            Some(exchange) => html(RequestTemplate { exchange }),
+            None => not_found(),
+        }
+    }
+}
+
+pub struct Replay;
+
+#[derive(Template)]
+#[template(path = "replay.html")]
+struct ReplayTemplate {
+    original: Arc<crate::capture::Exchange>,
+    replayed: crate::capture::Exchange,
+    diff: String,
+}
+
+#[async_trait]
+impl super::Handler for Replay {
+    async fn handle(
+        self: Arc<Self>,
+        req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        if req.method() != hyper::Method::POST {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("allow", "POST")
+                .body(Body::from("Method not allowed\n"))?);
+        }
+        // Replay sends requests to the application, another site must not trigger it
+        if !same_origin(&req) {
+            return Ok(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("Cross-origin request\n"))?);
+        }
+
+        let service = service(&ctx).await?;
+        let id = ctx.param("id")?.parse().ok();
+        let found = service.zip(id).and_then(|(service, id)| {
+            let original = service.capture.as_ref()?.get(id)?;
+            Some((service, original))
+        });
+        let (service, original) = match found {
+            Some(found) => found,
+            None => return not_found(),
+        };
+
+        let form = hyper::body::to_bytes(req.into_body()).await?;
+        let mut edit = crate::capture::Edit::default();
+        for (key, value) in form_urlencoded::parse(&form) {
+            match &*key {
+                "headers" => {
+                    let headers = value
+                        .lines()
+                        .filter_map(|line| line.split_once(':'))
+                        .map(|(name, value)| (name.trim().into(), value.trim().into()))
+                        .collect();
+                    edit.headers = Some(headers);
+                }
+                // Browsers submit line breaks as CRLF regardless of what was in the textarea
+                "body" => edit.body = Some(value.replace("\r\n", "\n").into_bytes()),
+                _ => (),
+            }
+        }
+
+        let replayed = match crate::capture::replay(&service, &original, &edit).await {
+            Ok(replayed) => replayed,
+            Err(err) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Cannot replay request: {}\n", err)))?)
+            }
+        };
+        let diff = crate::capture::diff(&original, &replayed);
+
+        html(ReplayTemplate {
+            original,
+            replayed,
+            diff,
+        })
+    }
+}
+
+/// Output buffer of the service given in the route
+async fn logs(ctx: &super::Context) -> Result<Option<Arc<crate::logs::Buffer>>> {
+    Ok(service(ctx).await?.map(|service| service.logs))
+}
+
+pub struct Logs;
+
+#[derive(Template)]
+#[template(path = "logs.html")]
+struct LogsTemplate<'a> {
+    service: &'a str,
+    lines: Vec<crate::logs::Line>,
+}
+
+impl LogsTemplate<'_> {
+    /// Sequence number from which the live updates continue
+    fn last_seq(&self) -> u64 {
+        self.lines.last().map_or(0, |line| line.seq)
+    }
+}
+
+#[async_trait]
+impl super::Handler for Logs {
+    async fn handle(
+        self: Arc<Self>,
+        _req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        let buffer = match logs(&ctx).await? {
+            Some(buffer) => buffer,
+            None => return not_found(),
+        };
+
+        html(LogsTemplate {
+            service: ctx.param("service")?,
+            lines: buffer.since(0, crate::logs::CAPACITY),
+        })
+    }
+}
+
+/// Live output of the application as Server-Sent Events
+///
+/// Each event carries single line, with its sequence number as the event ID, so reconnecting
+/// clients continue where they stopped. Stream ends when the server is stopping, so it does not
+/// hold up the shutdown.
+pub struct LogStream;
+
+#[async_trait]
+impl super::Handler for LogStream {
+    async fn handle(
+        self: Arc<Self>,
+        req: Request<Body>,
+        ctx: super::Context,
+    ) -> Result<Response<Body>> {
+        let buffer = match logs(&ctx).await? {
+            Some(buffer) => buffer,
+            None => return not_found(),
+        };
+
+        let last_event = req
+            .headers()
+            .get("last-event-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let since = last_event
+            .or_else(|| {
+                form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+                    .find(|(key, _)| key == "since")
+                    .map(|(_, value)| value.into_owned())
+            })
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+
+        // Subscribe before reading the backlog, so no line is missed in between
+        let mut updates = buffer.subscribe();
+        let backlog = buffer.since(since, crate::logs::CAPACITY);
+        // Stream ends once the service is gone, so it must not keep the buffer alive
+        let buffer = Arc::downgrade(&buffer);
+
+        let body = Body::wrap_stream(async_stream::stream! {
+            let mut last = since;
+            for line in backlog {
+                last = line.seq;
+                yield Ok::<_, std::convert::Infallible>(event(&line));
+            }
+
+            // Connection cannot be closed gracefully while streaming, so end with the server
+            let stopping = crate::shutdown::stopping();
+            tokio::pin!(stopping);
+            loop {
+                let update = tokio::select! {
+                    update = updates.recv() => update,
+                    () = &mut stopping => break,
+                };
+                let lines = match update {
+                    Ok(line) => vec![line],
+                    // Too slow to keep up, so catch up from the buffer instead
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                        match buffer.upgrade() {
+                            Some(buffer) => buffer.since(last, crate::logs::CAPACITY),
+                            None => break,
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                for line in lines {
+                    if line.seq <= last {
+                        continue;
+                    }
+                    last = line.seq;
+                    yield Ok(event(&line));
+                }
+            }
+        });
+
+        Ok(Response::builder()
+            .header("content-type", "text/event-stream")
+            .header("cache-control", "no-cache")
+            .body(body)?)
+    }
+}
+
+fn event(line: &crate::logs::Line) -> String {
+    // Carriage return would end the data field early
+    let data: String = line
+        .text
+        .split('\r')
+        .map(|part| format!("data: {}\n", part))
+        .collect();
+
+    format!("id: {}\nevent: {}\n{}\n", line.seq, line.stream, data)
+}
+
+pub(super) mod filters {
     #![allow(dead_code)]
 
     use hyper::Request;
@@ -56,10 +460,17 @@ mod filters {
     }
 
     pub fn domain_url<B>(domain: &str, req: &Request<B>) -> askama::Result<String> {
-        let port = match req.uri().port_u16() {
-            Some(p) if p != 443 => format!(":{p}"),
-            _ => "".into()
+        port_url(domain, &req.uri().port_u16().unwrap_or(443))
+    }
+
+    pub fn port_url(domain: &str, port: &u16) -> askama::Result<String> {
+        let port = match *port {
+            443 => "".into(),
+            p => format!(":{p}"),
         };
         Ok(format!("https://{domain}{port}"))
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::{Body, Request, StatusCode};
+
+use super::Replay;
+use crate::dashboard::{Context, Handler};
+
+fn context() -> Context {
+    let params = [("service", "foo.localhost"), ("id", "1")]
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+
+    Context {
+        registry: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+        params,
+        user: None,
+    }
+}
+
+async fn replay(headers: &[(&str, &str)]) -> StatusCode {
+    let mut req =
+        Request::post("https://dolores.localhost/services/foo.localhost/requests/1/replay");
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req.body(Body::from("body=x")).unwrap();
+
+    Arc::new(Replay)
+        .handle(req, context())
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn replay_rejects_cross_origin_requests() {
+    let cross_origin: &[&[(&str, &str)]] = &[
+        &[("sec-fetch-site", "cross-site")],
+        &[("sec-fetch-site", "same-site")],
+        &[("origin", "https://evil.example")],
+        &[("origin", "http://dolores.localhost")],
+        &[("origin", "https://dolores.localhost.evil.example")],
+        &[("origin", "null")],
+        // Fetch metadata wins over the matching origin
+        &[
+            ("sec-fetch-site", "cross-site"),
+            ("origin", "https://dolores.localhost"),
+        ],
+    ];
+    for headers in cross_origin {
+        assert_eq!(
+            replay(headers).await,
+            StatusCode::FORBIDDEN,
+            "{:?}",
+            headers
+        );
+    }
+}
+
+#[tokio::test]
+async fn replay_accepts_requests_from_dashboard() {
+    // Passes the check and looks up the unknown service
+    let same_origin: &[&[(&str, &str)]] = &[
+        &[("sec-fetch-site", "same-origin")],
+        &[("origin", "https://dolores.localhost")],
+        &[],
+    ];
+    for headers in same_origin {
+        assert_eq!(
+            replay(headers).await,
+            StatusCode::NOT_FOUND,
+            "{:?}",
+            headers
+        );
+    }
+}
+
+#[tokio::test]
+async fn replay_accepts_only_post() {
+    let req = Request::get("https://dolores.localhost/services/foo.localhost/requests/1/replay")
+        .body(Body::empty())
+        .unwrap();
+    let resp = Arc::new(Replay).handle(req, context()).await.unwrap();
+
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()["allow"], "POST");
+}
+
+#[tokio::test]
+async fn traffic_of_services_is_shown_only_to_their_users() {
+    let mut service = crate::service::Service::new(
+        "foo.localhost",
+        "[::1]:8000".parse().unwrap(),
+        crate::proxy::Type::Http,
+        &crate::proxy::Options {
+            capture: true,
+            ..Default::default()
+        },
+    );
+    let server = nix::unistd::geteuid().as_raw();
+    service.owner = Some(server + 1000);
+    let mut ctx = context();
+    ctx.registry
+        .write()
+        .await
+        .insert("foo.localhost".into(), service);
+
+    for (user, status) in [
+        (Some(server + 1000), StatusCode::OK),
+        (Some(server), StatusCode::OK),
+        (Some(server + 1001), StatusCode::NOT_FOUND),
+        (None, StatusCode::NOT_FOUND),
+    ] {
+        ctx.user = user;
+        let req = Request::get("https://dolores.localhost/services/foo.localhost/requests.har")
+            .body(Body::empty())
+            .unwrap();
+        let response = Arc::new(super::Har).handle(req, ctx.clone()).await.unwrap();
+        assert_eq!(response.status(), status, "{:?}", user);
+    }
+}
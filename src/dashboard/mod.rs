@@ -1,28 +1,40 @@
 use color_eyre::eyre::Result;
 use hyper::server::conn::Http;
 use hyper::service::service_fn;
-use hyper::{Body, Request, Response, StatusCode};
+use hyper::{Body, Request, Response};
 
-use std::sync::Arc;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::registry::RegistryStore;
 
+mod errors;
 mod handlers;
+mod peer;
+
+pub use errors::{bad_gateway, bad_gateway_response, unknown_service, waiting_response};
 
 #[async_trait]
 trait Handler: Send + Sync {
     async fn handle(self: Arc<Self>, req: Request<Body>, ctx: Context) -> Result<Response<Body>>;
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
-pub struct Data {
-    registry: HashMap<String, crate::service::Service>
-}
-
 #[derive(Clone)]
 pub struct Context {
     registry: RegistryStore,
+    /// Parameters captured from the route path
+    params: HashMap<String, String>,
+    /// Local user which connected, if known
+    user: Option<u32>,
+}
+
+impl Context {
+    fn param(&self, name: &str) -> Result<&str> {
+        self.params
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| color_eyre::eyre::eyre!("Missing route parameter {name}"))
+    }
 }
 
 pub struct Server {
@@ -38,27 +50,78 @@ impl Server {
 
         router.insert("/", Arc::new(handlers::Home)).unwrap();
         router.insert("/health", Arc::new(handlers::Health)).unwrap();
+        router.insert("/metrics", Arc::new(handlers::Metrics)).unwrap();
+        router
+            .insert("/services/:service/requests", Arc::new(handlers::Requests))
+            .unwrap();
+        router
+            .insert("/services/:service/requests.har", Arc::new(handlers::Har))
+            .unwrap();
+        router
+            .insert(
+                "/services/:service/metrics",
+                Arc::new(handlers::ServiceMetrics),
+            )
+            .unwrap();
+        router
+            .insert("/services/:service/logs", Arc::new(handlers::Logs))
+            .unwrap();
+        router
+            .insert(
+                "/services/:service/logs/stream",
+                Arc::new(handlers::LogStream),
+            )
+            .unwrap();
+        router
+            .insert(
+                "/services/:service/requests/:id",
+                Arc::new(handlers::RequestDetails),
+            )
+            .unwrap();
+        router
+            .insert(
+                "/services/:service/requests/:id/replay",
+                Arc::new(handlers::Replay),
+            )
+            .unwrap();
 
         Server { acceptor, registry, router: Arc::new(router) }
     }
 
     pub async fn handle(&self, stream: tokio::net::TcpStream) -> std::io::Result<()> {
+        let user = peer::user(&stream);
+        tracing::debug!(?user, "Dashboard user");
         let tls_stream = self.acceptor.accept(stream).await?;
 
         let service_fn = service_fn(move |req| {
             let req = add_host(req);
             tracing::info!(?req);
+            let router = self.router.clone();
+            let (handler, params) = match router.at(req.uri().path()) {
+                Ok(route) => {
+                    let params = route
+                        .params
+                        .iter()
+                        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                        .collect();
+                    (route.value.clone(), params)
+                }
+                Err(_) => (
+                    Arc::new(handlers::NotFound) as Arc<dyn Handler>,
+                    HashMap::new(),
+                ),
+            };
             let ctx = Context {
                 registry: self.registry.clone(),
+                params,
+                user,
             };
-            let router = self.router.clone();
-            let route = router.at(req.uri().path()).unwrap();
-            Handler::handle(route.value.clone(), req, ctx)
+            Handler::handle(handler, req, ctx)
         });
 
-        if let Err(http_err) = Http::new()
-            .serve_connection(tls_stream, service_fn)
-            .await
+        let connection = Http::new().serve_connection(tls_stream, service_fn);
+        if let Err(http_err) =
+            crate::shutdown::graceful(connection, |connection| connection.graceful_shutdown()).await
         {
             tracing::error!("Error while serving HTTP connection: {}", http_err);
         }
@@ -0,0 +1,56 @@
+//! Local user connected to the dashboard, found in the TCP socket tables of the kernel
+
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::TcpStream;
+
+const TABLES: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+
+/// User owning the client side of the connection, when the client runs on this machine
+pub fn user(stream: &TcpStream) -> Option<u32> {
+    let local = canonical(stream.local_addr().ok()?);
+    let peer = canonical(stream.peer_addr().ok()?);
+
+    TABLES.iter().find_map(|path| {
+        let table = std::fs::read_to_string(path).ok()?;
+        owner(&table, peer, local)
+    })
+}
+
+/// Owner of the socket connected from `local` to `remote`, as listed in the table
+fn owner(table: &str, local: SocketAddr, remote: SocketAddr) -> Option<u32> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (from, to, uid) = (fields.get(1)?, fields.get(2)?, fields.get(7)?);
+
+        (address(from)? == local && address(to)? == remote)
+            .then(|| uid.parse().ok())
+            .flatten()
+    })
+}
+
+/// Address as written in the table, IP as words in the byte order of this machine, port in hex
+fn address(field: &str) -> Option<SocketAddr> {
+    let (ip, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = Vec::with_capacity(16);
+    for start in (0..ip.len()).step_by(8) {
+        let word = u32::from_str_radix(ip.get(start..start + 8)?, 16).ok()?;
+        bytes.extend(word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?),
+        _ => return None,
+    };
+
+    Some(canonical((ip, port).into()))
+}
+
+/// IPv4 clients of dual-stack listeners have mapped addresses on the server side only
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+#[cfg(test)]
+mod tests;
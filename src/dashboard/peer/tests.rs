@@ -0,0 +1,68 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{address, owner, user};
+
+fn ipv4(ip: Ipv4Addr, port: u16) -> String {
+    format!("{:08X}:{:04X}", u32::from_ne_bytes(ip.octets()), port)
+}
+
+fn ipv6(ip: Ipv6Addr, port: u16) -> String {
+    let words: String = ip
+        .octets()
+        .chunks(4)
+        .map(|word| format!("{:08X}", u32::from_ne_bytes(word.try_into().unwrap())))
+        .collect();
+    format!("{}:{:04X}", words, port)
+}
+
+#[test]
+fn addresses_are_parsed_in_native_byte_order() {
+    let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    assert_eq!(address(&ipv4(Ipv4Addr::LOCALHOST, 8080)), Some(addr));
+
+    let addr: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+    assert_eq!(
+        address(&ipv6("2001:db8::1".parse().unwrap(), 443)),
+        Some(addr)
+    );
+
+    // Mapped addresses are compared as IPv4 ones
+    let mapped = Ipv4Addr::LOCALHOST.to_ipv6_mapped();
+    assert_eq!(
+        address(&ipv6(mapped, 443)),
+        Some("127.0.0.1:443".parse().unwrap())
+    );
+
+    assert_eq!(address("0100007F"), None);
+    assert_eq!(address("0100007:1F90"), None);
+}
+
+#[test]
+fn owner_of_client_side_is_found() {
+    let client = ipv4(Ipv4Addr::LOCALHOST, 50000);
+    let server = ipv4(Ipv4Addr::LOCALHOST, 443);
+    let table = format!(
+        "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n\
+         0: {server} {client} 01 00000000:00000000 00:00000000 00000000     0        0 1 1\n\
+         1: {client} {server} 01 00000000:00000000 00:00000000 00000000  1000        0 2 1\n"
+    );
+    let client: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+    let server: SocketAddr = "127.0.0.1:443".parse().unwrap();
+
+    assert_eq!(owner(&table, client, server), Some(1000));
+    assert_eq!(owner(&table, server, client), Some(0));
+    assert_eq!(owner(&table, client, "127.0.0.1:80".parse().unwrap()), None);
+}
+
+#[tokio::test]
+async fn user_of_loopback_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+
+    assert_eq!(user(&stream), Some(nix::unistd::geteuid().as_raw()));
+}
@@ -0,0 +1,289 @@
+//! Minimal authoritative DNS server for the domain of the services
+//!
+//! Only `.localhost` resolves to loopback out of the box, so other domains need a resolver that
+//! knows about them. The server answers A and AAAA queries for the domain itself and for the
+//! registered services (including their subdomains), responds with NXDOMAIN for other names in the
+//! domain and refuses queries for names outside of it, as it is not meant to be a recursive one.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::registry::RegistryStore;
+
+/// Time for which answers can be cached, kept short as services come and go
+const TTL: u32 = 5;
+
+/// Maximal size of the response sent over UDP, without EDNS
+const UDP_SIZE: usize = 512;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Rcode {
+    NoError = 0,
+    FormErr = 1,
+    NxDomain = 3,
+    NotImp = 4,
+    Refused = 5,
+}
+
+/// Addresses returned for loopback, when no other ones are configured
+pub fn loopback() -> Vec<IpAddr> {
+    vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()]
+}
+
+#[derive(Debug)]
+pub struct Server {
+    domain: String,
+    /// Addresses returned for all known names
+    addrs: Vec<IpAddr>,
+    services: RegistryStore,
+}
+
+impl Server {
+    pub fn new(domain: &str, addrs: Vec<IpAddr>, services: RegistryStore) -> Self {
+        Server {
+            domain: domain.trim_end_matches('.').to_ascii_lowercase(),
+            addrs,
+            services,
+        }
+    }
+
+    /// Listen on both UDP and TCP at given address
+    pub async fn listen(self: Arc<Self>, addr: SocketAddr) -> std::io::Result<()> {
+        let udp = UdpSocket::bind(addr).await?;
+        let tcp = TcpListener::bind(addr).await?;
+        tracing::info!(%addr, domain = %self.domain, "DNS");
+
+        tokio::spawn(self.clone().serve_udp(udp));
+        tokio::spawn(self.serve_tcp(tcp));
+
+        Ok(())
+    }
+
+    async fn serve_udp(self: Arc<Self>, socket: UdpSocket) {
+        let mut buf = [0; 4096];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(err) => {
+                    tracing::warn!(%err, "Cannot receive DNS query");
+                    continue;
+                }
+            };
+            if let Some(response) = self.respond(&buf[..len], UDP_SIZE).await {
+                if let Err(err) = socket.send_to(&response, from).await {
+                    tracing::debug!(%err, %from, "Cannot send DNS response");
+                }
+            }
+        }
+    }
+
+    async fn serve_tcp(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(self.clone().handle_tcp(stream));
+                }
+                Err(err) => tracing::warn!(%err, "Cannot accept DNS connection"),
+            }
+        }
+    }
+
+    /// Messages over TCP are prefixed by their length, multiple can be sent over one connection
+    async fn handle_tcp(self: Arc<Self>, mut stream: TcpStream) {
+        loop {
+            let len = match stream.read_u16().await {
+                Ok(len) => len as usize,
+                Err(_) => return,
+            };
+            let mut query = vec![0; len];
+            if stream.read_exact(&mut query).await.is_err() {
+                return;
+            }
+
+            let response = match self.respond(&query, u16::MAX.into()).await {
+                Some(response) => response,
+                None => return,
+            };
+            let written = async {
+                stream.write_u16(response.len() as u16).await?;
+                stream.write_all(&response).await
+            };
+            if written.await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Response to the query, nothing when it is not even possible to tell who asked
+    ///
+    /// Responses over `max_len` have no answers and are marked as truncated, so the client retries
+    /// over TCP.
+    async fn respond(&self, query: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        // Never answer responses, which could start a loop with another server
+        if query.get(2).is_none_or(|flags| flags & 0x80 != 0) {
+            return None;
+        }
+
+        let question = match parse_query(query) {
+            Ok(question) => question,
+            Err(rcode) => return query.get(..12).map(|header| error(header, rcode)),
+        };
+        let name = question.name.trim_end_matches('.').to_ascii_lowercase();
+
+        let rcode = if !self.in_domain(&name) {
+            Rcode::Refused
+        } else if self.exists(&name).await {
+            Rcode::NoError
+        } else {
+            Rcode::NxDomain
+        };
+        tracing::debug!(%name, qtype = question.qtype, ?rcode, "DNS query");
+
+        let answers: Vec<IpAddr> = match (rcode, question.qclass, question.qtype) {
+            (Rcode::NoError, CLASS_IN, TYPE_A) => self
+                .addrs
+                .iter()
+                .filter(|addr| addr.is_ipv4())
+                .copied()
+                .collect(),
+            (Rcode::NoError, CLASS_IN, TYPE_AAAA) => self
+                .addrs
+                .iter()
+                .filter(|addr| addr.is_ipv6())
+                .copied()
+                .collect(),
+            // Name exists, but there are no records of other types
+            _ => Vec::new(),
+        };
+
+        let mut out = response(query, &question, rcode, &answers);
+        if out.len() > max_len {
+            out = response(query, &question, rcode, &[]);
+            out[2] |= 0x02;
+        }
+
+        Some(out)
+    }
+
+    fn in_domain(&self, name: &str) -> bool {
+        name == self.domain
+            || name
+                .strip_suffix(&self.domain)
+                .is_some_and(|rest| rest.ends_with('.'))
+    }
+
+    /// Whether the name is the domain itself, or belongs to the registered service
+    async fn exists(&self, name: &str) -> bool {
+        if name == self.domain {
+            return true;
+        }
+
+        // Subdomains are routed to the service, same as in the SNI
+        let rest = &name[..name.len() - self.domain.len() - 1];
+        let service = rest.rsplit('.').next().unwrap_or(rest);
+        let service = format!("{}.{}", service, self.domain);
+
+        self.services.read().await.contains_key(&service)
+    }
+}
+
+#[derive(Debug)]
+struct Question {
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    /// End of the question section in the query
+    end: usize,
+}
+
+fn parse_query(query: &[u8]) -> Result<Question, Rcode> {
+    if query.len() < 12 {
+        return Err(Rcode::FormErr);
+    }
+    let opcode = (query[2] >> 3) & 0x0f;
+    if opcode != 0 {
+        return Err(Rcode::NotImp);
+    }
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    if questions != 1 {
+        return Err(Rcode::FormErr);
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *query.get(pos).ok_or(Rcode::FormErr)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers make no sense in the first name of the message
+        if len > 63 {
+            return Err(Rcode::FormErr);
+        }
+        let label = query.get(pos..pos + len).ok_or(Rcode::FormErr)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += len;
+    }
+
+    let fixed = query.get(pos..pos + 4).ok_or(Rcode::FormErr)?;
+
+    Ok(Question {
+        name: labels.join("."),
+        qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+        qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+        end: pos + 4,
+    })
+}
+
+/// Response with the question from the query and records for the given addresses
+fn response(query: &[u8], question: &Question, rcode: Rcode, answers: &[IpAddr]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(question.end + answers.len() * 28);
+    out.extend_from_slice(&query[..2]);
+    // Response, authoritative for the domain, with recursion desired flag copied from the query
+    let authoritative = if rcode == Rcode::Refused { 0 } else { 0x04 };
+    out.push(0x80 | authoritative | (query[2] & 0x01));
+    out.push(rcode as u8);
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    // Question is echoed as received, to keep the case used by the client
+    out.extend_from_slice(&query[12..question.end]);
+
+    for addr in answers {
+        // Pointer to the name in the question
+        out.extend_from_slice(&[0xc0, 12]);
+        let (kind, data) = match addr {
+            IpAddr::V4(addr) => (TYPE_A, addr.octets().to_vec()),
+            IpAddr::V6(addr) => (TYPE_AAAA, addr.octets().to_vec()),
+        };
+        out.extend_from_slice(&kind.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&TTL.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+
+    out
+}
+
+/// Response without any sections, for queries that cannot be understood
+fn error(header: &[u8], rcode: Rcode) -> Vec<u8> {
+    let mut out = vec![0; 12];
+    out[..2].copy_from_slice(&header[..2]);
+    out[2] = 0x80 | (header[2] & 0x79);
+    out[3] = rcode as u8;
+
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use super::{Rcode, Server, CLASS_IN, TTL, TYPE_A, TYPE_AAAA, UDP_SIZE};
+use crate::service::Service;
+
+/// Server for `test` with the `app` service registered
+fn server(addrs: Vec<IpAddr>) -> Server {
+    let service = Service::new(
+        "app.test",
+        "[::1]:8000".parse().unwrap(),
+        crate::proxy::Type::Passthrough,
+        &Default::default(),
+    );
+    let services = HashMap::from([("app.test".to_string(), service)]);
+
+    Server::new("Test.", addrs, Arc::new(tokio::sync::RwLock::new(services)))
+}
+
+fn query(name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = vec![0x12, 0x34, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    query
+}
+
+fn rcode(response: &[u8]) -> u8 {
+    response[3] & 0x0f
+}
+
+fn answers(response: &[u8]) -> u16 {
+    u16::from_be_bytes([response[6], response[7]])
+}
+
+/// Addresses in the answers, which follow the question of the query
+fn addresses(query: &[u8], response: &[u8]) -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+    let mut pos = query.len();
+    for _ in 0..answers(response) {
+        // Name is compressed, pointing to the question
+        assert_eq!(&response[pos..pos + 2], &[0xc0, 12]);
+        let kind = u16::from_be_bytes([response[pos + 2], response[pos + 3]]);
+        let class = u16::from_be_bytes([response[pos + 4], response[pos + 5]]);
+        assert_eq!(class, CLASS_IN);
+        let ttl = u32::from_be_bytes(response[pos + 6..pos + 10].try_into().unwrap());
+        assert_eq!(ttl, TTL);
+        let len = u16::from_be_bytes([response[pos + 10], response[pos + 11]]) as usize;
+        let data = &response[pos + 12..pos + 12 + len];
+        addrs.push(match kind {
+            TYPE_A => IpAddr::from(<[u8; 4]>::try_from(data).unwrap()),
+            TYPE_AAAA => IpAddr::from(<[u8; 16]>::try_from(data).unwrap()),
+            kind => panic!("unexpected record type {}", kind),
+        });
+        pos += 12 + len;
+    }
+    assert_eq!(pos, response.len());
+
+    addrs
+}
+
+#[tokio::test]
+async fn registered_service_has_addresses() {
+    let server = server(super::loopback());
+
+    let query_a = query("app.test", TYPE_A);
+    let response = server.respond(&query_a, UDP_SIZE).await.unwrap();
+    assert_eq!(&response[..2], &[0x12, 0x34]);
+    // Response, authoritative, recursion desired copied
+    assert_eq!(response[2], 0x85);
+    assert_eq!(rcode(&response), Rcode::NoError as u8);
+    assert_eq!(
+        addresses(&query_a, &response),
+        [IpAddr::from(Ipv4Addr::LOCALHOST)]
+    );
+
+    let query_aaaa = query("app.test", TYPE_AAAA);
+    let response = server.respond(&query_aaaa, UDP_SIZE).await.unwrap();
+    assert_eq!(
+        addresses(&query_aaaa, &response),
+        [IpAddr::from(Ipv6Addr::LOCALHOST)]
+    );
+}
+
+#[tokio::test]
+async fn names_are_matched_as_in_sni() {
+    let server = server(super::loopback());
+
+    for name in ["test", "app.test", "api.app.test", "APP.Test."] {
+        let query = query(name, TYPE_A);
+        let response = server.respond(&query, UDP_SIZE).await.unwrap();
+        assert_eq!(rcode(&response), Rcode::NoError as u8, "{}", name);
+        assert_eq!(answers(&response), 1, "{}", name);
+        // Question is echoed with the case used by the client
+        assert_eq!(&response[12..query.len()], &query[12..], "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn unknown_names_do_not_exist() {
+    let server = server(super::loopback());
+
+    for name in ["web.test", "app.web.test", "apptest.test"] {
+        let response = server
+            .respond(&query(name, TYPE_A), UDP_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(rcode(&response), Rcode::NxDomain as u8, "{}", name);
+        assert_eq!(answers(&response), 0, "{}", name);
+        assert_eq!(response[2] & 0x04, 0x04, "authoritative for {}", name);
+    }
+}
+
+#[tokio::test]
+async fn names_outside_of_domain_are_refused() {
+    let server = server(super::loopback());
+
+    for name in [
+        "example.com",
+        "apptest",
+        "app.test.example.com",
+        "mytest",
+        "",
+    ] {
+        let response = server
+            .respond(&query(name, TYPE_A), UDP_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(rcode(&response), Rcode::Refused as u8, "{:?}", name);
+        assert_eq!(answers(&response), 0, "{:?}", name);
+        assert_eq!(response[2] & 0x04, 0, "not authoritative for {:?}", name);
+    }
+}
+
+#[tokio::test]
+async fn other_types_have_no_records() {
+    let server = server(super::loopback());
+
+    // MX
+    let response = server
+        .respond(&query("app.test", 15), UDP_SIZE)
+        .await
+        .unwrap();
+    assert_eq!(rcode(&response), Rcode::NoError as u8);
+    assert_eq!(answers(&response), 0);
+
+    // Only IPv4 is configured
+    let server = self::server(vec![Ipv4Addr::new(192, 168, 1, 2).into()]);
+    let response = server
+        .respond(&query("app.test", TYPE_AAAA), UDP_SIZE)
+        .await
+        .unwrap();
+    assert_eq!(rcode(&response), Rcode::NoError as u8);
+    assert_eq!(answers(&response), 0);
+}
+
+#[tokio::test]
+async fn large_responses_are_truncated() {
+    let addrs: Vec<IpAddr> = (0..40)
+        .map(|index| Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, index).into())
+        .collect();
+    let server = server(addrs);
+    let query = query("app.test", TYPE_AAAA);
+
+    let response = server.respond(&query, UDP_SIZE).await.unwrap();
+    assert_eq!(response[2] & 0x02, 0x02);
+    assert_eq!(answers(&response), 0);
+    assert_eq!(response.len(), query.len());
+
+    // Over TCP all fit
+    let response = server.respond(&query, u16::MAX.into()).await.unwrap();
+    assert_eq!(response[2] & 0x02, 0);
+    assert_eq!(addresses(&query, &response).len(), 40);
+}
+
+#[tokio::test]
+async fn compressed_names_in_query_are_rejected() {
+    let server = server(super::loopback());
+    let mut query = query("app.test", TYPE_A);
+    // Name replaced by the pointer to itself
+    query.splice(12..22, [0xc0, 12]);
+
+    let response = server.respond(&query, UDP_SIZE).await.unwrap();
+    assert_eq!(rcode(&response), Rcode::FormErr as u8);
+    assert_eq!(response.len(), 12);
+    assert_eq!(answers(&response), 0);
+}
+
+#[tokio::test]
+async fn malformed_queries_get_errors() {
+    let server = server(super::loopback());
+    let valid = query("app.test", TYPE_A);
+
+    // More questions than one
+    let mut query = valid.clone();
+    query[5] = 2;
+    let response = server.respond(&query, UDP_SIZE).await.unwrap();
+    assert_eq!(rcode(&response), Rcode::FormErr as u8);
+
+    // Other opcode than a standard query
+    let mut query = valid.clone();
+    query[2] |= 2 << 3;
+    let response = server.respond(&query, UDP_SIZE).await.unwrap();
+    assert_eq!(rcode(&response), Rcode::NotImp as u8);
+    // Opcode and recursion desired are kept
+    assert_eq!(response[2], 0x80 | (2 << 3) | 0x01);
+}
+
+#[tokio::test]
+async fn responses_are_not_answered() {
+    let server = server(super::loopback());
+    let mut query = query("app.test", TYPE_A);
+    query[2] |= 0x80;
+
+    assert!(server.respond(&query, UDP_SIZE).await.is_none());
+}
+
+#[tokio::test]
+async fn truncated_queries_do_not_panic() {
+    let server = server(super::loopback());
+    let valid = query("app.test", TYPE_A);
+
+    for len in 0..valid.len() {
+        let response = server.respond(&valid[..len], UDP_SIZE).await;
+        match len {
+            // Without the whole header there is nothing to respond to
+            0..=11 => assert!(response.is_none(), "{}", len),
+            _ => assert_eq!(rcode(&response.unwrap()), Rcode::FormErr as u8, "{}", len),
+        }
+    }
+}
+
+#[tokio::test]
+async fn random_queries_do_not_panic() {
+    let server = server(super::loopback());
+    let valid = query("api.app.test", TYPE_A);
+
+    for _ in 0..10_000 {
+        let mut query = valid.clone();
+        // Some bytes replaced, possibly with label lengths pointing past the end
+        for _ in 0..rand::random::<usize>() % 4 + 1 {
+            let index = rand::random::<usize>() % query.len();
+            query[index] = rand::random();
+        }
+        query.truncate(rand::random::<usize>() % (query.len() + 1));
+
+        if let Some(response) = server.respond(&query, UDP_SIZE).await {
+            assert!(response.len() >= 12);
+            assert_eq!(&response[..2], &query[..2]);
+        }
+    }
+}
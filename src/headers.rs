@@ -0,0 +1,78 @@
+//! Rules adjusting headers of the requests and responses passed through the HTTP-aware proxies
+//!
+//! Rules come from the server configuration and can be replaced while the server is running, new
+//! ones apply to the next request, even on already open connections.
+
+use std::sync::{Arc, RwLock};
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use once_cell::sync::Lazy;
+
+static RULES: Lazy<RwLock<Arc<Vec<Rule>>>> = Lazy::new(Default::default);
+
+/// Headers to set and remove in one direction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Edit {
+    /// Headers to set, replacing all headers of the same name
+    pub set: Vec<(HeaderName, HeaderValue)>,
+    /// Names of the headers to remove
+    pub remove: Vec<HeaderName>,
+}
+
+impl Edit {
+    fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name, value.clone());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rule {
+    /// Domain of the service to which the rule applies, all services when missing
+    pub domain: Option<String>,
+    pub request: Edit,
+    pub response: Edit,
+}
+
+impl Rule {
+    fn matches(&self, domain: &str) -> bool {
+        self.domain.as_deref().is_none_or(|own| own == domain)
+    }
+}
+
+/// Whether some rule applies only to the service, rules for all services are not counted
+pub fn targets(domain: &str) -> bool {
+    rules()
+        .iter()
+        .any(|rule| rule.domain.as_deref() == Some(domain))
+}
+
+/// Replace all rules
+pub fn set(rules: Vec<Rule>) {
+    *RULES.write().unwrap() = Arc::new(rules);
+}
+
+fn rules() -> Arc<Vec<Rule>> {
+    RULES.read().unwrap().clone()
+}
+
+/// Adjust headers of the request passed to the service, in order of the rules
+pub fn request(domain: &str, headers: &mut HeaderMap) {
+    for rule in rules().iter().filter(|rule| rule.matches(domain)) {
+        rule.request.apply(headers);
+    }
+}
+
+/// Adjust headers of the response of the service passed to the client, in order of the rules
+pub fn response(domain: &str, headers: &mut HeaderMap) {
+    for rule in rules().iter().filter(|rule| rule.matches(domain)) {
+        rule.response.apply(headers);
+    }
+}
+
+#[cfg(test)]
+mod tests;
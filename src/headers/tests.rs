@@ -0,0 +1,112 @@
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+use super::{Edit, Rule};
+
+fn name(name: &'static str) -> HeaderName {
+    HeaderName::from_static(name)
+}
+
+fn value(value: &'static str) -> HeaderValue {
+    HeaderValue::from_static(value)
+}
+
+fn edit(set: &[(&'static str, &'static str)], remove: &[&'static str]) -> Edit {
+    Edit {
+        set: set.iter().map(|&(n, v)| (name(n), value(v))).collect(),
+        remove: remove.iter().copied().map(name).collect(),
+    }
+}
+
+fn values<'a>(headers: &'a HeaderMap, header: &str) -> Vec<&'a str> {
+    headers
+        .get_all(header)
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn set_replaces_all_values() {
+    let mut headers = HeaderMap::new();
+    headers.append("x-tag", value("a"));
+    headers.append("x-tag", value("b"));
+
+    edit(&[("x-tag", "c")], &[]).apply(&mut headers);
+
+    assert_eq!(values(&headers, "x-tag"), ["c"]);
+}
+
+#[test]
+fn remove_applies_before_set() {
+    let mut headers = HeaderMap::new();
+    headers.insert("server", value("app"));
+    headers.insert("x-powered-by", value("framework"));
+
+    // Header both removed and set ends up with the new value
+    edit(&[("server", "dolores")], &["server", "x-powered-by"]).apply(&mut headers);
+
+    assert_eq!(values(&headers, "server"), ["dolores"]);
+    assert!(!headers.contains_key("x-powered-by"));
+}
+
+#[test]
+fn other_headers_are_kept() {
+    let mut headers = HeaderMap::new();
+    headers.insert("accept", value("*/*"));
+
+    edit(&[("x-environment", "development")], &["server"]).apply(&mut headers);
+
+    assert_eq!(values(&headers, "accept"), ["*/*"]);
+    assert_eq!(values(&headers, "x-environment"), ["development"]);
+}
+
+#[test]
+fn rules_match_their_service_or_all() {
+    let all = Rule::default();
+    let api = Rule {
+        domain: Some("api.test".into()),
+        ..Default::default()
+    };
+
+    assert!(all.matches("web.test"));
+    assert!(api.matches("api.test"));
+    assert!(!api.matches("web.test"));
+    assert!(!api.matches("v2.api.test"));
+}
+
+#[test]
+fn rules_apply_in_order() {
+    // Only rules for this domain, so that proxies tested at the same time are not affected
+    super::set(vec![
+        Rule {
+            domain: Some("ordered.headers.test".into()),
+            request: edit(&[("x-stage", "first"), ("x-first", "yes")], &[]),
+            response: edit(&[], &["server"]),
+        },
+        Rule {
+            domain: Some("ordered.headers.test".into()),
+            request: edit(&[("x-stage", "second")], &["x-first"]),
+            response: edit(&[("server", "dolores")], &[]),
+        },
+        Rule {
+            domain: Some("other.headers.test".into()),
+            request: edit(&[("x-stage", "other")], &[]),
+            response: Edit::default(),
+        },
+    ]);
+
+    let mut request = HeaderMap::new();
+    super::request("ordered.headers.test", &mut request);
+    assert_eq!(values(&request, "x-stage"), ["second"]);
+    assert!(!request.contains_key("x-first"));
+
+    let mut response = HeaderMap::new();
+    response.insert("server", value("app"));
+    super::response("ordered.headers.test", &mut response);
+    assert_eq!(values(&response, "server"), ["dolores"]);
+
+    assert!(super::targets("ordered.headers.test"));
+    assert!(!super::targets("headers.test"));
+
+    super::set(Vec::new());
+}
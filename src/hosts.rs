@@ -0,0 +1,168 @@
+//! Synchronisation of the registered services into the hosts file
+//!
+//! Alternative to the DNS server for machines where running one is not possible. Entries are kept
+//! in a block delimited by marker comments, so the rest of the file is left untouched, and the
+//! block is removed once the server stops. Hosts file has no wildcards, so only the services
+//! themselves resolve, not their subdomains.
+
+use std::fs;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::registry::RegistryStore;
+
+pub const BEGIN: &str = "# BEGIN dolores";
+pub const END: &str = "# END dolores";
+
+/// Hosts file with the block managed by the server, removed when dropped
+#[derive(Debug)]
+pub struct HostsFile {
+    path: PathBuf,
+    domain: String,
+    addrs: Vec<IpAddr>,
+    /// Names written in the last update, to skip rewriting the file when nothing changed
+    synced: Mutex<Option<Vec<String>>>,
+}
+
+impl HostsFile {
+    pub fn new(path: &Path, domain: &str, addrs: Vec<IpAddr>) -> Self {
+        HostsFile {
+            path: path.into(),
+            domain: domain.into(),
+            addrs,
+            synced: Mutex::new(None),
+        }
+    }
+
+    /// Write entries for the domain and all registered services, when they changed
+    pub async fn sync(&self, services: &RegistryStore) -> io::Result<()> {
+        let mut names: Vec<String> = services.read().await.keys().cloned().collect();
+        names.sort();
+        names.insert(0, self.domain.clone());
+
+        let mut synced = self.synced.lock().unwrap();
+        if synced.as_ref() == Some(&names) {
+            return Ok(());
+        }
+
+        let entries: Vec<_> = names
+            .iter()
+            .flat_map(|name| self.addrs.iter().map(move |addr| (*addr, name.as_str())))
+            .collect();
+        self.rewrite(&entries)?;
+        tracing::debug!(path = ?self.path, ?names, "Hosts file updated");
+        *synced = Some(names);
+
+        Ok(())
+    }
+
+    /// Remove the managed block from the file
+    pub fn clear(&self) -> io::Result<()> {
+        self.rewrite(&[])?;
+        *self.synced.lock().unwrap() = None;
+
+        Ok(())
+    }
+
+    fn rewrite(&self, entries: &[(IpAddr, &str)]) -> io::Result<()> {
+        let current = match fs::read_to_string(&self.path) {
+            Ok(current) => current,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        replace(&self.path, &update(&current, entries))
+    }
+}
+
+impl Drop for HostsFile {
+    fn drop(&mut self) {
+        if let Err(err) = self.clear() {
+            tracing::warn!(%err, path = ?self.path, "Cannot clean up hosts file");
+        }
+    }
+}
+
+/// Content of the hosts file with the managed block replaced by the entries
+///
+/// Block is removed completely when there are no entries. Lines after a begin marker without
+/// the end one are kept, as they are not known to be managed by the server.
+pub fn update(hosts: &str, entries: &[(IpAddr, &str)]) -> String {
+    let mut out = String::with_capacity(hosts.len());
+    // Lines of the block, from its begin marker, until the end one is found
+    let mut block: Option<Vec<&str>> = None;
+    for line in hosts.lines() {
+        match (line.trim(), &mut block) {
+            (BEGIN, _) => {
+                for line in block.replace(vec![line]).into_iter().flatten() {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+            (END, Some(_)) => block = None,
+            (_, Some(block)) => block.push(line),
+            (_, None) => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    for line in block.into_iter().flatten() {
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    if !entries.is_empty() {
+        out.push_str(BEGIN);
+        out.push('\n');
+        for (addr, name) in entries {
+            // Anything else could add arbitrary lines to the file
+            assert!(
+                crate::service::is_valid_domain(name),
+                "invalid host name {:?}",
+                name
+            );
+            out.push_str(&format!("{}\t{}\n", addr, name));
+        }
+        out.push_str(END);
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Atomically replace the file, keeping its permissions
+fn replace(path: &Path, content: &str) -> io::Result<()> {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".dolores");
+    let temp = path.with_file_name(name);
+
+    let written = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, path)
+    })();
+
+    match written {
+        Ok(()) => Ok(()),
+        // Bind mounted files, like `/etc/hosts` in containers, cannot be replaced
+        Err(err) if err.raw_os_error() == Some(nix::errno::Errno::EBUSY as i32) => {
+            let _ = fs::remove_file(&temp);
+            fs::write(path, content)
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
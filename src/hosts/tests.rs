@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use super::{update, HostsFile};
+use crate::proxy::{Options, Type};
+use crate::registry::RegistryStore;
+use crate::service::Service;
+
+const ORIGINAL: &str = "127.0.0.1\tlocalhost\n::1\tlocalhost\n";
+
+fn registry(names: &[&str]) -> RegistryStore {
+    let addr = "[::1]:8000".parse().unwrap();
+    let services = names
+        .iter()
+        .map(|name| {
+            let service = Service::new(name, addr, Type::Passthrough, &Options::default());
+            (name.to_string(), service)
+        })
+        .collect::<HashMap<_, _>>();
+
+    Arc::new(tokio::sync::RwLock::new(services))
+}
+
+#[test]
+fn update_replaces_only_managed_block() {
+    let hosts = format!(
+        "{}# BEGIN dolores\n127.0.0.1\tstale.test\n# END dolores\n10.0.0.1\tother\n",
+        ORIGINAL
+    );
+    let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    assert_eq!(
+        update(&hosts, &[(addr, "foo.test")]),
+        format!(
+            "{}10.0.0.1\tother\n# BEGIN dolores\n127.0.0.1\tfoo.test\n# END dolores\n",
+            ORIGINAL
+        )
+    );
+    assert_eq!(
+        update(&hosts, &[]),
+        format!("{}10.0.0.1\tother\n", ORIGINAL)
+    );
+}
+
+#[test]
+fn update_keeps_lines_of_unterminated_block() {
+    let hosts = format!("{}# BEGIN dolores\n10.0.0.1\tother\n", ORIGINAL);
+    let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    let updated = update(&hosts, &[(addr, "foo.test")]);
+    assert_eq!(
+        updated,
+        format!(
+            "{}# BEGIN dolores\n10.0.0.1\tother\n# BEGIN dolores\n127.0.0.1\tfoo.test\n# END dolores\n",
+            ORIGINAL
+        )
+    );
+    assert_eq!(update(&updated, &[]), hosts);
+    assert_eq!(update(&hosts, &[]), hosts);
+}
+
+#[tokio::test]
+async fn services_are_synced_and_removed_on_drop() {
+    let path = std::env::temp_dir().join(format!("dolores-hosts-{:x}", rand::random::<u64>()));
+    std::fs::write(&path, ORIGINAL).unwrap();
+
+    let hosts = HostsFile::new(
+        &path,
+        "test",
+        vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+    );
+
+    hosts.sync(&registry(&["foo.test"])).await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!(
+            "{}# BEGIN dolores\n\
+            127.0.0.1\ttest\n::1\ttest\n\
+            127.0.0.1\tfoo.test\n::1\tfoo.test\n\
+            # END dolores\n",
+            ORIGINAL
+        )
+    );
+
+    hosts.sync(&registry(&[])).await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!(
+            "{}# BEGIN dolores\n127.0.0.1\ttest\n::1\ttest\n# END dolores\n",
+            ORIGINAL
+        )
+    );
+
+    drop(hosts);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), ORIGINAL);
+    // Temporary file used for the atomic rewrite is not left behind
+    let temp = path.with_file_name(format!(
+        ".{}.dolores",
+        path.file_name().unwrap().to_str().unwrap()
+    ));
+    assert!(!temp.exists());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "invalid host name")]
+fn update_rejects_names_adding_lines() {
+    let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    update(ORIGINAL, &[(addr, "foo.test\n10.0.0.1\tevil.example")]);
+}
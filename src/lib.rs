@@ -1,10 +1,23 @@
 #[macro_use]
 extern crate async_trait;
 
+pub mod access_log;
+pub mod activation;
+pub mod capture;
 pub mod cli;
+pub mod dns;
+pub mod headers;
+pub mod hosts;
+pub mod logs;
+pub mod metrics;
+pub mod plain;
+pub mod privileges;
 pub mod proxy;
 pub mod registry;
 pub mod service;
+pub mod share;
+pub mod shutdown;
+pub mod telemetry;
 
 mod dashboard;
 
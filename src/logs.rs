@@ -0,0 +1,123 @@
+//! Output of the applications, forwarded to the daemon by the runner
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use tokio::sync::broadcast;
+
+/// Amount of lines kept per service
+pub const CAPACITY: usize = 1000;
+
+/// Maximal length of the single line, longer ones are split
+pub const LINE_LIMIT: usize = 16 * 1024;
+
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl std::fmt::Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        })
+    }
+}
+
+/// Parts of the line that fit within the [`LINE_LIMIT`], empty line is kept as is
+pub fn split(line: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(line);
+    std::iter::from_fn(move || {
+        let line = rest.take()?;
+        let mut end = line.len().min(LINE_LIMIT);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (part, tail) = line.split_at(end);
+        rest = (!tail.is_empty()).then_some(tail);
+        Some(part)
+    })
+}
+
+/// Single line of the application output
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Line {
+    /// Sequence number, increasing across all services
+    pub seq: u64,
+    pub time: SystemTime,
+    pub stream: Stream,
+    pub text: String,
+}
+
+impl Line {
+    pub fn time(&self) -> humantime::Rfc3339Timestamp {
+        humantime::format_rfc3339_millis(self.time)
+    }
+}
+
+/// Ring buffer of the recent output of single service, with live updates
+#[derive(Debug)]
+pub struct Buffer {
+    lines: Mutex<VecDeque<Line>>,
+    capacity: usize,
+    updates: broadcast::Sender<Line>,
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::new(CAPACITY)
+    }
+}
+
+impl Buffer {
+    pub fn new(capacity: usize) -> Self {
+        Buffer {
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            updates: broadcast::channel(capacity).0,
+        }
+    }
+
+    pub fn push(&self, stream: Stream, text: String) {
+        let line = Line {
+            seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+            time: SystemTime::now(),
+            stream,
+            text,
+        };
+
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() >= self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line.clone());
+        // Nobody may be listening, which is fine
+        let _ = self.updates.send(line);
+    }
+
+    /// Lines after the one with given sequence number, at most `limit` most recent ones
+    pub fn since(&self, seq: u64, limit: usize) -> Vec<Line> {
+        let lines = self.lines.lock().unwrap();
+        let lines: Vec<_> = lines.iter().filter(|line| line.seq > seq).collect();
+
+        lines[lines.len().saturating_sub(limit)..]
+            .iter()
+            .map(|&line| line.clone())
+            .collect()
+    }
+
+    /// Receive lines pushed from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Line> {
+        self.updates.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,87 @@
+use super::{split, Buffer, Stream, LINE_LIMIT};
+
+fn texts(lines: &[super::Line]) -> Vec<&str> {
+    lines.iter().map(|line| line.text.as_str()).collect()
+}
+
+#[test]
+fn oldest_lines_are_dropped_over_capacity() {
+    let buffer = Buffer::new(3);
+    for index in 0..5 {
+        buffer.push(Stream::Stdout, format!("line {}", index));
+    }
+
+    assert_eq!(
+        texts(&buffer.since(0, usize::MAX)),
+        ["line 2", "line 3", "line 4"]
+    );
+}
+
+#[test]
+fn lines_are_returned_after_sequence_number() {
+    let buffer = Buffer::new(10);
+    buffer.push(Stream::Stdout, "first".into());
+    buffer.push(Stream::Stderr, "second".into());
+    buffer.push(Stream::Stdout, "third".into());
+
+    let lines = buffer.since(0, usize::MAX);
+    assert!(lines.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+    assert_eq!(lines[1].stream, Stream::Stderr);
+
+    assert_eq!(
+        texts(&buffer.since(lines[0].seq, usize::MAX)),
+        ["second", "third"]
+    );
+    assert!(buffer.since(lines[2].seq, usize::MAX).is_empty());
+}
+
+#[test]
+fn limit_keeps_most_recent_lines() {
+    let buffer = Buffer::new(10);
+    for index in 0..5 {
+        buffer.push(Stream::Stdout, index.to_string());
+    }
+
+    assert_eq!(texts(&buffer.since(0, 2)), ["3", "4"]);
+    assert!(buffer.since(0, 0).is_empty());
+}
+
+#[test]
+fn sequence_numbers_increase_across_buffers() {
+    let (one, other) = (Buffer::new(10), Buffer::new(10));
+    one.push(Stream::Stdout, "one".into());
+    other.push(Stream::Stdout, "other".into());
+
+    assert!(one.since(0, 1)[0].seq < other.since(0, 1)[0].seq);
+}
+
+#[tokio::test]
+async fn subscribers_receive_new_lines() {
+    let buffer = Buffer::new(10);
+    buffer.push(Stream::Stdout, "before".into());
+    let mut updates = buffer.subscribe();
+    buffer.push(Stream::Stderr, "after".into());
+
+    let line = updates.recv().await.unwrap();
+    assert_eq!(line.text, "after");
+    assert_eq!(line.stream, Stream::Stderr);
+    assert!(updates.try_recv().is_err());
+}
+
+#[test]
+fn long_lines_are_split_at_char_boundaries() {
+    assert_eq!(split("").collect::<Vec<_>>(), [""]);
+    assert_eq!(split("short").collect::<Vec<_>>(), ["short"]);
+
+    let exact = "a".repeat(LINE_LIMIT);
+    assert_eq!(split(&exact).collect::<Vec<_>>(), [exact.as_str()]);
+
+    // Multibyte character crossing the limit goes to the next part
+    let line = format!("{}ż{}", "a".repeat(LINE_LIMIT - 1), "b".repeat(LINE_LIMIT));
+    let parts: Vec<_> = split(&line).collect();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(parts[0].len(), LINE_LIMIT - 1);
+    assert!(parts[1].starts_with('ż'));
+    assert!(parts.iter().all(|part| part.len() <= LINE_LIMIT));
+    assert_eq!(parts.concat(), line);
+}
@@ -0,0 +1,269 @@
+//! Prometheus metrics of the proxy server
+//!
+//! Metrics related to the traffic are labelled by the domain of the service. Names come from the
+//! clients, so only registered services are labelled and their series are removed once they are
+//! deregistered, commands for other names are counted under [`OTHER`].
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use once_cell::sync::Lazy;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub mod federation;
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+/// Label of the commands which do not refer to a registered service
+pub const OTHER: &str = "other";
+
+fn register<T: Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+static CONNECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new("dolores_connections_total", "Connections accepted");
+    register(IntCounterVec::new(opts, &["service"]).unwrap())
+});
+
+static ACTIVE_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let opts = Opts::new("dolores_active_connections", "Connections currently open");
+    register(IntGaugeVec::new(opts, &["service"]).unwrap())
+});
+
+static RECEIVED_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new(
+        "dolores_received_bytes_total",
+        "Bytes received from clients, including TLS for passthrough services",
+    );
+    register(IntCounterVec::new(opts, &["service"]).unwrap())
+});
+
+static SENT_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new(
+        "dolores_sent_bytes_total",
+        "Bytes sent to clients, including TLS for passthrough services",
+    );
+    register(IntCounterVec::new(opts, &["service"]).unwrap())
+});
+
+static TLS_HANDSHAKE_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new(
+        "dolores_tls_handshake_failures_total",
+        "TLS handshakes with clients that failed",
+    );
+    register(IntCounterVec::new(opts, &["service"]).unwrap())
+});
+
+static BACKEND_CONNECT_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new(
+        "dolores_backend_connect_errors_total",
+        "Failed connections to the applications",
+    );
+    register(IntCounterVec::new(opts, &["service"]).unwrap())
+});
+
+static BACKEND_CONNECT_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    let opts = HistogramOpts::new(
+        "dolores_backend_connect_duration_seconds",
+        "Time needed to connect to the application, including waiting for it to start",
+    );
+    register(HistogramVec::new(opts, &["service"]).unwrap())
+});
+
+static REGISTERED_SERVICES: Lazy<IntGauge> = Lazy::new(|| {
+    let opts = Opts::new("dolores_registered_services", "Services in the registry");
+    register(IntGauge::with_opts(opts).unwrap())
+});
+
+static CONTROL_COMMANDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new(
+        "dolores_control_commands_total",
+        "Commands processed by the control socket",
+    );
+    register(IntCounterVec::new(opts, &["command", "service"]).unwrap())
+});
+
+/// Register all metrics, so they are exposed even before anything happens
+pub fn init() {
+    Lazy::force(&CONNECTIONS);
+    Lazy::force(&ACTIVE_CONNECTIONS);
+    Lazy::force(&RECEIVED_BYTES);
+    Lazy::force(&SENT_BYTES);
+    Lazy::force(&TLS_HANDSHAKE_FAILURES);
+    Lazy::force(&BACKEND_CONNECT_ERRORS);
+    Lazy::force(&BACKEND_CONNECT_DURATION);
+    Lazy::force(&REGISTERED_SERVICES);
+    Lazy::force(&CONTROL_COMMANDS);
+}
+
+/// All metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut out = Vec::new();
+    prometheus::TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut out)
+        .unwrap();
+
+    String::from_utf8(out).unwrap()
+}
+
+/// Client connection to the service, counted as active until dropped
+pub struct Connection {
+    active: IntGauge,
+}
+
+impl Connection {
+    pub fn start(service: &str) -> Self {
+        CONNECTIONS.with_label_values(&[service]).inc();
+        let active = ACTIVE_CONNECTIONS.with_label_values(&[service]);
+        active.inc();
+
+        Connection { active }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.active.dec();
+    }
+}
+
+pub fn tls_handshake_failed(service: &str) {
+    TLS_HANDSHAKE_FAILURES.with_label_values(&[service]).inc();
+}
+
+pub fn backend_connected(service: &str, duration: std::time::Duration, success: bool) {
+    BACKEND_CONNECT_DURATION
+        .with_label_values(&[service])
+        .observe(duration.as_secs_f64());
+    if !success {
+        BACKEND_CONNECT_ERRORS.with_label_values(&[service]).inc();
+    }
+}
+
+pub fn registered_services(count: usize) {
+    REGISTERED_SERVICES.set(count as i64);
+}
+
+/// Count the command, `service` is its domain when registered, [`OTHER`] or empty otherwise
+pub fn control_command(command: &str, service: &str) {
+    CONTROL_COMMANDS
+        .with_label_values(&[command, service])
+        .inc();
+}
+
+/// Remove all series of the deregistered service
+pub fn forget(service: &str) {
+    for metric in [
+        &*CONNECTIONS,
+        &*RECEIVED_BYTES,
+        &*SENT_BYTES,
+        &*TLS_HANDSHAKE_FAILURES,
+        &*BACKEND_CONNECT_ERRORS,
+    ] {
+        let _ = metric.remove_label_values(&[service]);
+    }
+    // Open connections keep their gauge, so it does not go below zero once they close
+    let _ = ACTIVE_CONNECTIONS.remove_label_values(&[service]);
+    let _ = BACKEND_CONNECT_DURATION.remove_label_values(&[service]);
+
+    let commands: Vec<String> = CONTROL_COMMANDS
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .filter(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .any(|label| label.get_name() == "service" && label.get_value() == service)
+        })
+        .flat_map(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .filter(|label| label.get_name() == "command")
+                .map(|label| label.get_value().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for command in commands {
+        let _ = CONTROL_COMMANDS.remove_label_values(&[&command, service]);
+    }
+}
+
+/// Stream counting the bytes passing through it
+pub struct Counted<S> {
+    inner: S,
+    received: IntCounter,
+    sent: IntCounter,
+}
+
+impl<S> Counted<S> {
+    pub fn new(inner: S, service: &str) -> Self {
+        Counted {
+            inner,
+            received: RECEIVED_BYTES.with_label_values(&[service]),
+            sent: SENT_BYTES.with_label_values(&[service]),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.received.inc_by((buf.filled().len() - before) as u64);
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            self.sent.inc_by(len as u64);
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(len)) = result {
+            self.sent.inc_by(len as u64);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests;
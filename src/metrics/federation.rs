@@ -0,0 +1,297 @@
+//! Metrics scraped from the applications and re-exposed with the `service` label
+//!
+//! Applications are scraped on demand, whenever the metrics of the server are requested, so the
+//! values are as fresh as the ones exposed by the applications themselves.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::registry::RegistryStore;
+use crate::service::Service;
+
+/// Maximal time for which the metrics of single application are awaited
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Suffixes added to the family name in the names of the samples of histograms, summaries,
+/// counters and info metrics
+const SUFFIXES: &[&str] = &[
+    "_bucket", "_sum", "_count", "_total", "_created", "_gsum", "_gcount", "_info",
+];
+
+/// Result of scraping single service
+#[derive(Debug)]
+pub struct Scrape {
+    /// Domain of the service
+    pub service: String,
+    pub result: Result<Vec<Family>, String>,
+}
+
+/// Metric family, samples described by the same `# HELP` and `# TYPE` comments
+#[derive(Debug, Clone, Default)]
+pub struct Family {
+    pub name: String,
+    pub help: Option<String>,
+    pub kind: Option<String>,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub name: String,
+    /// Labels with values kept escaped, as they were exposed
+    pub labels: Vec<(String, String)>,
+    /// Value, optionally followed by the timestamp
+    pub value: String,
+}
+
+impl Sample {
+    /// Labels in the exposition format, including braces
+    pub fn labels(&self) -> String {
+        if self.labels.is_empty() {
+            return String::new();
+        }
+
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, value))
+            .collect();
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+/// Scrape all services exposing metrics, concurrently
+pub async fn scrape_all(services: &RegistryStore) -> Vec<Scrape> {
+    let services: Vec<_> = services
+        .read()
+        .await
+        .iter()
+        .filter(|(_, service)| service.metrics_url().is_some())
+        .map(|(domain, service)| (domain.clone(), service.clone()))
+        .collect();
+
+    let mut scrapes = Vec::new();
+    let tasks: Vec<_> = services
+        .into_iter()
+        .map(|(domain, service)| tokio::spawn(async move { scrape(&domain, &service).await }))
+        .collect();
+    for task in tasks {
+        if let Ok(scrape) = task.await {
+            scrapes.push(scrape);
+        }
+    }
+    scrapes.sort_by(|a, b| a.service.cmp(&b.service));
+
+    scrapes
+}
+
+/// Scrape metrics of the service registered under `domain`
+pub async fn scrape(domain: &str, service: &Service) -> Scrape {
+    let result = match service.metrics_url() {
+        Some(url) => tokio::time::timeout(SCRAPE_TIMEOUT, fetch(&url))
+            .await
+            .unwrap_or_else(|_| Err("timed out".into()))
+            .map(|text| parse(&text)),
+        None => Err("service does not expose metrics".into()),
+    };
+
+    if let Err(ref err) = result {
+        tracing::debug!(%domain, %err, "Cannot scrape metrics");
+    }
+
+    Scrape {
+        service: domain.into(),
+        result,
+    }
+}
+
+async fn fetch(url: &str) -> Result<String, String> {
+    let uri = url.parse::<hyper::Uri>().map_err(|err| err.to_string())?;
+    let response = hyper::Client::new()
+        .get(uri)
+        .await
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("responded with {}", response.status()));
+    }
+
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|err| err.to_string())?;
+    String::from_utf8(body.into()).map_err(|err| err.to_string())
+}
+
+/// Parse metrics in the Prometheus text exposition format
+pub fn parse(text: &str) -> Vec<Family> {
+    let mut families: Vec<Family> = Vec::new();
+    let mut current: Option<usize> = None;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut parts = comment.trim_start().splitn(3, ' ');
+            let (keyword, name, rest) = match (parts.next(), parts.next()) {
+                (Some(keyword), Some(name)) => (keyword, name, parts.next().unwrap_or_default()),
+                _ => continue,
+            };
+            let index = match keyword {
+                "HELP" | "TYPE" => family(&mut families, name),
+                _ => continue,
+            };
+            if keyword == "HELP" {
+                families[index].help = Some(rest.into());
+            } else {
+                families[index].kind = Some(rest.into());
+            }
+            current = Some(index);
+            continue;
+        }
+
+        let sample = match parse_sample(line) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        // Histograms and summaries use suffixed names for their samples
+        let index = match current {
+            Some(index) if belongs(&families[index].name, &sample.name) => index,
+            _ => family(&mut families, &sample.name),
+        };
+        families[index].samples.push(sample);
+        current = Some(index);
+    }
+
+    families
+}
+
+/// Whether the sample of given name is a part of the family, not just named alike
+fn belongs(family: &str, sample: &str) -> bool {
+    sample
+        .strip_prefix(family)
+        .is_some_and(|suffix| suffix.is_empty() || SUFFIXES.contains(&suffix))
+}
+
+/// Index of the family with given name, created when missing
+fn family(families: &mut Vec<Family>, name: &str) -> usize {
+    match families.iter().position(|family| family.name == name) {
+        Some(index) => index,
+        None => {
+            families.push(Family {
+                name: name.into(),
+                ..Family::default()
+            });
+            families.len() - 1
+        }
+    }
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let (name, mut rest) = line.split_at(end);
+    let mut labels = Vec::new();
+
+    if let Some(inner) = rest.strip_prefix('{') {
+        rest = inner;
+        loop {
+            rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            if let Some(after) = rest.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+
+            let (label, after) = rest.split_once('=')?;
+            let after = after.trim_start().strip_prefix('"')?;
+
+            // Find closing quote, skipping the escaped ones
+            let mut escaped = false;
+            let end = after.char_indices().find_map(|(index, c)| match c {
+                _ if escaped => {
+                    escaped = false;
+                    None
+                }
+                '\\' => {
+                    escaped = true;
+                    None
+                }
+                '"' => Some(index),
+                _ => None,
+            })?;
+            labels.push((label.trim().to_owned(), after[..end].to_owned()));
+            rest = &after[end + 1..];
+        }
+    }
+
+    let value = rest.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    Some(Sample {
+        name: name.into(),
+        labels,
+        value: value.into(),
+    })
+}
+
+/// Metrics of all scraped services in the text exposition format
+///
+/// Families with the same name are merged, the `service` label is added to each sample and the
+/// label of the same name exposed by the application is renamed to `exported_service`.
+pub fn render(scrapes: &[Scrape]) -> String {
+    let mut families: Vec<Family> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for scrape in scrapes {
+        let service = scrape.service.replace('\\', "\\\\").replace('"', "\\\"");
+        for family in scrape.result.iter().flatten() {
+            let merged = *index.entry(family.name.clone()).or_insert_with(|| {
+                families.push(Family {
+                    samples: Vec::new(),
+                    ..family.clone()
+                });
+                families.len() - 1
+            });
+
+            for sample in &family.samples {
+                let mut sample = sample.clone();
+                for (name, _) in &mut sample.labels {
+                    if name == "service" {
+                        *name = "exported_service".into();
+                    }
+                }
+                sample.labels.insert(0, ("service".into(), service.clone()));
+                families[merged].samples.push(sample);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for family in &families {
+        if let Some(ref help) = family.help {
+            let _ = writeln!(out, "# HELP {} {}", family.name, help);
+        }
+        if let Some(ref kind) = family.kind {
+            let _ = writeln!(out, "# TYPE {} {}", family.name, kind);
+        }
+        for sample in &family.samples {
+            let _ = writeln!(out, "{}{} {}", sample.name, sample.labels(), sample.value);
+        }
+    }
+
+    if !scrapes.is_empty() {
+        out.push_str("# HELP dolores_scrape_up Whether metrics of the application were scraped\n");
+        out.push_str("# TYPE dolores_scrape_up gauge\n");
+        for scrape in scrapes {
+            let _ = writeln!(
+                out,
+                "dolores_scrape_up{{service=\"{}\"}} {}",
+                scrape.service,
+                u8::from(scrape.result.is_ok())
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests;
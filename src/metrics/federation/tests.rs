@@ -0,0 +1,151 @@
+use super::{parse, render, Family, Scrape};
+
+fn names(family: &Family) -> Vec<&str> {
+    family
+        .samples
+        .iter()
+        .map(|sample| sample.name.as_str())
+        .collect()
+}
+
+#[test]
+fn histogram_samples_belong_to_their_family() {
+    let families = parse(
+        "# HELP http_request_duration_seconds Duration of requests\n\
+         # TYPE http_request_duration_seconds histogram\n\
+         http_request_duration_seconds_bucket{le=\"0.1\"} 10\n\
+         http_request_duration_seconds_bucket{le=\"+Inf\"} 12\n\
+         http_request_duration_seconds_sum 1.5\n\
+         http_request_duration_seconds_count 12\n",
+    );
+
+    assert_eq!(families.len(), 1);
+    assert_eq!(families[0].name, "http_request_duration_seconds");
+    assert_eq!(families[0].help.as_deref(), Some("Duration of requests"));
+    assert_eq!(families[0].kind.as_deref(), Some("histogram"));
+    assert_eq!(
+        names(&families[0]),
+        [
+            "http_request_duration_seconds_bucket",
+            "http_request_duration_seconds_bucket",
+            "http_request_duration_seconds_sum",
+            "http_request_duration_seconds_count",
+        ]
+    );
+}
+
+#[test]
+fn metrics_named_alike_are_separate_families() {
+    let families = parse(
+        "# TYPE http_requests counter\n\
+         http_requests_total 5\n\
+         http_requests_created 1.7e9\n\
+         http_requests_in_flight 2\n\
+         # TYPE process_cpu_seconds gauge\n\
+         process_cpu_seconds 3\n\
+         process_cpu_seconds_max 4\n",
+    );
+
+    let families: Vec<_> = families
+        .iter()
+        .map(|family| (family.name.as_str(), names(family)))
+        .collect();
+    assert_eq!(
+        families,
+        [
+            (
+                "http_requests",
+                vec!["http_requests_total", "http_requests_created"]
+            ),
+            ("http_requests_in_flight", vec!["http_requests_in_flight"]),
+            ("process_cpu_seconds", vec!["process_cpu_seconds"]),
+            ("process_cpu_seconds_max", vec!["process_cpu_seconds_max"]),
+        ]
+    );
+}
+
+#[test]
+fn samples_without_comments_have_own_families() {
+    let families = parse("up 1\nup_time 5\n");
+
+    assert_eq!(families.len(), 2);
+    assert_eq!(families[0].name, "up");
+    assert_eq!(families[0].kind, None);
+    assert_eq!(families[1].name, "up_time");
+}
+
+#[test]
+fn labels_are_kept_escaped() {
+    let families =
+        parse("requests{path=\"/a,b\",quote=\"say \\\"hi\\\"\" , empty=\"\",} 1 1700000000000\n");
+    let sample = &families[0].samples[0];
+
+    assert_eq!(
+        sample.labels,
+        [
+            ("path".to_string(), "/a,b".to_string()),
+            ("quote".to_string(), "say \\\"hi\\\"".to_string()),
+            ("empty".to_string(), String::new()),
+        ]
+    );
+    assert_eq!(sample.value, "1 1700000000000");
+    assert_eq!(
+        sample.labels(),
+        "{path=\"/a,b\",quote=\"say \\\"hi\\\"\",empty=\"\"}"
+    );
+}
+
+#[test]
+fn malformed_lines_are_skipped() {
+    let families = parse(
+        "# just a comment\n\
+         #\n\
+         no_value\n\
+         no_value_with_labels{a=\"b\"}\n\
+         unclosed{a=\"b} 1\n\
+         unquoted{a=b} 1\n\
+         \n\
+         valid 1\n",
+    );
+
+    assert_eq!(families.len(), 1);
+    assert_eq!(families[0].name, "valid");
+}
+
+#[test]
+fn service_label_is_added() {
+    let scrapes = [
+        Scrape {
+            service: "api.localhost".into(),
+            result: Ok(parse(
+                "# HELP jobs Jobs\n# TYPE jobs gauge\njobs{service=\"mail\"} 1\n",
+            )),
+        },
+        Scrape {
+            service: "web.localhost".into(),
+            result: Ok(parse("# HELP jobs Jobs\n# TYPE jobs gauge\njobs 2\n")),
+        },
+        Scrape {
+            service: "down.localhost".into(),
+            result: Err("timed out".into()),
+        },
+    ];
+
+    assert_eq!(
+        render(&scrapes),
+        "# HELP jobs Jobs\n\
+         # TYPE jobs gauge\n\
+         jobs{service=\"api.localhost\",exported_service=\"mail\"} 1\n\
+         jobs{service=\"web.localhost\"} 2\n\
+         # HELP dolores_scrape_up Whether metrics of the application were scraped\n\
+         # TYPE dolores_scrape_up gauge\n\
+         dolores_scrape_up{service=\"api.localhost\"} 1\n\
+         dolores_scrape_up{service=\"web.localhost\"} 1\n\
+         dolores_scrape_up{service=\"down.localhost\"} 0\n"
+    );
+}
+
+#[test]
+fn nothing_is_rendered_without_scrapes() {
+    assert_eq!(render(&[]), "");
+}
@@ -0,0 +1,72 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{control_command, forget, render, Connection, Counted, OTHER};
+
+/// Lines of the rendered metrics with the series of the service
+fn series(service: &str) -> Vec<String> {
+    let label = format!("service=\"{}\"", service);
+    render()
+        .lines()
+        .filter(|line| line.contains(&label))
+        .map(String::from)
+        .collect()
+}
+
+#[tokio::test]
+async fn traffic_is_labelled_by_service() {
+    let service = "traffic.metrics.test";
+    let (client, server) = tokio::io::duplex(64);
+    let mut counted = Counted::new(server, service);
+    let connection = Connection::start(service);
+
+    let mut client = client;
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    counted.read_exact(&mut buf).await.unwrap();
+    counted.write_all(b"pong!").await.unwrap();
+
+    let lines = series(service);
+    let has = |line: &str| lines.iter().any(|series| series == line);
+    assert!(has(&format!(
+        "dolores_connections_total{{service=\"{}\"}} 1",
+        service
+    )));
+    assert!(has(&format!(
+        "dolores_active_connections{{service=\"{}\"}} 1",
+        service
+    )));
+    assert!(has(&format!(
+        "dolores_received_bytes_total{{service=\"{}\"}} 4",
+        service
+    )));
+    assert!(has(&format!(
+        "dolores_sent_bytes_total{{service=\"{}\"}} 5",
+        service
+    )));
+
+    drop(connection);
+    assert!(series(service).contains(&format!(
+        "dolores_active_connections{{service=\"{}\"}} 0",
+        service
+    )));
+}
+
+#[test]
+fn deregistered_service_is_forgotten() {
+    let service = "forgotten.metrics.test";
+    let connection = Connection::start(service);
+    super::tls_handshake_failed(service);
+    super::backend_connected(service, std::time::Duration::from_millis(5), false);
+    control_command("status", service);
+    control_command("set_state", service);
+    control_command("status", OTHER);
+    assert!(series(service).len() > 5);
+
+    forget(service);
+    assert_eq!(series(service), Vec::<String>::new());
+    assert!(!series(OTHER).is_empty());
+
+    // Connections open at that time do not bring the series back
+    drop(connection);
+    assert_eq!(series(service), Vec::<String>::new());
+}
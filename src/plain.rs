@@ -0,0 +1,213 @@
+//! Listener for plain HTTP
+//!
+//! Browsers use port 80 when the scheme is not typed, so requests there are redirected to HTTPS
+//! based on their `Host` header. Services run with `--plain-http` are served directly instead,
+//! and ACME HTTP-01 challenges can be answered from the webroot of the ACME client (e.g. certbot
+//! with `--webroot`), so publicly trusted certificates can be issued for the domain.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::proxy::{protocol, Backend, Connector};
+use crate::registry::RegistryStore;
+
+/// Path under which the ACME HTTP-01 challenges are requested
+pub const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+#[derive(Debug)]
+pub struct Server {
+    services: RegistryStore,
+    /// Port of the HTTPS listener, where requests are redirected
+    https_port: u16,
+    /// Directory in which ACME client stores `.well-known/acme-challenge/<token>` files
+    webroot: Option<PathBuf>,
+    wait: Duration,
+}
+
+impl Server {
+    pub fn new(
+        services: RegistryStore,
+        https_port: u16,
+        webroot: Option<PathBuf>,
+        wait: Duration,
+    ) -> Self {
+        Server {
+            services,
+            https_port,
+            webroot,
+            wait,
+        }
+    }
+
+    /// Accept connections from the listener, until the task is dropped
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(crate::shutdown::track(self.clone().handle(stream)));
+                }
+                Err(err) => tracing::warn!(%err, "Cannot accept HTTP connection"),
+            }
+        }
+    }
+
+    async fn handle(self: Arc<Self>, stream: TcpStream) {
+        let (client, local) = match (stream.peer_addr(), stream.local_addr()) {
+            (Ok(client), Ok(local)) => (client, local),
+            _ => return,
+        };
+        // Requests on one connection usually go to the same service, so its connection is reused
+        let backend = Arc::new(Mutex::new(None));
+
+        let service = service_fn(move |req| {
+            let server = self.clone();
+            let backend = backend.clone();
+            async move { server.respond(req, client, local, &backend).await }
+        });
+
+        let connection = Http::new()
+            .serve_connection(stream, service)
+            .with_upgrades();
+        if let Err(err) =
+            crate::shutdown::graceful(connection, |connection| connection.graceful_shutdown()).await
+        {
+            tracing::debug!(%err, "HTTP connection failed");
+        }
+    }
+
+    async fn respond(
+        &self,
+        req: Request<Body>,
+        client: SocketAddr,
+        local: SocketAddr,
+        backend: &Mutex<Option<Arc<Backend>>>,
+    ) -> hyper::Result<Response<Body>> {
+        if let (Some(webroot), Some(token)) =
+            (&self.webroot, req.uri().path().strip_prefix(CHALLENGE_PATH))
+        {
+            return Ok(challenge(webroot, token).await);
+        }
+
+        let host = match host(&req) {
+            Some(host) => host,
+            None => return Ok(empty(StatusCode::BAD_REQUEST)),
+        };
+        let found = crate::service::lookup(&*self.services.read().await, &host)
+            .map(|(domain, service)| (domain.clone(), service.clone()));
+        let (domain, service) = match found {
+            Some((domain, service)) if service.options.plain_http => (domain, service),
+            _ => return Ok(self.redirect(&host, &req)),
+        };
+
+        tracing::debug!(%domain, "Plain HTTP request");
+        let backend = {
+            let mut backend = backend.lock().unwrap();
+            match *backend {
+                // Service registered again, e.g. with other options, needs a new backend
+                Some(ref current)
+                    if current.connector().key() == domain
+                        && current.connector().service().same_registration(&service) =>
+                {
+                    current.clone()
+                }
+                _ => {
+                    let proxy_header = service.options.proxy_protocol.map(|version| {
+                        let header = protocol::Header {
+                            source: client,
+                            destination: local,
+                            sni: None,
+                            alpn: None,
+                        };
+                        header.encode(version)
+                    });
+                    let downstream = service.options.downstream;
+                    let connector =
+                        Connector::new(&domain, service, self.services.clone(), self.wait);
+                    // Plain HTTP listeners have their own role, apart from the local network one
+                    backend
+                        .insert(Arc::new(Backend::new(
+                            connector,
+                            downstream,
+                            proxy_header,
+                            false,
+                        )))
+                        .clone()
+                }
+            }
+        };
+
+        backend.forward(req, client, "http").await
+    }
+
+    /// Same URL on the HTTPS listener
+    ///
+    /// Temporary redirect is used, as browsers would remember permanent one even after the
+    /// service starts to be served over plain HTTP.
+    fn redirect(&self, host: &str, req: &Request<Body>) -> Response<Body> {
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        let location = match self.https_port {
+            443 => format!("https://{}{}", host, path),
+            port => format!("https://{}:{}{}", host, port, path),
+        };
+
+        Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(http::header::LOCATION, location)
+            .body(Body::empty())
+            .unwrap()
+    }
+}
+
+/// Host requested by the client, without the port
+fn host(req: &Request<Body>) -> Option<String> {
+    let authority = match req.headers().get(http::header::HOST) {
+        Some(host) => http::uri::Authority::try_from(host.as_bytes()).ok()?,
+        None => req.uri().authority()?.clone(),
+    };
+
+    Some(authority.host().trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// Key authorization for the token, as stored by the ACME client
+async fn challenge(webroot: &std::path::Path, token: &str) -> Response<Body> {
+    // Tokens are base64url encoded, anything else could point outside of the directory
+    let valid = !token.is_empty()
+        && token
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    if !valid {
+        return empty(StatusCode::NOT_FOUND);
+    }
+
+    let path = webroot.join(".well-known/acme-challenge").join(token);
+    match tokio::fs::read(&path).await {
+        Ok(content) => {
+            tracing::info!(%token, "ACME challenge");
+            Response::builder()
+                .header(http::header::CONTENT_TYPE, "application/octet-stream")
+                .body(Body::from(content))
+                .unwrap()
+        }
+        Err(err) => {
+            tracing::warn!(%err, ?path, "Unknown ACME challenge");
+            empty(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::net::TcpListener;
+
+use super::{Server, CHALLENGE_PATH};
+use crate::proxy::{Options, Type};
+use crate::registry::RegistryStore;
+use crate::service::Service;
+
+const CLIENT: &str = "192.0.2.1:50000";
+const LOCAL: &str = "127.0.0.1:80";
+
+/// Application answering with its name and the protocol of the original request
+async fn app(name: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: Request<Body>| async move {
+                let proto = req
+                    .headers()
+                    .get("x-forwarded-proto")
+                    .map(|value| value.to_str().unwrap().to_owned())
+                    .unwrap_or_default();
+                let body = format!("{} {} {}", name, proto, req.uri());
+                Ok::<_, hyper::Error>(Response::new(Body::from(body)))
+            });
+            tokio::spawn(hyper::server::conn::Http::new().serve_connection(stream, service));
+        }
+    });
+
+    addr
+}
+
+fn service(addr: SocketAddr, plain_http: bool) -> Service {
+    let options = Options {
+        plain_http,
+        ..Default::default()
+    };
+    Service::new("app.localhost", addr, Type::Http, &options)
+}
+
+fn registry(services: Vec<Service>) -> RegistryStore {
+    let services = services
+        .into_iter()
+        .map(|service| (service.domain.clone(), service))
+        .collect::<HashMap<_, _>>();
+    Arc::new(tokio::sync::RwLock::new(services))
+}
+
+fn server(services: RegistryStore, https_port: u16, webroot: Option<std::path::PathBuf>) -> Server {
+    Server::new(services, https_port, webroot, Duration::from_secs(1))
+}
+
+fn get(host: Option<&str>, path: &str) -> Request<Body> {
+    let mut req = Request::get(path);
+    if let Some(host) = host {
+        req = req.header(http::header::HOST, host);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+async fn respond(server: &Server, req: Request<Body>) -> Response<Body> {
+    let backend = Mutex::new(None);
+    server
+        .respond(
+            req,
+            CLIENT.parse().unwrap(),
+            LOCAL.parse().unwrap(),
+            &backend,
+        )
+        .await
+        .unwrap()
+}
+
+fn location(response: &Response<Body>) -> &str {
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    response.headers()[http::header::LOCATION].to_str().unwrap()
+}
+
+async fn body(response: Response<Body>) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn redirect_keeps_host_and_path() {
+    let server = server(registry(Vec::new()), 443, None);
+
+    let response = respond(&server, get(Some("App.localhost:80"), "/a/b?c=d")).await;
+    assert_eq!(location(&response), "https://app.localhost/a/b?c=d");
+
+    // Host from the absolute URI, when there is no header
+    let response = respond(&server, get(None, "http://app.localhost/")).await;
+    assert_eq!(location(&response), "https://app.localhost/");
+}
+
+#[tokio::test]
+async fn redirect_includes_other_https_port() {
+    let server = server(registry(Vec::new()), 8443, None);
+
+    let response = respond(&server, get(Some("app.localhost:8080"), "/path?q")).await;
+    assert_eq!(location(&response), "https://app.localhost:8443/path?q");
+}
+
+#[tokio::test]
+async fn unknown_host_is_redirected() {
+    // Dashboard shows what is wrong over HTTPS
+    let addr = app("app").await;
+    let server = server(registry(vec![service(addr, true)]), 443, None);
+
+    let response = respond(&server, get(Some("other.localhost"), "/")).await;
+    assert_eq!(location(&response), "https://other.localhost/");
+}
+
+#[tokio::test]
+async fn request_without_host_is_rejected() {
+    let server = server(registry(Vec::new()), 443, None);
+
+    let response = respond(&server, get(None, "/")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = respond(&server, get(Some("bad host"), "/")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn services_opting_in_are_served_over_plain_http() {
+    let addr = app("app").await;
+    let server = server(registry(vec![service(addr, true)]), 443, None);
+
+    let response = respond(&server, get(Some("app.localhost"), "/path?q")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, "app http /path?q");
+
+    // Subdomains are routed to the service, as over HTTPS
+    let response = respond(&server, get(Some("api.app.localhost"), "/")).await;
+    assert_eq!(body(response).await, "app http /");
+}
+
+#[tokio::test]
+async fn other_services_are_redirected() {
+    let addr = app("app").await;
+    let server = server(registry(vec![service(addr, false)]), 443, None);
+
+    let response = respond(&server, get(Some("app.localhost"), "/path")).await;
+    assert_eq!(location(&response), "https://app.localhost/path");
+}
+
+#[tokio::test]
+async fn backend_is_replaced_after_registering_again() {
+    let (one, two) = (app("one").await, app("two").await);
+    let services = registry(vec![service(one, true)]);
+    let server = server(services.clone(), 443, None);
+    // Shared by the requests, as on a single client connection
+    let backend = Mutex::new(None);
+    let client = CLIENT.parse().unwrap();
+    let local = LOCAL.parse().unwrap();
+
+    let req = get(Some("app.localhost"), "/");
+    let response = server.respond(req, client, local, &backend).await.unwrap();
+    assert_eq!(body(response).await, "one http /");
+
+    // State changes keep the registration, so the backend is kept too
+    services
+        .write()
+        .await
+        .get_mut("app.localhost")
+        .unwrap()
+        .state = crate::service::State::Running;
+    let cached = backend.lock().unwrap().clone().unwrap();
+    let req = get(Some("app.localhost"), "/");
+    let response = server.respond(req, client, local, &backend).await.unwrap();
+    assert_eq!(body(response).await, "one http /");
+    assert!(Arc::ptr_eq(
+        &cached,
+        backend.lock().unwrap().as_ref().unwrap()
+    ));
+
+    crate::registry::register(&services, service(two, true)).await;
+    let req = get(Some("app.localhost"), "/");
+    let response = server.respond(req, client, local, &backend).await.unwrap();
+    assert_eq!(body(response).await, "two http /");
+}
+
+/// Webroot with the challenge stored by the ACME client and a file outside of it
+fn webroot() -> std::path::PathBuf {
+    let webroot = std::env::temp_dir().join(format!("dolores-webroot-{:x}", rand::random::<u64>()));
+    let challenges = webroot.join(".well-known/acme-challenge");
+    std::fs::create_dir_all(&challenges).unwrap();
+    std::fs::write(challenges.join("Token_1-abc"), "Token_1-abc.thumbprint").unwrap();
+    std::fs::write(webroot.join("secret"), "secret").unwrap();
+
+    webroot
+}
+
+#[tokio::test]
+async fn challenge_is_answered_from_webroot() {
+    let webroot = webroot();
+    let server = server(registry(Vec::new()), 443, Some(webroot.clone()));
+
+    let path = format!("{}Token_1-abc", CHALLENGE_PATH);
+    let response = respond(&server, get(Some("app.localhost"), &path)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, "Token_1-abc.thumbprint");
+
+    // Also without the host, ACME servers always send one, but it is not needed
+    let response = respond(&server, get(None, &path)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let path = format!("{}unknown", CHALLENGE_PATH);
+    let response = respond(&server, get(Some("app.localhost"), &path)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    std::fs::remove_dir_all(&webroot).unwrap();
+}
+
+#[tokio::test]
+async fn challenge_tokens_cannot_leave_webroot() {
+    let webroot = webroot();
+    let server = server(registry(Vec::new()), 443, Some(webroot.clone()));
+
+    for token in [
+        "",
+        "../../secret",
+        "..%2F..%2Fsecret",
+        "%2e%2e/%2e%2e/secret",
+        "Token_1-abc/",
+        "Token.1",
+    ] {
+        let path = format!("{}{}", CHALLENGE_PATH, token);
+        let response = respond(&server, get(Some("app.localhost"), &path)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{:?}", token);
+        assert_ne!(body(response).await, "secret", "{:?}", token);
+    }
+
+    std::fs::remove_dir_all(&webroot).unwrap();
+}
+
+#[tokio::test]
+async fn challenges_are_redirected_without_webroot() {
+    let server = server(registry(Vec::new()), 443, None);
+
+    let path = format!("{}Token_1-abc", CHALLENGE_PATH);
+    let response = respond(&server, get(Some("app.localhost"), &path)).await;
+    assert_eq!(
+        location(&response),
+        format!("https://app.localhost{}", path)
+    );
+}
@@ -0,0 +1,87 @@
+//! Dropping root privileges once the privileged ports and sockets are bound
+
+use std::io;
+use std::path::Path;
+
+use nix::unistd::{Gid, Group, Uid, User};
+
+/// User and group the server switches to
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub uid: Uid,
+    pub gid: Gid,
+}
+
+impl Credentials {
+    /// Look up the user and the group, given by names or numeric IDs
+    ///
+    /// Primary group of the user is used, when the group is not given.
+    pub fn lookup(user: &str, group: Option<&str>) -> io::Result<Self> {
+        let not_found = |kind, name: &str| {
+            io::Error::new(io::ErrorKind::NotFound, format!("unknown {} {}", kind, name))
+        };
+
+        let found = match user.parse() {
+            Ok(uid) => User::from_uid(Uid::from_raw(uid))?,
+            Err(_) => User::from_name(user)?,
+        };
+        let found = found.ok_or_else(|| not_found("user", user))?;
+
+        let gid = match group {
+            Some(group) => {
+                let found = match group.parse() {
+                    Ok(gid) => Group::from_gid(Gid::from_raw(gid))?,
+                    Err(_) => Group::from_name(group)?,
+                };
+                found.ok_or_else(|| not_found("group", group))?.gid
+            }
+            None => found.gid,
+        };
+
+        Ok(Credentials {
+            user: found.name,
+            uid: found.uid,
+            gid,
+        })
+    }
+
+    /// Give the file to the user, so it can be still written after switching, missing one is
+    /// skipped
+    pub fn chown(&self, path: &Path) -> io::Result<()> {
+        match nix::unistd::chown(path, Some(self.uid), Some(self.gid)) {
+            Err(nix::errno::Errno::ENOENT) => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Switch the whole process to the user and the group
+    ///
+    /// Leaving root clears all capabilities of the process, as the server never asks to keep
+    /// them, so it is verified only that root cannot be regained.
+    pub fn switch(&self) -> io::Result<()> {
+        let euid = nix::unistd::geteuid();
+        if euid == self.uid {
+            return Ok(());
+        }
+        if !euid.is_root() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "only root can switch to another user",
+            ));
+        }
+
+        nix::unistd::setgroups(&[self.gid])?;
+        nix::unistd::setgid(self.gid)?;
+        nix::unistd::setuid(self.uid)?;
+
+        if nix::unistd::setuid(Uid::from_raw(0)).is_ok() {
+            return Err(io::Error::other(
+                "root privileges are still available after switching user",
+            ));
+        }
+        tracing::info!(user = %self.user, uid = %self.uid, gid = %self.gid, "Dropped privileges");
+
+        Ok(())
+    }
+}
@@ -2,10 +2,19 @@ use std::borrow::Cow;
 use std::io;
 use std::sync::Arc;
 
+mod connector;
+mod http;
+pub mod protocol;
 mod tls_terminating;
 mod transparent;
 
-pub use tls_terminating::TlsTerminating;
+#[cfg(test)]
+mod tests;
+
+pub(crate) use self::http::{inspect_body, Backend};
+pub use self::http::Http;
+pub use connector::{ConnectError, Connector};
+pub use tls_terminating::{init_ca, load_ca, TlsTerminating};
 pub use transparent::Transparent;
 
 #[derive(
@@ -14,31 +23,164 @@ pub use transparent::Transparent;
 pub enum Type {
     Passthrough,
     Terminating,
+    Http,
 }
 
 impl Type {
-    pub fn build<'a>(self, domain: impl Into<Domain<'a>>) -> Arc<TcpProxy> {
+    pub fn build<'a>(self, domain: impl Into<Domain<'a>>, options: &Options) -> Arc<TcpProxy> {
+        let proxy_protocol = options.proxy_protocol;
+
         match self {
-            Type::Passthrough => Arc::new(Transparent),
-            Type::Terminating => Arc::new(TlsTerminating::self_signed(domain.into())),
+            Type::Passthrough => Arc::new(Transparent::new(proxy_protocol)),
+            Type::Terminating => Arc::new(
+                TlsTerminating::generated(domain.into())
+                    .with_alpn(options.downstream.alpn())
+                    .with_proxy_protocol(proxy_protocol),
+            ),
+            Type::Http => Arc::new(Http::new(
+                TlsTerminating::generated(domain.into())
+                    .with_alpn(Downstream::H2c.alpn())
+                    .with_proxy_protocol(proxy_protocol),
+                options.downstream,
+            )),
         }
     }
 }
 
-pub struct Domain<'a>(Cow<'a, str>);
+/// HTTP version spoken by the downstream application
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+pub enum Downstream {
+    /// HTTP/1.1 only, HTTP/2 requests are translated by the `http` proxy
+    #[default]
+    Http1,
+    /// HTTP/2 with prior knowledge (cleartext)
+    ///
+    /// The application still needs to accept HTTP/1.1, which is used by clients that do not
+    /// negotiate HTTP/2 with the `terminating` proxy, and by the `http` proxy for upgrades.
+    H2c,
+}
+
+impl Downstream {
+    /// Protocols that can be advertised to clients when passing raw stream downstream
+    pub fn alpn(self) -> &'static [&'static [u8]] {
+        match self {
+            Downstream::Http1 => &[b"http/1.1"],
+            Downstream::H2c => &[b"h2", b"http/1.1"],
+        }
+    }
+}
+
+/// Service specific options for the proxy
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, clap::Args)]
+pub struct Options {
+    /// Prepend PROXY protocol header of given version to the downstream connections
+    #[arg(long, value_name = "VERSION")]
+    pub proxy_protocol: Option<protocol::Version>,
+
+    /// HTTP version supported by the application
+    #[arg(long, value_enum, default_value_t)]
+    pub downstream: Downstream,
+
+    /// Record recent requests and responses, so they can be inspected in the dashboard
+    ///
+    /// Works only with `http` proxy.
+    #[arg(long)]
+    pub capture: bool,
+
+    /// Write access log of the service to the file, `-` stands for standard output of the server
+    ///
+    /// Entries are written in addition to the global access log of the server. File is opened by
+    /// `dolores run` and passed to the server, which does not open paths sent by its clients.
+    #[arg(long, value_name = "PATH")]
+    pub access_log: Option<std::path::PathBuf>,
+
+    /// Format of the service access log
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    pub access_log_format: crate::access_log::Format,
+
+    /// Path at which the application exposes Prometheus metrics
+    ///
+    /// Metrics are scraped by the server and exposed at its `/metrics` with `service` label.
+    #[arg(long, value_name = "PATH", value_parser = metrics_path)]
+    pub metrics_path: Option<String>,
+
+    /// Pass additional socket named `metrics` (FD 4) on which the application exposes metrics
+    ///
+    /// Metrics are read from `--metrics-path` of that socket, `/metrics` by default.
+    #[arg(long)]
+    pub metrics_socket: bool,
+
+    /// Make the service reachable from the local network as `<name>.<host>.local`
+    ///
+    /// Server needs to run with `--share opt-in`, with plain `--share` all services are shared.
+    #[arg(long)]
+    pub share: bool,
+
+    /// Serve the application also over plain HTTP, instead of redirecting to HTTPS
+    ///
+    /// Server needs to run with `--http` or `--listen http=ADDR`, works with `terminating` and
+    /// `http` proxies.
+    #[arg(long)]
+    pub plain_http: bool,
+
+    /// Address of the metrics socket, filled in by the runner
+    #[arg(skip)]
+    pub metrics_addr: Option<std::net::SocketAddr>,
+}
+
+fn metrics_path(path: &str) -> Result<String, &'static str> {
+    if crate::service::is_valid_metrics_path(path) {
+        Ok(path.into())
+    } else {
+        Err("path has to start with `/`")
+    }
+}
+
+/// Information about the client connection gathered before the proxy was started
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    /// Server name requested by the client in TLS handshake
+    pub sni: Option<String>,
+    /// Client connected to the listener for the local network
+    pub lan: bool,
+}
+
+/// Domain for which the certificate is issued, with optional aliases it is also valid for
+pub struct Domain<'a>(Cow<'a, str>, Vec<String>);
+
+impl Domain<'_> {
+    pub fn with_aliases(mut self, aliases: &[String]) -> Self {
+        self.1.extend_from_slice(aliases);
+        self
+    }
+}
 
 impl<'a, S> From<S> for Domain<'a>
 where
     S: Into<Cow<'a, str>>,
 {
     fn from(input: S) -> Self {
-        Domain(input.into())
+        Domain(input.into(), Vec::new())
     }
 }
 
 impl From<Domain<'_>> for Vec<String> {
-    fn from(Domain(ref domain): Domain) -> Vec<String> {
-        vec![domain.to_string(), format!("*.{}", domain)]
+    fn from(Domain(ref domain, ref aliases): Domain) -> Vec<String> {
+        std::iter::once(domain.as_ref())
+            .chain(aliases.iter().map(String::as_str))
+            .flat_map(|name| [name.to_string(), format!("*.{}", name)])
+            .collect()
     }
 }
 
@@ -47,7 +189,7 @@ pub trait Proxy: Send + Sync + core::fmt::Debug {
     type Up;
     type Down;
 
-    async fn run(&self, up: Self::Up, down: Self::Down) -> io::Result<()>;
+    async fn run(&self, up: Self::Up, down: Self::Down, ctx: Context) -> io::Result<()>;
 }
 
-pub type TcpProxy = dyn Proxy<Up = tokio::net::TcpStream, Down = tokio::net::TcpStream>;
+pub type TcpProxy = dyn Proxy<Up = tokio::net::TcpStream, Down = Connector>;
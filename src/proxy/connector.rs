@@ -0,0 +1,120 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+
+use crate::registry::RegistryStore;
+use crate::service::{Service, State};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Connection to the application of the service, established on demand
+///
+/// When the service is starting or restarting, failed connection attempts are retried with
+/// exponential backoff for up to `wait`. The address is looked up in the registry on each attempt,
+/// so the service can come back on a different port.
+#[derive(Clone, Debug)]
+pub struct Connector {
+    key: String,
+    service: Service,
+    services: RegistryStore,
+    wait: Duration,
+}
+
+/// Failure to connect to the application
+#[derive(Debug)]
+pub struct ConnectError {
+    /// Last known registration of the service
+    pub service: Service,
+    pub error: io::Error,
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}",
+            self.service.domain, self.service.addr, self.error
+        )
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<ConnectError> for io::Error {
+    fn from(err: ConnectError) -> Self {
+        io::Error::new(err.error.kind(), err)
+    }
+}
+
+impl Connector {
+    /// Create connector for `service` registered under `key`
+    pub fn new(key: &str, service: Service, services: RegistryStore, wait: Duration) -> Self {
+        Connector {
+            key: key.into(),
+            service,
+            services,
+            wait,
+        }
+    }
+
+    /// Domain under which the service is registered
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Registration of the service at the moment when the connection was accepted
+    pub fn service(&self) -> &Service {
+        &self.service
+    }
+
+    /// Current registration of the service
+    pub async fn current(&self) -> Option<Service> {
+        self.services.read().await.get(&self.key).cloned()
+    }
+
+    pub async fn connect(&self) -> Result<TcpStream, ConnectError> {
+        let started = Instant::now();
+        let result = self.attempt(started).await;
+        crate::metrics::backend_connected(&self.key, started.elapsed(), result.is_ok());
+
+        result
+    }
+
+    async fn attempt(&self, started: Instant) -> Result<TcpStream, ConnectError> {
+        let mut backoff = INITIAL_BACKOFF;
+        // Service could be restarted since the client connection was accepted
+        let mut service = match self.current().await {
+            Some(service) => service,
+            None => self.service.clone(),
+        };
+
+        loop {
+            let error = match TcpStream::connect(service.addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(error) => error,
+            };
+
+            if service.state == State::Running || started.elapsed() + backoff > self.wait {
+                return Err(ConnectError { service, error });
+            }
+
+            tracing::debug!(%error, state = ?service.state, ?backoff, "Waiting for service");
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+
+            service = match self.current().await {
+                Some(service) => service,
+                None => {
+                    let error = io::Error::new(io::ErrorKind::NotFound, "service deregistered");
+                    return Err(ConnectError { service, error });
+                }
+            };
+        }
+    }
+}
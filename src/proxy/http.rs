@@ -0,0 +1,458 @@
+use std::future::poll_fn;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::header::{HeaderName, HeaderValue};
+use hyper::body::HttpBody;
+use hyper::client::conn::SendRequest;
+use hyper::server::conn::Http as Server;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use super::{ConnectError, Connector, Downstream, TlsTerminating};
+use crate::access_log;
+use crate::capture::Recorder;
+use crate::telemetry;
+
+/// Time after which browsers waiting for the application to start are shown waiting page
+const WAITING_PAGE_AFTER: Duration = Duration::from_secs(2);
+
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// HTTP-aware TLS terminating proxy
+///
+/// In contrast to [`TlsTerminating`] this proxy parses HTTP requests coming from the client, so it
+/// can inform the downstream application about the original request by adding `X-Forwarded-*`
+/// and [RFC 7239][rfc] `Forwarded` headers. Requests from a single client connection are sent
+/// over a single downstream connection, so keep-alive and pipelining are preserved.
+///
+/// Clients can negotiate HTTP/2 via ALPN independently of the HTTP version spoken by the
+/// application, requests are translated to the [`Downstream`] version when needed. Upgrade
+/// requests are the exception, they are always passed in HTTP/1.1.
+///
+/// Protocol upgrades (for example WebSockets) are supported, after the application responds with
+/// `101 Switching Protocols` both connections are joined and data is copied as is.
+///
+/// The downstream connection is established when the first request arrives. If the application
+/// is still starting, browsers are shown an auto-refreshing page instead of waiting indefinitely.
+///
+/// [rfc]: https://www.rfc-editor.org/rfc/rfc7239
+#[derive(Clone, Debug)]
+pub struct Http {
+    tls: TlsTerminating,
+    downstream: Downstream,
+}
+
+impl Http {
+    pub fn new(tls: TlsTerminating, downstream: Downstream) -> Self {
+        Http { tls, downstream }
+    }
+}
+
+#[async_trait]
+impl super::Proxy for Http {
+    type Up = tokio::net::TcpStream;
+    type Down = Connector;
+
+    async fn run(&self, up: Self::Up, down: Self::Down, ctx: super::Context) -> io::Result<()> {
+        tracing::debug!("Proxy started");
+        let client = up.peer_addr()?;
+        let up = self
+            .tls
+            .accept(up)
+            .await
+            .inspect_err(|_| crate::metrics::tls_handshake_failed(down.key()))?;
+
+        let h2 = up.get_ref().1.alpn_protocol() == Some(b"h2");
+        let downstream = self.downstream;
+        tracing::debug!(h2, ?downstream, "Negotiated");
+
+        let proxy_header = self.tls.proxy_header(&up)?;
+        let up = crate::metrics::Counted::new(up, down.key());
+        let backend = Arc::new(Backend::new(down, downstream, proxy_header, ctx.lan));
+
+        let service = service_fn(move |req| backend.clone().forward(req, client, "https"));
+
+        let connection = Server::new()
+            .http2_only(h2)
+            .pipeline_flush(true)
+            .serve_connection(up, service)
+            .with_upgrades();
+        crate::shutdown::graceful(connection, |connection| connection.graceful_shutdown())
+            .await
+            .map_err(io::Error::other)
+    }
+}
+
+/// Downstream side of the client connection
+pub(crate) struct Backend {
+    connector: Connector,
+    downstream: Downstream,
+    proxy_header: Option<Vec<u8>>,
+    /// Client is in the local network, see [`Context::lan`](super::Context::lan)
+    lan: bool,
+    sender: Mutex<Option<SendRequest<Body>>>,
+}
+
+impl Backend {
+    pub(crate) fn new(
+        connector: Connector,
+        downstream: Downstream,
+        proxy_header: Option<Vec<u8>>,
+        lan: bool,
+    ) -> Self {
+        Backend {
+            connector,
+            downstream,
+            proxy_header,
+            lan,
+            sender: Mutex::new(None),
+        }
+    }
+
+    /// Pass the request received over `proto` (`http` or `https`) from the client to the application
+    pub(crate) async fn forward(
+        self: Arc<Self>,
+        mut req: Request<Body>,
+        client: SocketAddr,
+        proto: &'static str,
+    ) -> hyper::Result<Response<Body>> {
+        let connector = &self.connector;
+        let log = access_log::Exchange::start(connector.service(), connector.key(), client, &req);
+        let span = telemetry::Span::start(connector.key(), client, &mut req);
+        forwarded(&mut req, client, proto);
+        crate::headers::request(connector.key(), req.headers_mut());
+        let version = self.version(req.headers());
+        translate(&mut req, version);
+        tracing::trace!(?req, "Request");
+
+        let mut response = match self.send(req).await {
+            Ok(response) => response,
+            Err(err) => {
+                if let Some(span) = span {
+                    span.error(&err);
+                }
+                return Err(err);
+            }
+        };
+        crate::headers::response(connector.key(), response.headers_mut());
+        if let Some(span) = span {
+            response = span.response(response);
+        }
+
+        Ok(match log {
+            Some(log) => log.response(response),
+            None => response,
+        })
+    }
+
+    /// Connector to the application of the service
+    pub(crate) fn connector(&self) -> &Connector {
+        &self.connector
+    }
+
+    /// HTTP version in which the request is passed downstream
+    ///
+    /// Upgrades exist only in HTTP/1.1, so they are never translated to HTTP/2.
+    fn version(&self, headers: &http::HeaderMap) -> Downstream {
+        if is_upgrade(headers) {
+            Downstream::Http1
+        } else {
+            self.downstream
+        }
+    }
+
+    async fn send(&self, mut req: Request<Body>) -> hyper::Result<Response<Body>> {
+        let upgrade = is_upgrade(req.headers()).then(|| hyper::upgrade::on(&mut req));
+
+        let (recorder, req) = match self.connector.service().capture {
+            Some(ref buffer) if upgrade.is_none() => {
+                let (recorder, req) = Recorder::start(buffer.clone(), self.connector.key(), req);
+                (Some(recorder), req)
+            }
+            _ => (None, req),
+        };
+
+        let html = accepts_html(req.headers());
+        let response = if upgrade.is_some() && self.downstream == Downstream::H2c {
+            // Shared connection speaks HTTP/2, so the upgrade gets its own HTTP/1.1 connection
+            let mut sender = match self.connect(html, Downstream::Http1).await {
+                Ok(sender) => sender,
+                Err(response) => return Ok(record(recorder, response)),
+            };
+            poll_fn(|cx| sender.poll_ready(cx)).await?;
+            sender.send_request(req)
+        } else {
+            // Hold the lock only while queueing the request, so responses can be awaited concurrently
+            let mut cached = self.sender.lock().await;
+            let ready = match &mut *cached {
+                Some(sender) => match poll_fn(|cx| sender.poll_ready(cx)).await {
+                    Ok(()) => true,
+                    Err(err) => {
+                        tracing::debug!(%err, "Connection to the application lost");
+                        false
+                    }
+                },
+                None => false,
+            };
+            let sender = match &mut *cached {
+                Some(sender) if ready => sender,
+                // Connection is lost when the application restarts, the connector waits for it
+                _ => {
+                    *cached = None;
+                    let mut connected = match self.connect(html, self.downstream).await {
+                        Ok(connected) => connected,
+                        Err(response) => return Ok(record(recorder, response)),
+                    };
+                    poll_fn(|cx| connected.poll_ready(cx)).await?;
+                    cached.insert(connected)
+                }
+            };
+            sender.send_request(req)
+        };
+
+        let mut response = match response.await {
+            Ok(response) => response,
+            Err(err) => {
+                if let Some(recorder) = recorder {
+                    recorder.error(&err);
+                }
+                return Err(err);
+            }
+        };
+
+        if let Some(upgrade) = upgrade {
+            if response.status() == http::StatusCode::SWITCHING_PROTOCOLS {
+                let down = hyper::upgrade::on(&mut response);
+                tokio::spawn(crate::shutdown::track(tunnel(upgrade, down)));
+            }
+        }
+
+        Ok(record(recorder, response))
+    }
+
+    /// Connect to the application, on failure returns error page that should be sent to client
+    async fn connect(
+        &self,
+        html: bool,
+        version: Downstream,
+    ) -> Result<SendRequest<Body>, Response<Body>> {
+        let connect = self.connector.connect();
+        tokio::pin!(connect);
+
+        let result = tokio::select! {
+            result = &mut connect => result,
+            _ = tokio::time::sleep(WAITING_PAGE_AFTER), if html => {
+                let service = self.connector.current().await;
+                let service = service.as_ref().unwrap_or_else(|| self.connector.service());
+                tracing::debug!(state = ?service.state, "Show waiting page");
+
+                return Err(crate::dashboard::waiting_response(service));
+            }
+        };
+
+        let handshake = async {
+            let mut stream = result?;
+            let error = |error| ConnectError {
+                service: self.connector.service().clone(),
+                error,
+            };
+
+            if let Some(ref header) = self.proxy_header {
+                stream.write_all(header).await.map_err(error)?;
+            }
+
+            hyper::client::conn::Builder::new()
+                .http2_only(version == Downstream::H2c)
+                .handshake(stream)
+                .await
+                .map_err(|err| error(io::Error::other(err)))
+        };
+
+        match handshake.await {
+            Ok((sender, connection)) => {
+                tokio::spawn(async move {
+                    if let Err(err) = connection.await {
+                        tracing::error!(%err, "Downstream connection failed");
+                    }
+                });
+
+                Ok(sender)
+            }
+            Err(err) => {
+                tracing::error!(%err, "Cannot connect to service");
+                Err(crate::dashboard::bad_gateway_response(&err, self.lan))
+            }
+        }
+    }
+}
+
+fn record(recorder: Option<Recorder>, response: Response<Body>) -> Response<Body> {
+    match recorder {
+        Some(recorder) => recorder.response(response),
+        None => response,
+    }
+}
+
+/// Check whether the client is a browser that will render the response
+fn accepts_html(headers: &http::HeaderMap) -> bool {
+    headers
+        .get_all(http::header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"))
+}
+
+/// Join both sides of the upgraded connection
+async fn tunnel(up: hyper::upgrade::OnUpgrade, down: hyper::upgrade::OnUpgrade) {
+    let result = async {
+        let (mut up, mut down) = tokio::try_join!(up, down).map_err(io::Error::other)?;
+        tracing::debug!("Connection upgraded");
+        tokio::io::copy_bidirectional(&mut up, &mut down).await
+    };
+
+    match result.await {
+        Ok((sent, received)) => tracing::debug!(sent, received, "Upgraded connection closed"),
+        Err(err) => tracing::debug!(%err, "Upgraded connection failed"),
+    }
+}
+
+/// Check whether the request asks for switching protocols
+fn is_upgrade(headers: &http::HeaderMap) -> bool {
+    headers.contains_key(http::header::UPGRADE)
+        && headers
+            .get_all(http::header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Add headers describing the original request to the request passed downstream
+fn forwarded<B>(req: &mut Request<B>, client: SocketAddr, proto: &'static str) {
+    let host = req
+        .headers()
+        .get(http::header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .or_else(|| req.uri().authority().map(ToString::to_string));
+    let headers = req.headers_mut();
+
+    headers.insert(&X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+
+    if let Some(ref host) = host {
+        if let Ok(value) = HeaderValue::from_str(host) {
+            headers.insert(&X_FORWARDED_HOST, value);
+        }
+    }
+
+    let ip = client.ip().to_canonical();
+    append(headers, &X_FORWARDED_FOR, &ip.to_string());
+
+    let node = match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    };
+    let mut element = format!("for={};proto={}", node, proto);
+    if let Some(host) = host {
+        element.push_str(&format!(";host={}", quoted(&host)));
+    }
+    append(headers, &http::header::FORWARDED, &element);
+}
+
+/// Value as a quoted string, so it cannot end early and add other parameters
+fn quoted(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+
+    format!("\"{}\"", escaped)
+}
+
+/// Adjust request received from the client to the HTTP version used downstream
+///
+/// HTTP/1.1 requests need `Host` header and path-only URI, while HTTP/2 requests carry the
+/// authority and scheme in the URI instead.
+fn translate<B>(req: &mut Request<B>, downstream: Downstream) {
+    let authority = req.uri().authority().cloned().or_else(|| {
+        req.headers()
+            .get(http::header::HOST)
+            .and_then(|host| http::uri::Authority::try_from(host.as_bytes()).ok())
+    });
+    let path = req
+        .uri()
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| http::uri::PathAndQuery::from_static("/"));
+
+    let mut parts = http::uri::Parts::default();
+    parts.path_and_query = Some(path);
+
+    match downstream {
+        Downstream::Http1 => {
+            *req.version_mut() = http::Version::HTTP_11;
+            if let Some(authority) = authority {
+                if !req.headers().contains_key(http::header::HOST) {
+                    if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+                        req.headers_mut().insert(http::header::HOST, host);
+                    }
+                }
+            }
+        }
+        Downstream::H2c => {
+            *req.version_mut() = http::Version::HTTP_2;
+            parts.scheme = Some(http::uri::Scheme::HTTP);
+            parts.authority = authority;
+        }
+    }
+
+    if let Ok(uri) = http::Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+}
+
+/// Append value to the comma separated list stored in the header, creating it when needed
+fn append(headers: &mut http::HeaderMap, name: &HeaderName, value: &str) {
+    let value = match headers.get(name).and_then(|old| old.to_str().ok()) {
+        Some(old) if !old.is_empty() => format!("{}, {}", old, value),
+        _ => value.to_owned(),
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+/// Pass the body through, calling `data` with each chunk and `end` once all of them were passed
+///
+/// Empty body is returned as it is, wrapping would hide its size, which matters for messages
+/// without one. Both functions are dropped together with the body, also when it is not read to the
+/// end, so they can hold what needs to be finished either way.
+pub(crate) fn inspect_body<D, E>(mut body: Body, mut data: D, end: E) -> Body
+where
+    D: FnMut(&[u8]) + Send + 'static,
+    E: FnOnce() + Send + 'static,
+{
+    if body.is_end_stream() {
+        end();
+        return body;
+    }
+
+    Body::wrap_stream(async_stream::stream! {
+        while let Some(chunk) = body.data().await {
+            if let Ok(ref chunk) = chunk {
+                data(chunk);
+            }
+            yield chunk;
+        }
+
+        end();
+    })
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,108 @@
+use std::net::SocketAddr;
+
+use hyper::{Body, Request};
+
+use super::forwarded;
+
+fn request(host: Option<&str>) -> Request<Body> {
+    let mut builder = Request::get("/path");
+    if let Some(host) = host {
+        builder = builder.header("host", host);
+    }
+
+    builder.body(Body::empty()).unwrap()
+}
+
+fn header<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.headers().get(name).map(|value| value.to_str().unwrap())
+}
+
+#[test]
+fn forwarded_from_ipv4_client() {
+    let mut req = request(Some("foo.localhost"));
+    let client: SocketAddr = "192.0.2.1:54321".parse().unwrap();
+
+    forwarded(&mut req, client, "https");
+
+    assert_eq!(header(&req, "x-forwarded-proto"), Some("https"));
+    assert_eq!(header(&req, "x-forwarded-host"), Some("foo.localhost"));
+    assert_eq!(header(&req, "x-forwarded-for"), Some("192.0.2.1"));
+    assert_eq!(
+        header(&req, "forwarded"),
+        Some("for=192.0.2.1;proto=https;host=\"foo.localhost\"")
+    );
+}
+
+#[test]
+fn forwarded_quotes_ipv6_node() {
+    let mut req = request(Some("foo.localhost"));
+    let client: SocketAddr = "[2001:db8::1]:54321".parse().unwrap();
+
+    forwarded(&mut req, client, "http");
+
+    assert_eq!(header(&req, "x-forwarded-for"), Some("2001:db8::1"));
+    assert_eq!(
+        header(&req, "forwarded"),
+        Some("for=\"[2001:db8::1]\";proto=http;host=\"foo.localhost\"")
+    );
+}
+
+#[test]
+fn forwarded_unmaps_ipv4_mapped_client() {
+    let mut req = request(None);
+    let client: SocketAddr = "[::ffff:127.0.0.1]:54321".parse().unwrap();
+
+    forwarded(&mut req, client, "https");
+
+    assert_eq!(header(&req, "x-forwarded-host"), None);
+    assert_eq!(header(&req, "x-forwarded-for"), Some("127.0.0.1"));
+    assert_eq!(header(&req, "forwarded"), Some("for=127.0.0.1;proto=https"));
+}
+
+#[test]
+fn forwarded_appends_to_previous_proxies() {
+    let mut req = request(Some("foo.localhost"));
+    let headers = req.headers_mut();
+    headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+    headers.insert("x-forwarded-proto", "http".parse().unwrap());
+    headers.insert("forwarded", "for=203.0.113.7".parse().unwrap());
+    let client: SocketAddr = "[::1]:54321".parse().unwrap();
+
+    forwarded(&mut req, client, "https");
+
+    assert_eq!(header(&req, "x-forwarded-proto"), Some("https"));
+    assert_eq!(header(&req, "x-forwarded-for"), Some("203.0.113.7, ::1"));
+    assert_eq!(
+        header(&req, "forwarded"),
+        Some("for=203.0.113.7, for=\"[::1]\";proto=https;host=\"foo.localhost\"")
+    );
+}
+
+#[test]
+fn forwarded_host_from_http2_authority() {
+    let mut req = Request::get("https://foo.localhost:8443/path")
+        .body(Body::empty())
+        .unwrap();
+    let client: SocketAddr = "127.0.0.1:54321".parse().unwrap();
+
+    forwarded(&mut req, client, "https");
+
+    assert_eq!(header(&req, "x-forwarded-host"), Some("foo.localhost:8443"));
+    assert_eq!(
+        header(&req, "forwarded"),
+        Some("for=127.0.0.1;proto=https;host=\"foo.localhost:8443\"")
+    );
+}
+
+#[test]
+fn forwarded_escapes_host() {
+    let mut req = request(Some("foo\";for=203.0.113.1;proto=\\http"));
+    let client: SocketAddr = "192.0.2.1:54321".parse().unwrap();
+
+    forwarded(&mut req, client, "https");
+
+    assert_eq!(
+        header(&req, "forwarded"),
+        Some("for=192.0.2.1;proto=https;host=\"foo\\\";for=203.0.113.1;proto=\\\\http\"")
+    );
+}
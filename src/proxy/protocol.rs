@@ -0,0 +1,135 @@
+//! HAProxy [PROXY protocol][spec] support
+//!
+//! [spec]: https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+const PP2_TYPE_ALPN: u8 = 0x01;
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+
+/// Version of the PROXY protocol header sent downstream
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, clap::ValueEnum,
+)]
+pub enum Version {
+    /// Human readable header, carries only addresses
+    V1,
+    /// Binary header, additionally carries SNI and negotiated ALPN
+    V2,
+}
+
+/// Information about the original connection passed in the header
+#[derive(Debug, Clone)]
+pub struct Header<'a> {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub sni: Option<&'a str>,
+    pub alpn: Option<&'a [u8]>,
+}
+
+impl Header<'_> {
+    pub fn encode(&self, version: Version) -> Vec<u8> {
+        match version {
+            Version::V1 => self.encode_v1(),
+            Version::V2 => self.encode_v2(),
+        }
+    }
+
+    /// Write header to the downstream connection
+    pub async fn write_to<W>(&self, version: Version, out: &mut W) -> std::io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        tracing::trace!(?version, header = ?self, "PROXY protocol");
+        out.write_all(&self.encode(version)).await
+    }
+
+    /// Source and destination addresses in the same address family
+    fn addresses(&self) -> (IpAddr, IpAddr) {
+        match (
+            self.source.ip().to_canonical(),
+            self.destination.ip().to_canonical(),
+        ) {
+            (src @ IpAddr::V4(_), dst @ IpAddr::V4(_)) => (src, dst),
+            (src, dst) => (to_ipv6(src), to_ipv6(dst)),
+        }
+    }
+
+    fn encode_v1(&self) -> Vec<u8> {
+        let (src, dst) = self.addresses();
+        let family = if src.is_ipv4() { "TCP4" } else { "TCP6" };
+
+        format!(
+            "PROXY {} {} {} {} {}\r\n",
+            family,
+            src,
+            dst,
+            self.source.port(),
+            self.destination.port()
+        )
+        .into_bytes()
+    }
+
+    fn encode_v2(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+
+        let family = match self.addresses() {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                body.extend_from_slice(&src.octets());
+                body.extend_from_slice(&dst.octets());
+                0x11
+            }
+            (src, dst) => {
+                body.extend_from_slice(&to_ipv6_octets(src));
+                body.extend_from_slice(&to_ipv6_octets(dst));
+                0x21
+            }
+        };
+        body.extend_from_slice(&self.source.port().to_be_bytes());
+        body.extend_from_slice(&self.destination.port().to_be_bytes());
+
+        if let Some(alpn) = self.alpn {
+            tlv(&mut body, PP2_TYPE_ALPN, alpn);
+        }
+        if let Some(sni) = self.sni {
+            tlv(&mut body, PP2_TYPE_AUTHORITY, sni.as_bytes());
+        }
+
+        let mut out = Vec::with_capacity(16 + body.len());
+        out.extend_from_slice(&SIGNATURE);
+        // Version 2, PROXY command
+        out.push(0x21);
+        out.push(family);
+        out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        out.extend_from_slice(&body);
+
+        out
+    }
+}
+
+fn tlv(out: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    out.push(kind);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+fn to_ipv6(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V6(ip.to_ipv6_mapped()),
+        ip => ip,
+    }
+}
+
+fn to_ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match to_ipv6(ip) {
+        IpAddr::V6(ip) => ip.octets(),
+        IpAddr::V4(_) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,141 @@
+use super::{Header, Version};
+
+fn header(source: &str, destination: &str) -> Header<'static> {
+    Header {
+        source: source.parse().unwrap(),
+        destination: destination.parse().unwrap(),
+        sni: None,
+        alpn: None,
+    }
+}
+
+/// Fixed part of the version 2 header, up to the length
+fn v2_prefix(family: u8, len: u16) -> Vec<u8> {
+    let mut out = vec![
+        0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a,
+    ];
+    out.extend_from_slice(&[0x21, family]);
+    out.extend_from_slice(&len.to_be_bytes());
+    out
+}
+
+#[test]
+fn v1_tcp4() {
+    // Example from section 2.1 of the specification
+    let header = header("192.168.0.1:56324", "192.168.0.11:443");
+
+    assert_eq!(
+        header.encode(Version::V1),
+        b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n"
+    );
+}
+
+#[test]
+fn v1_longest_tcp4_and_tcp6() {
+    let tcp4 = header("255.255.255.255:65535", "255.255.255.255:65535").encode(Version::V1);
+    let tcp6 = header(
+        "[ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff]:65535",
+        "[ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff]:65535",
+    )
+    .encode(Version::V1);
+
+    assert_eq!(
+        tcp4,
+        b"PROXY TCP4 255.255.255.255 255.255.255.255 65535 65535\r\n"
+    );
+    assert_eq!(
+        tcp6,
+        &b"PROXY TCP6 ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff \
+        ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff 65535 65535\r\n"[..]
+    );
+    // Both fit in the 107 bytes the receivers need to buffer
+    assert_eq!(tcp4.len(), 56);
+    assert_eq!(tcp6.len(), 104);
+}
+
+#[test]
+fn v1_mixed_families_use_tcp6() {
+    let header = header("[::ffff:192.0.2.1]:1234", "[2001:db8::1]:443");
+
+    assert_eq!(
+        header.encode(Version::V1),
+        b"PROXY TCP6 ::ffff:192.0.2.1 2001:db8::1 1234 443\r\n"
+    );
+}
+
+#[test]
+fn v1_ipv4_mapped_on_both_sides_use_tcp4() {
+    let header = header("[::ffff:192.0.2.1]:1234", "[::ffff:192.0.2.2]:443");
+
+    assert_eq!(
+        header.encode(Version::V1),
+        b"PROXY TCP4 192.0.2.1 192.0.2.2 1234 443\r\n"
+    );
+}
+
+#[test]
+fn v2_tcp4() {
+    let header = header("192.168.0.1:56324", "192.168.0.11:443");
+
+    let mut expected = v2_prefix(0x11, 12);
+    expected.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 11, 0xdc, 0x04, 0x01, 0xbb]);
+
+    assert_eq!(header.encode(Version::V2), expected);
+}
+
+#[test]
+fn v2_tcp6() {
+    let header = header("[2001:db8::1]:56324", "[2001:db8::2]:443");
+
+    let mut expected = v2_prefix(0x21, 36);
+    expected.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    expected.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    expected.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+
+    assert_eq!(header.encode(Version::V2), expected);
+}
+
+#[test]
+fn v2_mixed_families_map_ipv4_to_ipv6() {
+    let header = header("192.0.2.1:1234", "[::1]:443");
+
+    let mut expected = v2_prefix(0x21, 36);
+    expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 0, 2, 1]);
+    expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    expected.extend_from_slice(&[0x04, 0xd2, 0x01, 0xbb]);
+
+    assert_eq!(header.encode(Version::V2), expected);
+}
+
+#[test]
+fn v2_tlvs_follow_addresses_and_count_in_length() {
+    let header = Header {
+        sni: Some("foo.localhost"),
+        alpn: Some(b"h2"),
+        ..header("127.0.0.1:1234", "127.0.0.1:443")
+    };
+
+    // 12 bytes of addresses, 3 + 2 of ALPN and 3 + 13 of authority
+    let mut expected = v2_prefix(0x11, 33);
+    expected.extend_from_slice(&[127, 0, 0, 1, 127, 0, 0, 1, 0x04, 0xd2, 0x01, 0xbb]);
+    expected.extend_from_slice(&[0x01, 0x00, 0x02]);
+    expected.extend_from_slice(b"h2");
+    expected.extend_from_slice(&[0x02, 0x00, 0x0d]);
+    expected.extend_from_slice(b"foo.localhost");
+
+    assert_eq!(header.encode(Version::V2), expected);
+}
+
+#[test]
+fn v1_ignores_tlvs() {
+    let header = Header {
+        sni: Some("foo.localhost"),
+        alpn: Some(b"h2"),
+        ..header("127.0.0.1:1234", "127.0.0.1:443")
+    };
+
+    assert_eq!(
+        header.encode(Version::V1),
+        b"PROXY TCP4 127.0.0.1 127.0.0.1 1234 443\r\n"
+    );
+}
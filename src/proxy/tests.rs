@@ -0,0 +1,725 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{Connector, Context, Downstream, Http, Options, Proxy, TcpProxy, TlsTerminating, Type};
+use crate::registry::RegistryStore;
+use crate::service::{Service, State};
+
+const DOMAIN: &str = "foo.localhost";
+
+const UPGRADE_REQUEST: &str = "GET /socket HTTP/1.1\r\n\
+    Host: foo.localhost\r\n\
+    Connection: Upgrade\r\n\
+    Upgrade: websocket\r\n\
+    Sec-WebSocket-Version: 13\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+    \r\n";
+
+const UPGRADE_RESPONSE: &str = "HTTP/1.1 101 Switching Protocols\r\n\
+    Connection: Upgrade\r\n\
+    Upgrade: websocket\r\n\
+    Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+    \r\n";
+
+fn ca() -> rcgen::Certificate {
+    let mut params = rcgen::CertificateParams::new(vec![]);
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    params.key_usages = vec![rcgen::KeyUsagePurpose::KeyCertSign];
+
+    rcgen::Certificate::from_params(params).unwrap()
+}
+
+/// Registry with single service for the application at `addr`
+fn registry(addr: SocketAddr, state: State) -> RegistryStore {
+    let mut service = Service::new(DOMAIN, addr, Type::Passthrough, &Options::default());
+    service.state = state;
+
+    Arc::new(tokio::sync::RwLock::new(HashMap::from([(
+        DOMAIN.to_owned(),
+        service,
+    )])))
+}
+
+async fn backend(services: &RegistryStore, wait: Duration) -> Connector {
+    let service = services.read().await[DOMAIN].clone();
+
+    Connector::new(DOMAIN, service, services.clone(), wait)
+}
+
+fn connector(ca: &rcgen::Certificate) -> tokio_rustls::TlsConnector {
+    connector_with_alpn(ca, &[])
+}
+
+fn connector_with_alpn(ca: &rcgen::Certificate, alpn: &[&[u8]]) -> tokio_rustls::TlsConnector {
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+        .unwrap();
+
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|proto| proto.to_vec()).collect();
+
+    tokio_rustls::TlsConnector::from(Arc::new(config))
+}
+
+/// Read HTTP message head, returns it with all data that was read past it
+async fn read_head(stream: &mut (impl AsyncRead + Unpin)) -> (String, Vec<u8>) {
+    let mut data = Vec::new();
+    let mut buf = [0; 1024];
+
+    loop {
+        let len = stream.read(&mut buf).await.unwrap();
+        assert_ne!(len, 0, "connection closed before end of head");
+        data.extend_from_slice(&buf[..len]);
+
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = data.split_off(pos + 4);
+            return (String::from_utf8(data).unwrap(), rest);
+        }
+    }
+}
+
+/// WebSocket-like application that accepts upgrade and then echoes everything back
+async fn echo_app(listener: TcpListener) -> String {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let (head, rest) = read_head(&mut stream).await;
+
+    stream.write_all(UPGRADE_RESPONSE.as_bytes()).await.unwrap();
+    stream.write_all(&rest).await.unwrap();
+
+    let mut buf = [0; 1024];
+    loop {
+        match stream.read(&mut buf).await.unwrap() {
+            0 => return head,
+            len => stream.write_all(&buf[..len]).await.unwrap(),
+        }
+    }
+}
+
+/// Start proxy for single connection and return address of it and application request head
+async fn start(proxy: Arc<TcpProxy>) -> (std::net::SocketAddr, tokio::task::JoinHandle<String>) {
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let app_addr = app.local_addr().unwrap();
+    let app = tokio::spawn(echo_app(app));
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = front.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (up, _) = front.accept().await.unwrap();
+        let down = backend(&registry(app_addr, State::Running), Duration::ZERO).await;
+        let ctx = Context {
+            sni: Some(DOMAIN.into()),
+            lan: false,
+        };
+        let _ = proxy.run(up, down, ctx).await;
+    });
+
+    (addr, app)
+}
+
+async fn upgrade(
+    addr: std::net::SocketAddr,
+    ca: &rcgen::Certificate,
+) -> impl AsyncRead + AsyncWrite + Unpin {
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = rustls::ServerName::try_from(DOMAIN).unwrap();
+    let mut stream = connector(ca).connect(name, tcp).await.unwrap();
+
+    stream.write_all(UPGRADE_REQUEST.as_bytes()).await.unwrap();
+    let (head, rest) = read_head(&mut stream).await;
+
+    assert!(
+        head.starts_with("HTTP/1.1 101"),
+        "unexpected response: {head}"
+    );
+    assert!(head.to_lowercase().contains("upgrade: websocket"));
+    assert!(rest.is_empty());
+
+    stream
+}
+
+async fn assert_echo(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) {
+    for message in [&b"ping"[..], b"\x81\x05hello", &[0; 8 * 1024]] {
+        stream.write_all(message).await.unwrap();
+
+        let mut buf = vec![0; message.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, message);
+    }
+}
+
+#[tokio::test]
+async fn upgrade_through_tls_terminating() {
+    let ca = ca();
+    let proxy = Arc::new(TlsTerminating::from_ca(DOMAIN.into(), &ca));
+    let (addr, app) = start(proxy).await;
+
+    let mut stream = upgrade(addr, &ca).await;
+    assert_echo(&mut stream).await;
+    stream.shutdown().await.unwrap();
+    drop(stream);
+
+    let head = app.await.unwrap();
+    assert!(head.starts_with("GET /socket HTTP/1.1\r\n"));
+}
+
+#[tokio::test]
+async fn upgrade_through_http() {
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca).with_alpn(Downstream::H2c.alpn());
+    let proxy = Arc::new(Http::new(tls, Downstream::Http1));
+    let (addr, app) = start(proxy).await;
+
+    let mut stream = upgrade(addr, &ca).await;
+    assert_echo(&mut stream).await;
+    stream.shutdown().await.unwrap();
+    drop(stream);
+
+    let head = app.await.unwrap().to_lowercase();
+    assert!(head.starts_with("get /socket http/1.1\r\n"));
+    assert!(head.contains("connection: upgrade\r\n"));
+    assert!(head.contains("upgrade: websocket\r\n"));
+    assert!(head.contains("x-forwarded-proto: https\r\n"));
+    assert!(head.contains("x-forwarded-for: 127.0.0.1\r\n"));
+}
+
+#[tokio::test]
+async fn upgrade_through_http_to_h2c_application() {
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca).with_alpn(Downstream::H2c.alpn());
+    let proxy = Arc::new(Http::new(tls, Downstream::H2c));
+    let (addr, app) = start(proxy).await;
+
+    let mut stream = upgrade(addr, &ca).await;
+    assert_echo(&mut stream).await;
+    stream.shutdown().await.unwrap();
+    drop(stream);
+
+    let head = app.await.unwrap().to_lowercase();
+    assert!(head.starts_with("get /socket http/1.1\r\n"));
+    assert!(head.contains("host: foo.localhost\r\n"));
+    assert!(head.contains("upgrade: websocket\r\n"));
+}
+
+/// Serve single connection with the proxy and return its address
+async fn serve_once(proxy: Arc<TcpProxy>, app_addr: SocketAddr) -> SocketAddr {
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = front.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (up, _) = front.accept().await.unwrap();
+        let down = backend(&registry(app_addr, State::Running), Duration::ZERO).await;
+        let _ = proxy.run(up, down, Context::default()).await;
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn http2_client_to_http1_application() {
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca).with_alpn(Downstream::H2c.alpn());
+    let proxy = Arc::new(Http::new(tls, Downstream::Http1));
+
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let app_addr = app.local_addr().unwrap();
+    let app = tokio::spawn(async move {
+        let (mut stream, _) = app.accept().await.unwrap();
+        let (head, _) = read_head(&mut stream).await;
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+            .await
+            .unwrap();
+        head.to_lowercase()
+    });
+    let addr = serve_once(proxy, app_addr).await;
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = rustls::ServerName::try_from(DOMAIN).unwrap();
+    let stream = connector_with_alpn(&ca, &[b"h2", b"http/1.1"])
+        .connect(name, tcp)
+        .await
+        .unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(stream)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    let req = hyper::Request::get("https://foo.localhost/path?query")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let response = sender.send_request(req).await.unwrap();
+
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(response.version(), hyper::Version::HTTP_2);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"ok");
+
+    let head = app.await.unwrap();
+    assert!(
+        head.starts_with("get /path?query http/1.1\r\n"),
+        "unexpected request: {head}"
+    );
+    assert!(head.contains("host: foo.localhost\r\n"));
+}
+
+#[tokio::test]
+async fn http1_client_to_h2c_application() {
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca).with_alpn(Downstream::H2c.alpn());
+    let proxy = Arc::new(Http::new(tls, Downstream::H2c));
+
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let app_addr = app.local_addr().unwrap();
+    let app = tokio::spawn(async move {
+        let (stream, _) = app.accept().await.unwrap();
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let service = hyper::service::service_fn(move |req: hyper::Request<hyper::Body>| {
+            let _ = sender.send((req.version(), req.uri().clone()));
+            async { Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from("ok"))) }
+        });
+        let connection = hyper::server::conn::Http::new()
+            .http2_only(true)
+            .serve_connection(stream, service);
+        tokio::spawn(connection);
+        received.recv().await.unwrap()
+    });
+    let addr = serve_once(proxy, app_addr).await;
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = rustls::ServerName::try_from(DOMAIN).unwrap();
+    let mut stream = connector_with_alpn(&ca, &[b"http/1.1"])
+        .connect(name, tcp)
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /path HTTP/1.1\r\nHost: foo.localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let (head, _) = read_head(&mut stream).await;
+    assert!(
+        head.starts_with("HTTP/1.1 200"),
+        "unexpected response: {head}"
+    );
+
+    let (version, uri) = app.await.unwrap();
+    assert_eq!(version, hyper::Version::HTTP_2);
+    assert_eq!(uri, "http://foo.localhost/path");
+}
+
+/// Bad gateway page of the application which is not running, with some output
+async fn dead_application_page(ctx: Context) -> String {
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca);
+    let proxy = Arc::new(Http::new(tls, Downstream::Http1));
+
+    let old = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let app_addr = old.local_addr().unwrap();
+    drop(old);
+    let services = registry(app_addr, State::Running);
+    {
+        let services = services.read().await;
+        let logs = &services[DOMAIN].logs;
+        logs.push(crate::logs::Stream::Stdout, "Listening".into());
+        logs.push(crate::logs::Stream::Stderr, "panic: <oops> & exit".into());
+    }
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = front.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (up, _) = front.accept().await.unwrap();
+        let down = backend(&services, Duration::ZERO).await;
+        let _ = proxy.run(up, down, ctx).await;
+    });
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = rustls::ServerName::try_from(DOMAIN).unwrap();
+    let stream = connector(&ca).connect(name, tcp).await.unwrap();
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await.unwrap();
+    tokio::spawn(connection);
+    let req = hyper::Request::get("/")
+        .header("host", DOMAIN)
+        .body(hyper::Body::empty())
+        .unwrap();
+    let response = sender.send_request(req).await.unwrap();
+
+    assert_eq!(response.status(), hyper::StatusCode::BAD_GATEWAY);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn dead_application_shows_its_recent_output() {
+    let page = dead_application_page(Context::default()).await;
+
+    assert!(page.contains(">Listening\n</span>"), "{page}");
+    assert!(page.contains("panic: &lt;oops&gt; &amp; exit"), "{page}");
+    assert!(!page.contains("<oops>"));
+}
+
+#[tokio::test]
+async fn dead_application_hides_its_output_in_local_network() {
+    let ctx = Context {
+        sni: Some(DOMAIN.into()),
+        lan: true,
+    };
+    let page = dead_application_page(ctx).await;
+
+    assert!(page.contains(DOMAIN), "{page}");
+    assert!(!page.contains("Listening"), "{page}");
+    assert!(!page.contains("oops"), "{page}");
+}
+
+#[tokio::test]
+async fn requests_after_rejected_upgrade_use_same_connection() {
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca);
+    let proxy = Arc::new(Http::new(tls, Downstream::Http1));
+
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let app_addr = app.local_addr().unwrap();
+    let app = tokio::spawn(async move {
+        let (mut stream, _) = app.accept().await.unwrap();
+        let mut paths = Vec::new();
+        for _ in 0..2 {
+            let (head, _) = read_head(&mut stream).await;
+            paths.push(head.split(' ').nth(1).unwrap().to_owned());
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        }
+        paths
+    });
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = front.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (up, _) = front.accept().await.unwrap();
+        let down = backend(&registry(app_addr, State::Running), Duration::ZERO).await;
+        let _ = proxy.run(up, down, Context::default()).await;
+    });
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = rustls::ServerName::try_from(DOMAIN).unwrap();
+    let mut stream = connector(&ca).connect(name, tcp).await.unwrap();
+
+    stream.write_all(UPGRADE_REQUEST.as_bytes()).await.unwrap();
+    let (head, _) = read_head(&mut stream).await;
+    assert!(
+        head.starts_with("HTTP/1.1 200"),
+        "unexpected response: {head}"
+    );
+
+    stream
+        .write_all(b"GET /other HTTP/1.1\r\nHost: foo.localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let (head, _) = read_head(&mut stream).await;
+    assert!(
+        head.starts_with("HTTP/1.1 200"),
+        "unexpected response: {head}"
+    );
+
+    assert_eq!(app.await.unwrap(), ["/socket", "/other"]);
+}
+
+#[tokio::test]
+async fn requests_after_application_restart_reconnect() {
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca);
+    let proxy = Arc::new(Http::new(tls, Downstream::Http1));
+
+    /// Respond to single request and close the connection along with the listener
+    async fn respond_once(app: TcpListener) {
+        let (mut stream, _) = app.accept().await.unwrap();
+        read_head(&mut stream).await;
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+    }
+
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let services = registry(app.local_addr().unwrap(), State::Running);
+    let first = tokio::spawn(respond_once(app));
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = front.local_addr().unwrap();
+    let down = backend(&services, Duration::from_secs(5)).await;
+    tokio::spawn(async move {
+        let (up, _) = front.accept().await.unwrap();
+        let _ = proxy.run(up, down, Context::default()).await;
+    });
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = rustls::ServerName::try_from(DOMAIN).unwrap();
+    let mut stream = connector(&ca).connect(name, tcp).await.unwrap();
+    let request = b"GET / HTTP/1.1\r\nHost: foo.localhost\r\n\r\n";
+
+    stream.write_all(request).await.unwrap();
+    let (head, _) = read_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    first.await.unwrap();
+
+    // Application is restarting and comes back on different port
+    services.write().await.get_mut(DOMAIN).unwrap().state = State::Restarting;
+    stream.write_all(request).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    {
+        let mut services = services.write().await;
+        let service = services.get_mut(DOMAIN).unwrap();
+        service.addr = app.local_addr().unwrap();
+        service.state = State::Running;
+    }
+    tokio::time::timeout(Duration::from_secs(5), respond_once(app))
+        .await
+        .expect("proxy connects to the restarted application");
+
+    let (head, _) = read_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+}
+
+#[tokio::test]
+async fn connect_to_running_service_fails_immediately() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let services = registry(addr, State::Running);
+
+    let started = std::time::Instant::now();
+    let err = backend(&services, Duration::from_secs(5))
+        .await
+        .connect()
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.error.kind(), std::io::ErrorKind::ConnectionRefused);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn connect_waits_for_restarting_service() {
+    let old = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let services = registry(old.local_addr().unwrap(), State::Restarting);
+    drop(old);
+
+    let connector = backend(&services, Duration::from_secs(5)).await;
+    let connect = tokio::spawn(async move { connector.connect().await });
+
+    // Application comes back on different port
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    {
+        let mut services = services.write().await;
+        let service = services.get_mut(DOMAIN).unwrap();
+        service.addr = app.local_addr().unwrap();
+        service.state = State::Running;
+    }
+
+    let stream = connect.await.unwrap().unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), app.local_addr().unwrap());
+}
+
+#[tokio::test]
+async fn connect_gives_up_after_wait() {
+    let old = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let services = registry(old.local_addr().unwrap(), State::Starting);
+    drop(old);
+
+    let started = std::time::Instant::now();
+    let err = backend(&services, Duration::from_millis(300))
+        .await
+        .connect()
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.service.state, State::Starting);
+    assert!(started.elapsed() <= Duration::from_millis(300));
+}
+
+/// Stand-in for OpenTelemetry collector, passes bodies of the received requests
+async fn collector() -> (
+    SocketAddr,
+    tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>,
+) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let service = hyper::service::make_service_fn(move |_| {
+        let sender = sender.clone();
+        async move {
+            Ok::<_, hyper::Error>(hyper::service::service_fn(
+                move |req: hyper::Request<hyper::Body>| {
+                    let sender = sender.clone();
+                    async move {
+                        assert_eq!(req.uri().path(), "/v1/traces");
+                        let body = hyper::body::to_bytes(req.into_body()).await?;
+                        let _ = sender.send(serde_json::from_slice(&body).unwrap());
+                        Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::empty()))
+                    }
+                },
+            ))
+        }
+    });
+    let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    (addr, receiver)
+}
+
+#[tokio::test]
+async fn request_span_is_propagated_and_exported() {
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    let (collector_addr, mut spans) = collector().await;
+    crate::telemetry::init(&format!("http://{}", collector_addr)).unwrap();
+
+    let ca = ca();
+    let tls = TlsTerminating::from_ca(DOMAIN.into(), &ca);
+    let proxy = Arc::new(Http::new(tls, Downstream::Http1));
+
+    let app = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let app_addr = app.local_addr().unwrap();
+    let app = tokio::spawn(async move {
+        let (mut stream, _) = app.accept().await.unwrap();
+        let (head, _) = read_head(&mut stream).await;
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+            .await
+            .unwrap();
+        head.to_lowercase()
+    });
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = front.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (up, _) = front.accept().await.unwrap();
+        let down = backend(&registry(app_addr, State::Running), Duration::ZERO).await;
+        let _ = proxy.run(up, down, Context::default()).await;
+    });
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = rustls::ServerName::try_from(DOMAIN).unwrap();
+    let mut stream = connector(&ca).connect(name, tcp).await.unwrap();
+    let request = format!(
+        "GET /traced HTTP/1.1\r\n\
+        Host: foo.localhost\r\n\
+        traceparent: 00-{TRACE_ID}-{PARENT_ID}-01\r\n\
+        tracestate: vendor=value\r\n\
+        \r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let (head, _) = read_head(&mut stream).await;
+    assert!(
+        head.starts_with("HTTP/1.1 200"),
+        "unexpected response: {head}"
+    );
+
+    let head = app.await.unwrap();
+    let traceparent = head
+        .lines()
+        .find_map(|line| line.strip_prefix("traceparent: "))
+        .expect("traceparent passed downstream");
+    let context = crate::telemetry::TraceContext::parse(traceparent).unwrap();
+    let span_id = traceparent.split('-').nth(2).unwrap();
+    assert!(traceparent.starts_with(&format!("00-{TRACE_ID}-")));
+    assert_ne!(span_id, PARENT_ID);
+    assert!(context.sampled);
+    assert!(head.contains("tracestate: vendor=value\r\n"));
+
+    // Other tests can export their spans as well, so look for the one of our trace
+    let span = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let request = spans.recv().await.unwrap();
+            let found = request["resourceSpans"][0]["scopeSpans"][0]["spans"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|span| span["traceId"] == TRACE_ID)
+                .cloned();
+            if let Some(span) = found {
+                return span;
+            }
+        }
+    })
+    .await
+    .expect("span exported");
+
+    assert_eq!(span["spanId"], span_id);
+    assert_eq!(span["parentSpanId"], PARENT_ID);
+    assert_eq!(span["traceState"], "vendor=value");
+    assert_eq!(span["kind"], 2);
+    let status = span["attributes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|attribute| attribute["key"] == "http.response.status_code")
+        .unwrap();
+    assert_eq!(status["value"]["intValue"], "200");
+}
+
+#[tokio::test]
+async fn certificates_are_signed_by_loaded_ca() {
+    let ca = ca();
+    let dir = std::env::temp_dir().join(format!("dolores-ca-{:x}", rand::random::<u64>()));
+    std::fs::create_dir(&dir).unwrap();
+    let (cert, key) = (dir.join("ca.crt"), dir.join("ca.key"));
+    std::fs::write(&cert, ca.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&key, ca.serialize_private_key_pem()).unwrap();
+
+    let loaded = super::load_ca(&cert, &key).unwrap();
+    let proxy = Arc::new(TlsTerminating::from_ca(DOMAIN.into(), &loaded));
+    let (addr, app) = start(proxy).await;
+
+    // Client trusts the original certificate of the CA
+    let mut stream = upgrade(addr, &ca).await;
+    assert_echo(&mut stream).await;
+    stream.shutdown().await.unwrap();
+    drop(stream);
+    app.await.unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_ca_is_reported_with_path() {
+    let dir = std::env::temp_dir().join(format!("dolores-ca-{:x}", rand::random::<u64>()));
+    std::fs::create_dir(&dir).unwrap();
+    let (cert, key) = (dir.join("ca.crt"), dir.join("ca.key"));
+    std::fs::write(&cert, "not a certificate").unwrap();
+    std::fs::write(&key, ca().serialize_private_key_pem()).unwrap();
+
+    let err = super::load_ca(&cert, &key).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("ca.crt"), "{}", err);
+
+    let err = super::load_ca(&cert, &dir.join("missing.key"))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(err.to_string().contains("missing.key"), "{}", err);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn metrics_path_has_to_be_absolute() {
+    assert_eq!(super::metrics_path("/metrics").unwrap(), "/metrics");
+    assert!(super::metrics_path("metrics").is_err());
+    assert!(super::metrics_path("@evil.example/metrics").is_err());
+}
@@ -1,23 +1,72 @@
 use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
+use once_cell::sync::OnceCell;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
 use tokio_rustls::TlsAcceptor;
 
+use super::protocol;
+use crate::access_log;
+
+/// Certificate Authority signing the generated certificates, given to the server
+static CA: OnceCell<rcgen::Certificate> = OnceCell::new();
+
+/// Sign certificates generated from now on by the CA instead of self-signing them
+pub fn init_ca(ca: rcgen::Certificate) {
+    if CA.set(ca).is_err() {
+        tracing::warn!("Certificate Authority already set");
+    }
+}
+
+/// Load PEM encoded Certificate Authority certificate and its private key
+pub fn load_ca(cert: &Path, key: &Path) -> io::Result<rcgen::Certificate> {
+    let invalid = |path: &Path, err: rcgen::RcgenError| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid {}: {}", path.display(), err),
+        )
+    };
+    let read = |path: &Path| {
+        std::fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    };
+
+    let key_pair = rcgen::KeyPair::from_pem(&read(key)?).map_err(|err| invalid(key, err))?;
+    let params = rcgen::CertificateParams::from_ca_cert_pem(&read(cert)?, key_pair)
+        .map_err(|err| invalid(cert, err))?;
+
+    rcgen::Certificate::from_params(params).map_err(|err| invalid(cert, err))
+}
+
 /// TLS terminating proxy
 ///
 /// This proxy will terminate TLS on the boundary and will pass raw TCP communication downstream.
 /// It supports:
 ///
 /// - Self-signed certificates generated on demand
-/// - Generated certificates that are signed by the given CA (WIP)
+/// - Generated certificates that are signed by the given CA
 /// - Passed certificate (TODO)
+///
+/// Optionally it can inform downstream about the original connection using PROXY protocol.
 #[derive(Clone)]
 pub struct TlsTerminating {
+    config: Arc<rustls::ServerConfig>,
     acceptor: TlsAcceptor,
+    proxy_protocol: Option<protocol::Version>,
 }
 
 impl TlsTerminating {
+    /// Certificate generated for the domain, signed by the CA given to the server if there is one
+    pub fn generated(domain: super::Domain) -> Self {
+        match CA.get() {
+            Some(ca) => Self::from_ca(domain, ca),
+            None => Self::self_signed(domain),
+        }
+    }
+
     pub fn self_signed(domain: super::Domain) -> Self {
         let cert = rcgen::generate_simple_self_signed(domain).unwrap();
         let certs = vec![rustls::Certificate(cert.serialize_der().unwrap())];
@@ -43,34 +92,104 @@ impl TlsTerminating {
             .with_single_cert(certs, priv_key)
             .expect("Bad certificate/key");
 
-        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let config = Arc::new(config);
+        let acceptor = TlsAcceptor::from(config.clone());
 
-        TlsTerminating { acceptor }
+        TlsTerminating {
+            config,
+            acceptor,
+            proxy_protocol: None,
+        }
+    }
+
+    /// Advertise given protocols, in order of preference, during ALPN negotiation
+    pub fn with_alpn(self, protocols: &[&[u8]]) -> Self {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = protocols.iter().map(|proto| proto.to_vec()).collect();
+        let config = Arc::new(config);
+
+        TlsTerminating {
+            acceptor: TlsAcceptor::from(config.clone()),
+            config,
+            ..self
+        }
+    }
+
+    /// Send PROXY protocol header of given version before passing any data downstream
+    pub fn with_proxy_protocol(self, proxy_protocol: Option<protocol::Version>) -> Self {
+        TlsTerminating {
+            proxy_protocol,
+            ..self
+        }
+    }
+
+    /// Perform TLS handshake with the client
+    pub(crate) async fn accept(&self, up: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        self.acceptor.accept(up).await
+    }
+
+    /// Encoded PROXY protocol header, if enabled, describing already accepted connection
+    pub(crate) fn proxy_header(&self, up: &TlsStream<TcpStream>) -> io::Result<Option<Vec<u8>>> {
+        let version = match self.proxy_protocol {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        let (stream, connection) = up.get_ref();
+        let header = protocol::Header {
+            source: stream.peer_addr()?,
+            destination: stream.local_addr()?,
+            sni: connection.sni_hostname(),
+            alpn: connection.alpn_protocol(),
+        };
+        tracing::trace!(?version, ?header, "PROXY protocol");
+
+        Ok(Some(header.encode(version)))
     }
 }
 
 #[async_trait]
 impl super::Proxy for TlsTerminating {
-    type Up = tokio::net::TcpStream;
-    type Down = tokio::net::TcpStream;
+    type Up = TcpStream;
+    type Down = super::Connector;
 
-    async fn run(&self, up: Self::Up, mut down: Self::Down) -> io::Result<()> {
+    async fn run(&self, up: Self::Up, down: Self::Down, ctx: super::Context) -> io::Result<()> {
         tracing::debug!("Proxy started");
-        let up_addr = up.local_addr().unwrap();
-        let down_addr = down.peer_addr().unwrap();
+        let mut log = access_log::Connection::start(down.service(), down.key(), up.peer_addr()?);
+        let up_addr = up.local_addr()?;
         let mut up_buf = [0; 4 * 1024];
         let mut down_buf = [0; 4 * 1024];
-        let mut up = self.acceptor.accept(up).await?;
+        let up = self
+            .accept(up)
+            .await
+            .inspect_err(|_| crate::metrics::tls_handshake_failed(down.key()))?;
+
+        let mut down_stream = match down.connect().await {
+            Ok(down) => down,
+            Err(err) => {
+                tracing::error!(%err, "Cannot connect to service");
+                return crate::dashboard::bad_gateway(up, &err, ctx.lan).await;
+            }
+        };
+        let down_addr = down_stream.peer_addr()?;
+
+        if let Some(header) = self.proxy_header(&up)? {
+            down_stream.write_all(&header).await?;
+        }
+
+        let mut up = crate::metrics::Counted::new(up, down.key());
+        let mut down = down_stream;
 
         loop {
             // Read from any connection and write to the another one
             let finished = tokio::select! {
                 result = up.read(&mut up_buf) => {
                     tracing::trace!("{} -> {}", up_addr, down_addr);
+                    log.transferred(*result.as_ref().unwrap_or(&0) as u64, 0);
                     copy(result, &up_buf, &mut down).await?
                 }
                 result = down.read(&mut down_buf) => {
                     tracing::trace!("{} <- {}", up_addr, down_addr);
+                    log.transferred(0, *result.as_ref().unwrap_or(&0) as u64);
                     copy(result, &down_buf, &mut up).await?
                 }
             };
@@ -95,7 +214,7 @@ async fn copy(
         Ok(len) => {
             let data = std::str::from_utf8(&buf[..len]);
             tracing::trace!(?data, "Received");
-            out.write(&buf[..len]).await?;
+            out.write_all(&buf[..len]).await?;
 
             Ok(false)
         }
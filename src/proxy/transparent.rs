@@ -1,37 +1,62 @@
 use tokio::io;
-use tokio::io::AsyncWriteExt;
+
+use super::protocol;
+use crate::access_log;
 
 /// Transparent proxy
 ///
 /// This proxy will forward all data **as is** so it is the downstream responsibility to handle TLS
 /// termination
-#[derive(Clone, Debug)]
-pub struct Transparent;
+#[derive(Clone, Debug, Default)]
+pub struct Transparent {
+    proxy_protocol: Option<protocol::Version>,
+}
+
+impl Transparent {
+    pub fn new(proxy_protocol: Option<protocol::Version>) -> Self {
+        Transparent { proxy_protocol }
+    }
+}
 
 #[async_trait]
 impl super::Proxy for Transparent {
     type Up = tokio::net::TcpStream;
-    type Down = tokio::net::TcpStream;
+    type Down = super::Connector;
 
-    async fn run(&self, mut up: Self::Up, mut down: Self::Down) -> io::Result<()> {
+    async fn run(&self, up: Self::Up, down: Self::Down, ctx: super::Context) -> io::Result<()> {
         tracing::debug!("Proxy started");
+        let mut log = access_log::Connection::start(down.service(), down.key(), up.peer_addr()?);
+        let key = down.key().to_owned();
 
-        let (mut ru, mut wu) = up.split();
-        let (mut rd, mut wd) = down.split();
+        let mut down = match down.connect().await {
+            Ok(down) => down,
+            Err(err) => {
+                tracing::error!(%err, "Cannot connect to service");
+                // Client expects TLS, so present our own certificate to be able to show the error
+                let host = ctx.sni.unwrap_or_else(|| err.service.domain.clone());
+                let up = super::TlsTerminating::generated(host.into())
+                    .accept(up)
+                    .await?;
+                return crate::dashboard::bad_gateway(up, &err, ctx.lan).await;
+            }
+        };
 
-        loop {
-            let up_down = async {
-                io::copy(&mut ru, &mut wd).await?;
-                wd.shutdown().await
+        if let Some(version) = self.proxy_protocol {
+            // ALPN is negotiated by the downstream, so we do not know it there
+            let header = protocol::Header {
+                source: up.peer_addr()?,
+                destination: up.local_addr()?,
+                sni: ctx.sni.as_deref(),
+                alpn: None,
             };
-            let down_up = async {
-                io::copy(&mut rd, &mut wu).await?;
-                wu.shutdown().await
-            };
-
-            // XXX: Check how to handle this result and whether it makes sense to report anything
-            // meaningful there
-            let _ = tokio::try_join!(up_down, down_up);
+            header.write_to(version, &mut down).await?;
         }
+
+        let mut up = crate::metrics::Counted::new(up, &key);
+        let (received, sent) = io::copy_bidirectional(&mut up, &mut down).await?;
+        tracing::debug!(received, sent, "Connection closed");
+        log.transferred(received, sent);
+
+        Ok(())
     }
 }
@@ -1,33 +1,98 @@
 use std::collections::HashMap;
-use std::fs::Permissions;
+use std::fs::{File, Permissions};
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use std::borrow::Cow;
 
+use nix::sys::socket;
 use tokio::io;
 use tokio::net::UnixDatagram;
 use tokio::sync::RwLock;
 
+/// Maximal size of the datagram received over the control socket
+const DATAGRAM_SIZE: usize = 64 * 1024;
+
+/// Size of the parts into which responses are split
+const REPLY_CHUNK: usize = 8 * 1024;
+
+/// Time for which client waits for each part of the response
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time for which client waits for the result of [`Command::Replay`], which includes the
+/// response of the application
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum Command<'a> {
     Register {
         name: Cow<'a, str>,
         addr: std::net::SocketAddr,
         proxy: crate::proxy::Type,
+        options: crate::proxy::Options,
     },
     Deregister {
         name: Cow<'a, str>,
     },
+    SetState {
+        name: Cow<'a, str>,
+        state: crate::service::State,
+    },
     Status {
         name: Option<String>,
     },
+    /// Export traffic captured for the service
+    Export {
+        name: Cow<'a, str>,
+        format: crate::capture::Format,
+    },
+    /// Send captured request again, responds with the diff of the responses
+    Replay {
+        id: u64,
+        edit: crate::capture::Edit,
+    },
+    /// Line of the application output, no response is sent
+    Log {
+        name: Cow<'a, str>,
+        stream: crate::logs::Stream,
+        text: Cow<'a, str>,
+    },
+    /// Recent output of the application, responds with JSON array of lines
+    Logs {
+        name: Cow<'a, str>,
+        /// Return only lines newer than the one with given sequence number
+        since: Option<u64>,
+        limit: usize,
+    },
+    /// Re-read configuration of the server, responds with `ok` or the error
+    Reload,
+}
+
+impl Command<'_> {
+    /// Time for which client waits for each part of the response
+    fn reply_timeout(&self) -> Duration {
+        match self {
+            Command::Replay { .. } => REPLAY_TIMEOUT,
+            _ => REPLY_TIMEOUT,
+        }
+    }
+}
+
+/// Message sent by the server to the clients which registered services
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub enum Notification {
+    /// Server is shutting down, registered services are no longer reachable
+    Shutdown,
 }
 
 #[derive(Debug)]
 pub struct Client {
     socket: UnixDatagram,
+    /// Socket file of the client, removed when dropped
+    path: PathBuf,
 }
 
 impl Client {
@@ -38,11 +103,11 @@ impl Client {
         let socket = UnixDatagram::bind(rx.as_path())?;
         let perms = Permissions::from_mode(0o777);
         std::fs::set_permissions(&rx, perms)?;
-        match socket.connect(path) {
-            Ok(_) => Ok(Client { socket }),
+        match socket.connect(&path) {
+            Ok(_) => Ok(Client { socket, path: rx }),
             Err(err) => {
                 std::fs::remove_file(rx.as_path())?;
-                Err(err)
+                Err(unreachable(path.as_ref(), err))
             }
         }
     }
@@ -55,20 +120,81 @@ impl Client {
             .map(|_| ())
     }
 
+    /// Send message passing the file along, so the server can use it without opening the file
+    pub async fn send_with_file(&self, cmd: Command<'_>, file: &File) -> io::Result<()> {
+        let data = bincode::serialize(&cmd).unwrap();
+        let iov = [std::io::IoSlice::new(&data)];
+        let fds = [file.as_raw_fd()];
+        let cmsg = [socket::ControlMessage::ScmRights(&fds)];
+        loop {
+            self.socket.writable().await?;
+            let sent = self.socket.try_io(io::Interest::WRITABLE, || {
+                let fd = self.socket.as_raw_fd();
+                Ok(socket::sendmsg::<()>(
+                    fd,
+                    &iov,
+                    &cmsg,
+                    socket::MsgFlags::empty(),
+                    None,
+                )?)
+            });
+            match sent {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                sent => return sent.map(|_| ()),
+            }
+        }
+    }
+
     /// Send message and await for response
+    ///
+    /// Response can be split into multiple datagrams, it is terminated by an empty one.
     pub async fn call(&self, cmd: Command<'_>) -> io::Result<String> {
+        let timeout = cmd.reply_timeout();
         self.send(cmd).await?;
-        let mut buf = [0; 1024];
-        let len = tokio::time::timeout(
-            std::time::Duration::from_secs(5),
-            self.socket.recv(&mut buf),
-        )
-        .await??;
-        String::from_utf8(buf[..len].into())
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        let mut buf = vec![0; REPLY_CHUNK];
+        let mut resp = Vec::new();
+        loop {
+            let len = tokio::time::timeout(timeout, self.socket.recv(&mut buf)).await??;
+            if len == 0 {
+                break;
+            }
+            resp.extend_from_slice(&buf[..len]);
+        }
+        String::from_utf8(resp).map_err(io::Error::other)
+    }
+
+    /// Wait for the notification sent by the server
+    ///
+    /// Only clients that do not await responses, like the runner, can receive notifications.
+    pub async fn notification(&self) -> io::Result<Notification> {
+        let mut buf = [0; 64];
+        let len = self.socket.recv(&mut buf).await?;
+
+        bincode::deserialize(&buf[..len])
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 }
 
+/// Explain why the server cannot be reached at the path
+fn unreachable(path: &Path, err: io::Error) -> io::Error {
+    let reason = match err.kind() {
+        io::ErrorKind::NotFound => "server is not running",
+        io::ErrorKind::ConnectionRefused => "server is not running anymore, socket is stale",
+        io::ErrorKind::PermissionDenied => "socket is not accessible by this user",
+        _ => return err,
+    };
+
+    io::Error::new(
+        err.kind(),
+        format!(
+            "cannot reach Dolores at {}: {}, start it with `dolores serve` or choose its socket \
+             with `--socket`",
+            path.display(),
+            reason
+        ),
+    )
+}
+
 impl std::str::FromStr for Client {
     type Err = io::Error;
 
@@ -79,10 +205,9 @@ impl std::str::FromStr for Client {
 
 impl Drop for Client {
     fn drop(&mut self) {
-        let addr = self.socket.local_addr().unwrap();
-        let path = addr.as_pathname().unwrap();
-
-        std::fs::remove_file(path).unwrap();
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            tracing::debug!(%err, path = ?self.path, "Cannot remove client socket");
+        }
     }
 }
 
@@ -91,83 +216,291 @@ pub type RegistryStore = Arc<RwLock<HashMap<String, crate::service::Service>>>;
 #[derive(Debug)]
 pub struct Registry {
     domain: String,
-    socket: UnixDatagram,
+    /// Sharing of the services in the local network, when enabled
+    share: Option<crate::share::Share>,
+    socket: Arc<UnixDatagram>,
+    /// Socket file removed when dropped, inherited socket is left to its owner
+    path: Option<PathBuf>,
+    /// Reloading of the server configuration, requested with [`Command::Reload`]
+    reload: Option<Arc<dyn Reload>>,
     pub services: RegistryStore,
 }
 
+/// Configuration of the server that can be applied again while it is running
+#[async_trait]
+pub trait Reload: Send + Sync + std::fmt::Debug {
+    async fn reload(&self) -> Result<(), String>;
+}
+
 impl Registry {
     pub fn open<P: AsRef<Path>>(path: P, domain: &str) -> io::Result<Self> {
-        let socket = UnixDatagram::bind(&path)?;
+        let socket = match UnixDatagram::bind(&path) {
+            // Left by a server which could not remove it, e.g. after dropping privileges
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && stale(path.as_ref()) => {
+                tracing::debug!(path = ?path.as_ref(), "Removing stale control socket");
+                std::fs::remove_file(&path)?;
+                UnixDatagram::bind(&path)?
+            }
+            socket => socket?,
+        };
         let perms = Permissions::from_mode(0o777);
         std::fs::set_permissions(&path, perms)?;
 
+        Self::new(socket, domain, Some(path.as_ref().into()))
+    }
+
+    /// Use already bound socket, e.g. passed by the service manager
+    pub fn from_socket(socket: std::os::unix::net::UnixDatagram, domain: &str) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+
+        Self::new(UnixDatagram::from_std(socket)?, domain, None)
+    }
+
+    fn new(socket: UnixDatagram, domain: &str, path: Option<PathBuf>) -> io::Result<Self> {
+        // Kernel tells which user sent each command, see `Service::permits`
+        socket::setsockopt(socket.as_raw_fd(), socket::sockopt::PassCred, &true)?;
+
         Ok(Registry {
             domain: domain.into(),
-            socket,
+            share: None,
+            socket: Arc::new(socket),
+            path,
+            reload: None,
             services: Arc::new(Default::default()),
         })
     }
 
+    /// Reload the configuration when requested by the clients
+    pub fn with_reload(mut self, reload: Arc<dyn Reload>) -> Self {
+        self.reload = Some(reload);
+        self
+    }
+
+    /// Share newly registered services in the local network
+    pub fn with_share(mut self, share: Option<crate::share::Share>) -> Self {
+        self.share = share;
+        self
+    }
+
+    /// Tell the clients which registered services that the server stops
+    pub async fn shutdown(&self) {
+        let clients: Vec<_> = self
+            .services
+            .read()
+            .await
+            .values()
+            .filter_map(|service| service.client.clone())
+            .collect();
+        let notification = bincode::serialize(&Notification::Shutdown).unwrap();
+
+        for client in clients {
+            if let Err(err) = self.socket.send_to(&notification, &client).await {
+                tracing::debug!(%err, ?client, "Cannot notify client");
+            }
+        }
+    }
+
     pub async fn handle(&self) -> io::Result<()> {
-        let mut buf = [0; 1024];
-        let (len, from) = self.socket.recv_from(&mut buf).await?;
-        let cmd = bincode::deserialize::<Command>(&buf[..len]).unwrap();
+        let mut buf = vec![0; DATAGRAM_SIZE];
+        let (len, from, file, peer) = loop {
+            self.socket.readable().await?;
+            let received = self.socket.try_io(io::Interest::READABLE, || {
+                recv_with_file(self.socket.as_raw_fd(), &mut buf)
+            });
+            match received {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                received => break received?,
+            }
+        };
+        let cmd = bincode::deserialize::<Command>(&buf[..len]).map_err(|err| {
+            tracing::warn!(%err, "Invalid command");
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?;
         tracing::debug!(?cmd);
-        Self::handle_command(
-            self.services.clone(),
-            cmd,
-            &self.socket,
-            from.as_pathname().unwrap(),
-            &self.domain,
-        )
-        .await
+        self.handle_command(cmd, &from, file, peer).await
     }
 
+    /// Handle command sent from `to` by the `peer` user, `file` is the descriptor passed along
     async fn handle_command(
-        services: RegistryStore,
+        &self,
         command: Command<'_>,
-        sock: &UnixDatagram,
         to: &std::path::Path,
-        domain: &str,
+        file: Option<File>,
+        peer: Option<u32>,
     ) -> std::io::Result<()> {
         use Command::*;
 
+        let services = self.services.clone();
+        let sock = &self.socket;
+        let domain = self.domain.as_str();
+        let share = self.share.as_ref();
+        let reload = self.reload.as_ref();
+
+        let (kind, name) = match command {
+            Register { ref name, .. } => ("register", Some(name.as_ref())),
+            Deregister { ref name } => ("deregister", Some(name.as_ref())),
+            SetState { ref name, .. } => ("set_state", Some(name.as_ref())),
+            Status { ref name } => ("status", name.as_deref()),
+            Export { ref name, .. } => ("export", Some(name.as_ref())),
+            Replay { .. } => ("replay", None),
+            Log { ref name, .. } => ("log", Some(name.as_ref())),
+            Logs { ref name, .. } => ("logs", Some(name.as_ref())),
+            Reload => ("reload", None),
+        };
+        let service = match name.map(|name| format!("{}.{}", name, domain)) {
+            Some(domain) if services.read().await.contains_key(&domain) => domain,
+            Some(_) => crate::metrics::OTHER.into(),
+            None => String::new(),
+        };
+        crate::metrics::control_command(kind, &service);
+
         match command {
             Status { name, .. } => {
                 tracing::info!(name = %name.as_deref().unwrap_or("(all)"), "Status");
                 match name {
                     Some(ref name) => {
                         let services = services.read().await;
-                        let service = services.get(&*name);
-                        sock.send_to(
-                            format!("ok {:?}", service.map(|s| &s.domain)).as_bytes(),
+                        let service = services.get(name);
+                        reply(
+                            sock,
                             to,
+                            format!("ok {:?}", service.map(|s| &s.domain)).as_bytes(),
                         )
                         .await?;
                     }
                     None => {
                         let mut out = String::new();
                         for (name, service) in &*services.read().await {
-                            out.push_str(&format!("{} -> {}", name, service.addr));
+                            out.push_str(&format!(
+                                "{} -> {} ({})\n",
+                                name, service.addr, service.state
+                            ));
+                        }
+
+                        reply(sock, to, out.as_bytes()).await?;
+                    }
+                }
+            }
+            Register {
+                name,
+                addr,
+                proxy,
+                options,
+            } => {
+                if !crate::service::is_valid_name(&name) {
+                    tracing::warn!(%name, "Registration with invalid name");
+                    return Ok(());
+                }
+                if let Some(ref path) = options.metrics_path {
+                    if !crate::service::is_valid_metrics_path(path) {
+                        tracing::warn!(%name, %path, "Registration with invalid metrics path");
+                        return Ok(());
+                    }
+                }
+                let mut service = build(domain, share, &name, addr, proxy, options);
+                service.access_log = passed_access_log(&name, &service.options, file);
+                // Runner tells once the application is executed
+                service.state = crate::service::State::Starting;
+                service.client = Some(to.into());
+                service.owner = peer;
+                register(&services, service).await;
+            }
+            SetState { name, state } => {
+                let domain = format!("{}.{}", name, domain);
+                match services.write().await.get_mut(&domain) {
+                    Some(service) => {
+                        tracing::info!(%name, %domain, %state, "State changed");
+                        service.state = state;
+                    }
+                    None => tracing::warn!(%name, %domain, "State change of unknown service"),
+                }
+            }
+            Export { name, format } => {
+                let domain = format!("{}.{}", name, domain);
+                tracing::info!(%name, %domain, ?format, "Export");
+                let capture = services
+                    .read()
+                    .await
+                    .get(&domain)
+                    .map(|service| (service.permits(peer), service.capture.clone()));
+                let out = match capture {
+                    Some((false, _)) => format!("error: permission denied for {}\n", name),
+                    Some((true, Some(buffer))) => format.export(&buffer.list()),
+                    Some((true, None)) => {
+                        format!("error: capturing is not enabled for {}\n", name)
+                    }
+                    None => format!("error: service {} not found\n", name),
+                };
+
+                reply(sock, to, out.as_bytes()).await?;
+            }
+            Replay { id, edit } => {
+                tracing::info!(id, "Replay");
+                // Replaying can take a while, so do not block handling of other commands, the
+                // replayed request is let to finish on shutdown as the client connections
+                let sock = sock.clone();
+                let to = PathBuf::from(to);
+                tokio::spawn(crate::shutdown::track(async move {
+                    let out = match crate::capture::find(&services, id).await {
+                        Some((service, _)) if !service.permits(peer) => {
+                            format!("error: permission denied for request #{}\n", id)
+                        }
+                        Some((service, original)) => {
+                            match crate::capture::replay(&service, &original, &edit).await {
+                                Ok(replayed) => format!(
+                                    "replayed as #{}\n{}",
+                                    replayed.id,
+                                    crate::capture::diff(&original, &replayed)
+                                ),
+                                Err(err) => format!("error: {}\n", err),
+                            }
                         }
+                        None => format!("error: request #{} not found\n", id),
+                    };
 
-                        sock.send_to(out.as_bytes(), to).await?;
+                    if let Err(err) = reply(&sock, &to, out.as_bytes()).await {
+                        tracing::warn!(%err, "Cannot send replay result");
                     }
+                }));
+            }
+            Log { name, stream, text } => {
+                let domain = format!("{}.{}", name, domain);
+                match services.read().await.get(&domain) {
+                    Some(service) => service.logs.push(stream, text.into_owned()),
+                    None => tracing::debug!(%name, %domain, "Output of unknown service"),
                 }
             }
-            Register { name, addr, proxy } => {
+            Logs { name, since, limit } => {
                 let domain = format!("{}.{}", name, domain);
-                tracing::info!(%name, %domain, "Register");
-                services
-                    .write()
+                let logs = services
+                    .read()
                     .await
-                    .insert(domain, crate::service::Service::new(&name, addr, proxy));
+                    .get(&domain)
+                    .map(|service| (service.permits(peer), service.logs.clone()));
+                let out = match logs {
+                    Some((false, _)) => format!("error: permission denied for {}\n", name),
+                    Some((true, logs)) => {
+                        serde_json::to_string(&logs.since(since.unwrap_or(0), limit)).unwrap()
+                    }
+                    None => format!("error: service {} not found\n", name),
+                };
+
+                reply(sock, to, out.as_bytes()).await?;
+            }
+            Reload => {
+                tracing::info!("Reload requested");
+                let out = match reload {
+                    Some(reload) => match reload.reload().await {
+                        Ok(()) => "ok\n".to_owned(),
+                        Err(err) => format!("error: {}\n", err),
+                    },
+                    None => "error: server has no configuration to reload\n".to_owned(),
+                };
+
+                reply(sock, to, out.as_bytes()).await?;
             }
             Deregister { name, .. } => {
-                let domain = format!("{}.{}", name, domain);
-                let mut services = services.write().await;
-                services.remove(&*domain).unwrap();
-                tracing::info!(%name, %domain, "Deregistered");
+                deregister(&services, domain, &name).await;
             }
         };
 
@@ -175,11 +508,229 @@ impl Registry {
     }
 }
 
+/// Service `name` under the domain of the server
+pub fn build(
+    domain: &str,
+    share: Option<&crate::share::Share>,
+    name: &str,
+    addr: std::net::SocketAddr,
+    proxy: crate::proxy::Type,
+    mut options: crate::proxy::Options,
+) -> crate::service::Service {
+    let domain = format!("{}.{}", name, domain);
+    if options.plain_http && proxy == crate::proxy::Type::Passthrough {
+        tracing::warn!(%name, "Passthrough service cannot be served over plain HTTP");
+        options.plain_http = false;
+    }
+    let aliases: Vec<_> = share
+        .and_then(|share| share.alias(name, &options))
+        .into_iter()
+        .collect();
+    if options.share && share.is_none() {
+        tracing::warn!(%name, "Service cannot be shared, sharing is disabled in the server");
+    }
+    if proxy != crate::proxy::Type::Http && crate::headers::targets(&domain) {
+        tracing::warn!(%name, "Header rules of the service apply only with `http` proxy");
+    }
+    tracing::info!(%name, %domain, ?aliases, "Register");
+
+    crate::service::Service::with_aliases(&domain, aliases, addr, proxy, &options)
+}
+
+/// Access log of the service registered by the client, written to the file it passed
+///
+/// Server runs with more privileges than its clients, so it does not open the paths they send.
+/// Only `-`, standard output of the server, is accepted without the file.
+fn passed_access_log(
+    name: &str,
+    options: &crate::proxy::Options,
+    file: Option<File>,
+) -> Option<Arc<crate::access_log::Log>> {
+    let format = options.access_log_format;
+    match (&options.access_log, file) {
+        (Some(_), Some(file)) => Some(Arc::new(crate::access_log::Log::from_file(file, format))),
+        (Some(path), None) if path.as_path() == Path::new("-") => {
+            crate::access_log::Log::configured(options)
+        }
+        (Some(path), None) => {
+            tracing::warn!(%name, ?path, "Access log file is not passed by the client, ignoring it");
+            None
+        }
+        (None, Some(_)) => {
+            tracing::warn!(%name, "Unexpected file passed by the client");
+            None
+        }
+        (None, None) => None,
+    }
+}
+
+/// Add the service, replacing previous registration under its domain
+pub async fn register(services: &RegistryStore, service: crate::service::Service) {
+    let mut services = services.write().await;
+    services.insert(service.domain.clone(), service);
+    crate::metrics::registered_services(services.len());
+}
+
+/// Add the service from the configuration, unless a client registered the same one
+///
+/// Services from the configuration have no client, so only they are replaced.
+pub async fn register_static(services: &RegistryStore, service: crate::service::Service) {
+    let mut services = services.write().await;
+    match services.get(&service.domain) {
+        Some(registered) if registered.client.is_some() => {
+            tracing::warn!(domain = %service.domain, "Service is registered by a client, keeping it");
+        }
+        _ => {
+            services.insert(service.domain.clone(), service);
+            crate::metrics::registered_services(services.len());
+        }
+    }
+}
+
+/// Remove the service `name` registered under the domain of the server
+pub async fn deregister(services: &RegistryStore, domain: &str, name: &str) {
+    remove(services, domain, name, |_| true).await
+}
+
+/// Remove the service `name` registered from the configuration, keeping the one of a client
+pub async fn deregister_static(services: &RegistryStore, domain: &str, name: &str) {
+    remove(services, domain, name, |service| service.client.is_none()).await
+}
+
+async fn remove(
+    services: &RegistryStore,
+    domain: &str,
+    name: &str,
+    filter: impl FnOnce(&crate::service::Service) -> bool,
+) {
+    let domain = format!("{}.{}", name, domain);
+    let mut services = services.write().await;
+    match services.get(&domain) {
+        Some(service) if filter(service) => {
+            services.remove(&domain);
+            crate::metrics::forget(&domain);
+        }
+        Some(_) => {
+            tracing::debug!(%name, %domain, "Service is registered by a client, keeping it");
+            return;
+        }
+        None => {
+            tracing::warn!(%name, %domain, "Deregistration of unknown service");
+            return;
+        }
+    }
+    crate::metrics::registered_services(services.len());
+    tracing::info!(%name, %domain, "Deregistered");
+}
+
+/// Receive the command with its sender, the file descriptor passed along, if any, and the user
+/// which sent it
+///
+/// Uses `recvmsg` directly, the one of `nix` does not report the length of Unix addresses.
+fn recv_with_file(
+    fd: RawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, PathBuf, Option<File>, Option<u32>)> {
+    use nix::libc;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut cmsg = nix::cmsg_space!([RawFd; 1], libc::ucred);
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = std::ptr::addr_of_mut!(addr).cast();
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_un>() as _;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg.as_mut_ptr().cast();
+    msg.msg_controllen = cmsg.capacity() as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Take ownership of all the passed descriptors, so the unexpected ones are closed
+    let mut files = Vec::new();
+    let mut peer = None;
+    let mut header = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while let Some(cmsg) = unsafe { header.as_ref() } {
+        if cmsg.cmsg_level == libc::SOL_SOCKET && cmsg.cmsg_type == libc::SCM_CREDENTIALS {
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const libc::ucred;
+            peer = Some(unsafe { data.read_unaligned() }.uid);
+        }
+        if cmsg.cmsg_level == libc::SOL_SOCKET && cmsg.cmsg_type == libc::SCM_RIGHTS {
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const RawFd;
+            let size = cmsg.cmsg_len - unsafe { libc::CMSG_LEN(0) } as usize;
+            for index in 0..size / std::mem::size_of::<RawFd>() {
+                let fd = unsafe { data.add(index).read_unaligned() };
+                files.push(unsafe { File::from_raw_fd(fd) });
+            }
+        }
+        header = unsafe { libc::CMSG_NXTHDR(&msg, header) };
+    }
+    if files.len() > 1 {
+        tracing::warn!(count = files.len(), "Too many files passed by the client");
+        files.clear();
+    }
+
+    let path_offset = std::mem::size_of::<libc::sa_family_t>();
+    let path_len = (msg.msg_namelen as usize).saturating_sub(path_offset);
+    let path: Vec<u8> = addr.sun_path[..path_len.min(addr.sun_path.len())]
+        .iter()
+        .map(|&c| c as u8)
+        .take_while(|&c| c != 0)
+        .collect();
+    if path.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "client socket is not bound to a path",
+        ));
+    }
+
+    Ok((
+        len as usize,
+        std::ffi::OsStr::from_bytes(&path).into(),
+        files.pop(),
+        peer,
+    ))
+}
+
+/// Socket at the path is not bound by any process
+fn stale(path: &Path) -> bool {
+    let connected =
+        std::os::unix::net::UnixDatagram::unbound().and_then(|socket| socket.connect(path));
+
+    matches!(connected, Err(err) if err.kind() == io::ErrorKind::ConnectionRefused)
+}
+
+/// Send response split into datagrams, terminated by an empty one
+async fn reply(sock: &UnixDatagram, to: &Path, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(REPLY_CHUNK) {
+        sock.send_to(chunk, to).await?;
+    }
+    sock.send_to(&[], to).await?;
+
+    Ok(())
+}
+
 impl Drop for Registry {
     fn drop(&mut self) {
-        let addr = self.socket.local_addr().unwrap();
-        let path = addr.as_pathname().unwrap();
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
 
-        std::fs::remove_file(path).unwrap();
+        // After dropping privileges the directory of the socket may not be writable anymore, the
+        // stale socket is then replaced by the next server
+        if let Err(err) = std::fs::remove_file(path) {
+            tracing::warn!(%err, ?path, "Cannot remove control socket");
+        }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,328 @@
+use std::borrow::Cow;
+
+use tokio::net::UnixDatagram;
+
+use super::{reply, Client, Command, REPLAY_TIMEOUT, REPLY_CHUNK, REPLY_TIMEOUT};
+
+fn socket_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("dolores-{:x}-server.sock", rand::random::<u64>()))
+}
+
+#[tokio::test]
+async fn response_is_reassembled_from_chunks() {
+    let path = socket_path();
+    let server = UnixDatagram::bind(&path).unwrap();
+    let client = Client::open(&path).unwrap();
+
+    // Neither a multiple of the chunk nor shorter than it
+    let response: String = (0..REPLY_CHUNK * 3 + 10)
+        .map(|index| char::from(b'a' + (index % 26) as u8))
+        .collect();
+    let server = {
+        let response = response.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 1024];
+            let (len, from) = server.recv_from(&mut buf).await.unwrap();
+            let cmd: Command = bincode::deserialize(&buf[..len]).unwrap();
+            assert!(matches!(cmd, Command::Status { name: None }));
+
+            reply(&server, from.as_pathname().unwrap(), response.as_bytes())
+                .await
+                .unwrap();
+        })
+    };
+
+    let received = client.call(Command::Status { name: None }).await.unwrap();
+    server.await.unwrap();
+    assert_eq!(received, response);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn empty_response_is_only_terminator() {
+    let path = socket_path();
+    let server = UnixDatagram::bind(&path).unwrap();
+    let client = Client::open(&path).unwrap();
+
+    let server = tokio::spawn(async move {
+        let mut buf = vec![0; 1024];
+        let (_, from) = server.recv_from(&mut buf).await.unwrap();
+        reply(&server, from.as_pathname().unwrap(), b"")
+            .await
+            .unwrap();
+    });
+
+    assert_eq!(client.call(Command::Reload).await.unwrap(), "");
+    server.await.unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn only_replay_waits_longer() {
+    let replay = Command::Replay {
+        id: 1,
+        edit: Default::default(),
+    };
+    assert_eq!(replay.reply_timeout(), REPLAY_TIMEOUT);
+
+    let status = Command::Status { name: None };
+    assert_eq!(status.reply_timeout(), REPLY_TIMEOUT);
+    let logs = Command::Logs {
+        name: Cow::Borrowed("foo"),
+        since: None,
+        limit: 10,
+    };
+    assert_eq!(logs.reply_timeout(), REPLY_TIMEOUT);
+}
+
+fn register(access_log: &std::path::Path) -> Command<'static> {
+    Command::Register {
+        name: Cow::Borrowed("app"),
+        addr: "[::1]:8000".parse().unwrap(),
+        proxy: crate::proxy::Type::Http,
+        options: crate::proxy::Options {
+            access_log: Some(access_log.into()),
+            ..Default::default()
+        },
+    }
+}
+
+#[tokio::test]
+async fn access_log_is_written_only_to_passed_file() {
+    let path = socket_path();
+    let registry = super::Registry::open(&path, "test").unwrap();
+    let client = Client::open(&path).unwrap();
+    // Path sent by the client is never opened by the server
+    let requested = std::env::temp_dir().join(format!("dolores-{:x}.log", rand::random::<u64>()));
+    let passed = std::env::temp_dir().join(format!("dolores-{:x}.log", rand::random::<u64>()));
+
+    client.send(register(&requested)).await.unwrap();
+    registry.handle().await.unwrap();
+    assert!(registry.services.read().await["app.test"]
+        .access_log
+        .is_none());
+
+    let file = std::fs::File::create(&passed).unwrap();
+    client
+        .send_with_file(register(&requested), &file)
+        .await
+        .unwrap();
+    drop(file);
+    registry.handle().await.unwrap();
+
+    let log = registry.services.read().await["app.test"]
+        .access_log
+        .clone()
+        .unwrap();
+    log.write(&crate::access_log::Entry {
+        client: "::1".parse().unwrap(),
+        service: "app.test".into(),
+        started: std::time::SystemTime::UNIX_EPOCH,
+        duration: std::time::Duration::ZERO,
+        request: None,
+        status: None,
+        received: 0,
+        sent: 0,
+    });
+    assert_eq!(
+        std::fs::read_to_string(&passed).unwrap(),
+        "::1 - - [01/Jan/1970:00:00:00 +0000] \"CONNECT app.test TLS\" - - \"-\" \"-\"\n"
+    );
+    assert!(!requested.exists());
+
+    std::fs::remove_file(&passed).unwrap();
+}
+
+#[test]
+fn service_names_are_dns_labels() {
+    for name in ["app", "my-app", "app2", "0", &"a".repeat(63)] {
+        assert!(crate::service::is_valid_name(name), "{:?}", name);
+    }
+    let invalid = [
+        "",
+        "-app",
+        "app-",
+        "App",
+        "my_app",
+        "app.test",
+        "./app",
+        "app\n127.0.0.1\tevil.example",
+        &"a".repeat(64),
+    ];
+    for name in invalid {
+        assert!(!crate::service::is_valid_name(name), "{:?}", name);
+    }
+
+    assert!(crate::service::is_valid_domain("app.test"));
+    assert!(!crate::service::is_valid_domain("app..test"));
+    assert!(!crate::service::is_valid_domain("app.test."));
+}
+
+#[tokio::test]
+async fn registration_with_invalid_name_is_rejected() {
+    let path = socket_path();
+    let registry = super::Registry::open(&path, "test").unwrap();
+    let client = Client::open(&path).unwrap();
+
+    client
+        .send(Command::Register {
+            name: Cow::Borrowed("app\n127.0.0.1\tevil.example"),
+            addr: "[::1]:8000".parse().unwrap(),
+            proxy: crate::proxy::Type::Http,
+            options: Default::default(),
+        })
+        .await
+        .unwrap();
+    registry.handle().await.unwrap();
+
+    assert!(registry.services.read().await.is_empty());
+}
+
+#[tokio::test]
+async fn static_registrations_do_not_touch_services_of_clients() {
+    let services = super::RegistryStore::default();
+    let service = |addr: &str, client: Option<&str>| crate::service::Service {
+        client: client.map(Into::into),
+        ..crate::service::Service::new(
+            "app.test",
+            addr.parse().unwrap(),
+            crate::proxy::Type::Passthrough,
+            &Default::default(),
+        )
+    };
+
+    super::register_static(&services, service("[::1]:8000", None)).await;
+    super::deregister_static(&services, "test", "app").await;
+    assert!(services.read().await.is_empty());
+
+    super::register(&services, service("[::1]:8001", Some("/client.sock"))).await;
+    super::register_static(&services, service("[::1]:8000", None)).await;
+    assert_eq!(services.read().await["app.test"].addr.port(), 8001);
+
+    super::deregister_static(&services, "test", "app").await;
+    assert_eq!(services.read().await["app.test"].addr.port(), 8001);
+}
+
+#[tokio::test]
+async fn commands_are_labelled_only_by_registered_services() {
+    let path = socket_path();
+    let registry = super::Registry::open(&path, "labels.test").unwrap();
+    let client = Client::open(&path).unwrap();
+    let status = |name: &'static str| Command::Status {
+        name: Some(name.into()),
+    };
+    let counted = |service: &str| {
+        crate::metrics::render().lines().any(|line| {
+            line.starts_with("dolores_control_commands_total{")
+                && line.contains(&format!("service=\"{}\"", service))
+        })
+    };
+
+    client.send(status("unknown-name")).await.unwrap();
+    registry.handle().await.unwrap();
+    assert!(!counted("unknown-name.labels.test"));
+    assert!(counted(crate::metrics::OTHER));
+
+    super::register(
+        &registry.services,
+        crate::service::Service::new(
+            "known.labels.test",
+            "[::1]:8000".parse().unwrap(),
+            crate::proxy::Type::Passthrough,
+            &Default::default(),
+        ),
+    )
+    .await;
+    client.send(status("known")).await.unwrap();
+    registry.handle().await.unwrap();
+    assert!(counted("known.labels.test"));
+
+    super::deregister(&registry.services, "labels.test", "known").await;
+    assert!(!counted("known.labels.test"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn stale_control_socket_is_replaced() {
+    let path = socket_path();
+    drop(std::os::unix::net::UnixDatagram::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let registry = super::Registry::open(&path, "test").unwrap();
+    let err = super::Registry::open(&path, "test").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+
+    drop(registry);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn registration_with_invalid_metrics_path_is_rejected() {
+    let path = socket_path();
+    let registry = super::Registry::open(&path, "test").unwrap();
+    let client = Client::open(&path).unwrap();
+
+    for metrics_path in ["metrics", "@evil.example/metrics"] {
+        client
+            .send(Command::Register {
+                name: Cow::Borrowed("app"),
+                addr: "[::1]:8000".parse().unwrap(),
+                proxy: crate::proxy::Type::Http,
+                options: crate::proxy::Options {
+                    metrics_path: Some(metrics_path.into()),
+                    ..Default::default()
+                },
+            })
+            .await
+            .unwrap();
+        registry.handle().await.unwrap();
+    }
+
+    assert!(registry.services.read().await.is_empty());
+}
+
+#[tokio::test]
+async fn registered_services_belong_to_their_users() {
+    let path = socket_path();
+    let registry = super::Registry::open(&path, "test").unwrap();
+    let client = Client::open(&path).unwrap();
+
+    client
+        .send(Command::Register {
+            name: Cow::Borrowed("app"),
+            addr: "[::1]:8000".parse().unwrap(),
+            proxy: crate::proxy::Type::Http,
+            options: Default::default(),
+        })
+        .await
+        .unwrap();
+    registry.handle().await.unwrap();
+
+    let user = nix::unistd::geteuid().as_raw();
+    assert_eq!(registry.services.read().await["app.test"].owner, Some(user));
+}
+
+#[test]
+fn only_owners_see_traffic_of_services() {
+    let server = nix::unistd::geteuid().as_raw();
+    // Neither root nor the user of the server
+    let (owner, other) = (server + 1000, server + 1001);
+    let mut service = crate::service::Service::new(
+        "app.test",
+        "[::1]:8000".parse().unwrap(),
+        crate::proxy::Type::Http,
+        &Default::default(),
+    );
+
+    assert!(service.permits(Some(0)));
+    assert!(service.permits(Some(server)));
+    assert!(!service.permits(Some(owner)));
+    assert!(!service.permits(None));
+
+    service.owner = Some(owner);
+    assert!(service.permits(Some(owner)));
+    assert!(!service.permits(Some(other)));
+}
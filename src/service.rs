@@ -1,20 +1,120 @@
+use std::collections::HashMap;
 use std::net;
 use std::sync::Arc;
 
 #[derive(Clone, Debug, serde::Serialize)]
 pub struct Service {
     pub domain: String,
+    /// Other names of the service, like the one under which it is shared in the local network
+    pub aliases: Vec<String>,
     pub addr: net::SocketAddr,
+    pub state: State,
     #[serde(skip_serializing)]
     pub proxy: Arc<crate::proxy::TcpProxy>,
+    /// Recently captured HTTP traffic, when capturing is enabled
+    #[serde(skip_serializing)]
+    pub capture: Option<Arc<crate::capture::Buffer>>,
+    /// Access log of the service, written in addition to the global one
+    #[serde(skip_serializing)]
+    pub access_log: Option<Arc<crate::access_log::Log>>,
+    /// Recent output of the application, forwarded by the runner
+    #[serde(skip_serializing)]
+    pub logs: Arc<crate::logs::Buffer>,
+    /// Socket of the client which registered the service, notified when the server stops
+    #[serde(skip_serializing)]
+    pub client: Option<std::path::PathBuf>,
+    /// User which registered the service, who can read its captured traffic and output
+    #[serde(skip_serializing)]
+    pub owner: Option<u32>,
+    pub options: crate::proxy::Options,
+}
+
+/// Lifecycle state of the application behind the service
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum State {
+    /// Application is starting and may not accept connections yet
+    Starting,
+    /// Application is accepting connections
+    #[default]
+    Running,
+    /// Application is restarting and will be back shortly
+    Restarting,
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            State::Starting => "starting",
+            State::Running => "running",
+            State::Restarting => "restarting",
+        })
+    }
 }
 
 impl Service {
-    pub fn new(domain: &str, addr: net::SocketAddr, proxy: crate::proxy::Type) -> Self {
+    /// Whether both are the same registration, possibly in different states
+    ///
+    /// Proxy is built anew for each registration, so it identifies the registration.
+    pub fn same_registration(&self, other: &Service) -> bool {
+        Arc::ptr_eq(&self.proxy, &other.proxy)
+    }
+
+    /// Whether the `user` can read the captured traffic and the output of the application
+    ///
+    /// Control socket and dashboard are open to all local users, so they can run their
+    /// applications, while only root, the user of the server and the one which registered the
+    /// service can see what passes through it.
+    pub fn permits(&self, user: Option<u32>) -> bool {
+        match user {
+            Some(uid) => {
+                uid == 0 || uid == nix::unistd::geteuid().as_raw() || self.owner == Some(uid)
+            }
+            None => false,
+        }
+    }
+
+    /// URL from which the Prometheus metrics of the application can be scraped
+    pub fn metrics_url(&self) -> Option<String> {
+        let (addr, path) = match (self.options.metrics_addr, &self.options.metrics_path) {
+            (Some(addr), path) => (addr, path.as_deref().unwrap_or("/metrics")),
+            (None, Some(path)) => (self.addr, path.as_str()),
+            (None, None) => return None,
+        };
+
+        Some(format!("http://{}{}", addr, path))
+    }
+
+    pub fn new(
+        domain: &str,
+        addr: net::SocketAddr,
+        proxy: crate::proxy::Type,
+        options: &crate::proxy::Options,
+    ) -> Self {
+        Self::with_aliases(domain, Vec::new(), addr, proxy, options)
+    }
+
+    /// Service reachable also under other names, which are included in its certificate
+    pub fn with_aliases(
+        domain: &str,
+        aliases: Vec<String>,
+        addr: net::SocketAddr,
+        proxy: crate::proxy::Type,
+        options: &crate::proxy::Options,
+    ) -> Self {
+        let names = crate::proxy::Domain::from(domain).with_aliases(&aliases);
+
         Service {
             domain: domain.into(),
+            aliases,
             addr,
-            proxy: proxy.build(domain),
+            state: State::default(),
+            proxy: proxy.build(names, options),
+            capture: options.capture.then(Default::default),
+            access_log: None,
+            logs: Default::default(),
+            client: None,
+            owner: None,
+            options: options.clone(),
         }
     }
 }
@@ -23,13 +123,80 @@ pub fn parse_handshake(connection: &mut rustls::ServerConnection, mut data: &[u8
     connection.read_tls(&mut data).ok()?;
     let _ = connection.process_new_packets();
     connection.sni_hostname().and_then(|sni| {
-        let mut parts = sni.split('.');
-        let tld = parts.nth_back(0)?;
-        let name = parts.nth_back(0)?;
-        Some(format!("{}.{}", name, tld))
+        // Single label names, like `localhost`, are meant for the dashboard
+        sni.contains('.').then(|| sni.trim_end_matches('.').to_ascii_lowercase())
     })
 }
 
+/// Whether the name can be used for a service, it has to be a lowercase DNS label
+///
+/// Names end up in the hosts file and DNS answers, so nothing else is accepted from the clients.
+pub fn is_valid_name(name: &str) -> bool {
+    (1..=63).contains(&name.len())
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Whether the metrics path can be appended to the address of the application
+///
+/// Anything not starting with `/` would change the URL, e.g. `@example.com/` its host.
+pub fn is_valid_metrics_path(path: &str) -> bool {
+    path.starts_with('/')
+}
+
+/// Whether the domain consists of names valid for services
+pub fn is_valid_domain(domain: &str) -> bool {
+    domain.len() <= 253 && domain.split('.').all(is_valid_name)
+}
+
+/// Service responsible for the host, either by its domain or alias, including their subdomains
+///
+/// Returns the domain under which the service is registered, along with the service.
+pub fn lookup<'a>(
+    services: &'a HashMap<String, Service>,
+    host: &str,
+) -> Option<(&'a String, &'a Service)> {
+    find(services, host, true)
+}
+
+/// Shared service responsible for the host, only by its alias in the local network
+pub fn lookup_shared<'a>(
+    services: &'a HashMap<String, Service>,
+    host: &str,
+) -> Option<(&'a String, &'a Service)> {
+    find(services, host, false)
+}
+
+fn find<'a>(
+    services: &'a HashMap<String, Service>,
+    host: &str,
+    by_domain: bool,
+) -> Option<(&'a String, &'a Service)> {
+    let matches = |name: &str| {
+        host == name
+            || host
+                .strip_suffix(name)
+                .is_some_and(|rest| rest.ends_with('.'))
+    };
+
+    services
+        .iter()
+        .filter_map(|(domain, service)| {
+            std::iter::once(domain)
+                .filter(|_| by_domain)
+                .chain(&service.aliases)
+                .filter(|name| matches(name))
+                .map(|name| name.len())
+                .max()
+                .map(|len| (len, (domain, service)))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, found)| found)
+}
+
 impl net::ToSocketAddrs for Service {
     type Iter = std::option::IntoIter<net::SocketAddr>;
 
@@ -0,0 +1,71 @@
+//! Sharing of the services in the local network
+//!
+//! Shared services get an alias `<name>.<host>.local`, which is included in their certificates and
+//! announced over mDNS, so other devices in the network, like phones, can reach them.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+pub mod mdns;
+
+/// Which services are shared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// All registered services
+    #[default]
+    All,
+    /// Only services started with `dolores run --share`
+    OptIn,
+}
+
+#[derive(Debug, Clone)]
+pub struct Share {
+    /// Host label used in the names, `<name>.<host>.local`
+    pub host: String,
+    pub mode: Mode,
+    /// Address of the server in the local network
+    pub addr: Ipv4Addr,
+}
+
+impl Share {
+    /// Name under which the service is shared, when it is
+    pub fn alias(&self, name: &str, options: &crate::proxy::Options) -> Option<String> {
+        (self.mode == Mode::All || options.share)
+            .then(|| format!("{}.{}.local", name.to_ascii_lowercase(), self.host))
+    }
+}
+
+/// First label of the host name of the machine
+pub fn hostname() -> io::Result<String> {
+    let name = nix::unistd::gethostname()?;
+    let name = name.to_string_lossy();
+
+    Ok(name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase())
+}
+
+/// Address of the interface used to reach the local network
+///
+/// No packets are sent, connecting UDP socket only selects the route.
+pub fn lan_address() -> io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    // Prefer the interface used for multicast, fall back to the default route
+    let targets = [mdns::GROUP, Ipv4Addr::new(192, 0, 2, 1)];
+    for target in targets {
+        if socket.connect((target, mdns::PORT)).is_err() {
+            continue;
+        }
+        match socket.local_addr()? {
+            SocketAddr::V4(addr) if !addr.ip().is_unspecified() => return Ok(*addr.ip()),
+            _ => continue,
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "cannot detect address in the local network, pass it with --share-address",
+    ))
+}
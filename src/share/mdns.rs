@@ -0,0 +1,495 @@
+//! mDNS responder announcing the shared services, with DNS-SD records for `_https._tcp`
+//!
+//! Records are announced whenever the set of shared services changes and withdrawn, by sending
+//! them with zero TTL, once the services are gone or the server stops. Queries from other devices
+//! are answered over multicast, or directly to the sender for one-shot queries not sent from the
+//! mDNS port.
+//!
+//! Probing before the first announcement and resolution of conflicts with other responders, as
+//! described in RFC 6762 section 8 and 9, are not implemented. Names are announced right away, and
+//! another device claiming the same name is not detected, so its records and ours are both cached
+//! by the clients. Aliases include the host name of the machine, which makes conflicts unlikely.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nix::sys::socket::{self, sockopt};
+
+use crate::registry::RegistryStore;
+
+pub const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const PORT: u16 = 5353;
+
+/// TTL of the records, recommended by RFC 6762 for the records with host names
+const TTL: u32 = 120;
+
+/// TTL used in responses to one-shot queries, which are cached by conventional resolvers
+const LEGACY_TTL: u32 = 10;
+
+/// Interval in which the changes of the registry are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+const SERVICE_TYPE: &str = "_https._tcp.local";
+const SERVICE_TYPES: &str = "_services._dns-sd._udp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set in the class of the records that are unique to the responder
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// Limits of the names from RFC 1035, in bytes
+const MAX_LABEL: usize = 63;
+const MAX_NAME: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Data {
+    A(Ipv4Addr),
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    name: String,
+    data: Data,
+    /// Unique records replace the cached ones, shared ones like PTR are merged with them
+    unique: bool,
+}
+
+impl Record {
+    fn kind(&self) -> u16 {
+        match self.data {
+            Data::A(_) => TYPE_A,
+            Data::Ptr(_) => TYPE_PTR,
+            Data::Srv { .. } => TYPE_SRV,
+            Data::Txt(_) => TYPE_TXT,
+        }
+    }
+
+    /// Record in the wire format, unless its names or text do not fit in their limits
+    fn encode(&self, ttl: u32, flush: bool) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        encode_name(&mut out, &self.name)?;
+        out.extend_from_slice(&self.kind().to_be_bytes());
+        let class = if flush && self.unique {
+            CLASS_IN | CACHE_FLUSH
+        } else {
+            CLASS_IN
+        };
+        out.extend_from_slice(&class.to_be_bytes());
+        out.extend_from_slice(&ttl.to_be_bytes());
+
+        let mut data = Vec::new();
+        match self.data {
+            Data::A(addr) => data.extend_from_slice(&addr.octets()),
+            Data::Ptr(ref name) => encode_name(&mut data, name)?,
+            Data::Srv { port, ref target } => {
+                // Priority and weight
+                data.extend_from_slice(&[0, 0, 0, 0]);
+                data.extend_from_slice(&port.to_be_bytes());
+                encode_name(&mut data, target)?;
+            }
+            Data::Txt(ref entries) => {
+                for entry in entries {
+                    data.push(u8::try_from(entry.len()).ok()?);
+                    data.extend_from_slice(entry.as_bytes());
+                }
+            }
+        }
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+
+        Some(out)
+    }
+}
+
+/// Append the name, fails without changing `out` when a label or the name is too long
+fn encode_name(out: &mut Vec<u8>, name: &str) -> Option<()> {
+    let mut encoded = Vec::with_capacity(name.len() + 2);
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        if label.len() > MAX_LABEL {
+            return None;
+        }
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    if encoded.len() > MAX_NAME {
+        return None;
+    }
+
+    out.extend_from_slice(&encoded);
+    Some(())
+}
+
+/// Read possibly compressed name starting at `pos`, returns it with the position after it
+fn read_name(message: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Guard against pointer loops
+    for _ in 0..128 {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(pos + 1)));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let target = (len & 0x3f) << 8 | *message.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            len if len <= 63 => {
+                let label = message.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+/// Records announcing the service shared under `alias`
+fn service_records(alias: &str, addr: Ipv4Addr, port: u16) -> Vec<Record> {
+    let label = alias.split('.').next().unwrap_or(alias);
+    let instance = format!("{}.{}", label, SERVICE_TYPE);
+
+    vec![
+        Record {
+            name: alias.into(),
+            data: Data::A(addr),
+            unique: true,
+        },
+        Record {
+            name: SERVICE_TYPE.into(),
+            data: Data::Ptr(instance.clone()),
+            unique: false,
+        },
+        Record {
+            name: instance.clone(),
+            data: Data::Srv {
+                port,
+                target: alias.into(),
+            },
+            unique: true,
+        },
+        Record {
+            name: instance,
+            data: Data::Txt(vec!["path=/".into()]),
+            unique: true,
+        },
+    ]
+}
+
+/// Response message with given records, `id` and `questions` are set only for one-shot queries
+///
+/// Records that cannot be encoded are left out.
+fn response(
+    id: u16,
+    questions: &[u8],
+    answers: &[&Record],
+    additional: &[&Record],
+    ttl: u32,
+) -> Vec<u8> {
+    let legacy = !questions.is_empty();
+    let question_count = if legacy { 1u16 } else { 0 };
+    let encode = |records: &[&Record]| -> Vec<Vec<u8>> {
+        records
+            .iter()
+            .filter_map(|record| {
+                let encoded = record.encode(ttl, !legacy);
+                if encoded.is_none() {
+                    tracing::warn!(name = %record.name, "mDNS record is too long, leaving it out");
+                }
+                encoded
+            })
+            .collect()
+    };
+    let (answers, additional) = (encode(answers), encode(additional));
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    // Authoritative response
+    out.extend_from_slice(&0x8400u16.to_be_bytes());
+    out.extend_from_slice(&question_count.to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(additional.len() as u16).to_be_bytes());
+    out.extend_from_slice(questions);
+    for record in answers.iter().chain(&additional) {
+        out.extend_from_slice(record);
+    }
+
+    out
+}
+
+#[derive(Debug, Default)]
+struct Announced {
+    records: Vec<Record>,
+    /// Announcements are sent twice, as required by RFC 6762
+    repeat: bool,
+}
+
+#[derive(Debug)]
+struct Responder {
+    socket: tokio::net::UdpSocket,
+    addr: Ipv4Addr,
+    port: u16,
+    services: RegistryStore,
+    announced: Mutex<Announced>,
+}
+
+impl Responder {
+    /// Records of all currently shared services
+    async fn records(&self) -> Vec<Record> {
+        let mut aliases: Vec<_> = self
+            .services
+            .read()
+            .await
+            .values()
+            .flat_map(|service| service.aliases.clone())
+            .filter(|alias| alias.ends_with(".local"))
+            .collect();
+        aliases.sort();
+
+        let mut records: Vec<_> = aliases
+            .iter()
+            .flat_map(|alias| service_records(alias, self.addr, self.port))
+            .collect();
+        if !records.is_empty() {
+            records.push(Record {
+                name: SERVICE_TYPES.into(),
+                data: Data::Ptr(SERVICE_TYPE.into()),
+                unique: false,
+            });
+        }
+
+        records
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut buf = vec![0; 9000];
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.refresh().await,
+                received = self.socket.recv_from(&mut buf) => match received {
+                    Ok((len, from)) => self.answer(&buf[..len], from).await,
+                    Err(err) => tracing::debug!(%err, "Cannot receive mDNS message"),
+                },
+            }
+        }
+    }
+
+    /// Announce new records and withdraw the removed ones
+    async fn refresh(&self) {
+        let records = self.records().await;
+        let (announce, withdraw) = {
+            let mut announced = self.announced.lock().unwrap();
+            if announced.records == records && !announced.repeat {
+                return;
+            }
+
+            let withdraw: Vec<_> = announced
+                .records
+                .iter()
+                .filter(|record| !records.contains(record))
+                .cloned()
+                .collect();
+            announced.repeat = announced.records != records;
+            announced.records = records.clone();
+
+            (records, withdraw)
+        };
+
+        if !withdraw.is_empty() {
+            self.send(&withdraw.iter().collect::<Vec<_>>(), 0).await;
+        }
+        if !announce.is_empty() {
+            tracing::debug!(records = announce.len(), "Announce shared services");
+            self.send(&announce.iter().collect::<Vec<_>>(), TTL).await;
+        }
+    }
+
+    async fn send(&self, records: &[&Record], ttl: u32) {
+        let message = response(0, &[], records, &[], ttl);
+        if let Err(err) = self.socket.send_to(&message, (GROUP, PORT)).await {
+            tracing::warn!(%err, "Cannot send mDNS announcement");
+        }
+    }
+
+    async fn answer(&self, message: &[u8], from: SocketAddr) {
+        if message.len() < 12 || message[2] & 0x80 != 0 {
+            return;
+        }
+        let id = u16::from_be_bytes([message[0], message[1]]);
+        let count = u16::from_be_bytes([message[4], message[5]]);
+
+        let records = self.announced.lock().unwrap().records.clone();
+        let mut answers: Vec<&Record> = Vec::new();
+        let mut first_question = None;
+        let mut pos = 12;
+        for _ in 0..count {
+            let (name, end) = match read_name(message, pos) {
+                Some(name) => name,
+                None => return,
+            };
+            let fixed = match message.get(end..end + 4) {
+                Some(fixed) => fixed,
+                None => return,
+            };
+            let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+            first_question.get_or_insert((pos, end + 4));
+            pos = end + 4;
+
+            for record in &records {
+                if record.name.eq_ignore_ascii_case(&name)
+                    && (kind == record.kind() || kind == TYPE_ANY)
+                    && !answers.contains(&record)
+                {
+                    answers.push(record);
+                }
+            }
+        }
+        if answers.is_empty() {
+            return;
+        }
+
+        // Help the client to resolve the service without further queries, by including records
+        // the answers point to, transitively
+        let mut additional: Vec<&Record> = Vec::new();
+        let mut pending: Vec<&Record> = answers.clone();
+        while let Some(record) = pending.pop() {
+            let target = match record.data {
+                Data::Ptr(ref target) | Data::Srv { ref target, .. } => target,
+                _ => continue,
+            };
+            for record in &records {
+                if record.name == *target
+                    && !answers.contains(&record)
+                    && !additional.contains(&record)
+                {
+                    additional.push(record);
+                    pending.push(record);
+                }
+            }
+        }
+
+        tracing::debug!(%from, answers = answers.len(), "mDNS query");
+        let result = if from.port() == PORT {
+            let message = response(0, &[], &answers, &additional, TTL);
+            self.socket.send_to(&message, (GROUP, PORT)).await
+        } else {
+            // One-shot query expects conventional DNS response, with the question echoed
+            let question = first_question.map_or(&[][..], |(start, end)| &message[start..end]);
+            let message = response(id, question, &answers, &additional, LEGACY_TTL);
+            self.socket.send_to(&message, from).await
+        };
+        if let Err(err) = result {
+            tracing::debug!(%err, %from, "Cannot send mDNS response");
+        }
+    }
+}
+
+/// Running responder, which withdraws the announced records when dropped
+#[derive(Debug)]
+pub struct Announcer {
+    responder: Arc<Responder>,
+    task: tokio::task::JoinHandle<()>,
+    /// Blocking handle of the socket, usable also when the runtime is shutting down
+    socket: std::net::UdpSocket,
+}
+
+impl Announcer {
+    /// Start announcing services shared at `addr`, reachable on given `port`
+    ///
+    /// Multicast is sent through the interface with the given address, so using loopback keeps
+    /// the announcements on the local machine.
+    pub fn start(addr: Ipv4Addr, port: u16, services: RegistryStore) -> io::Result<Self> {
+        let fd = socket::socket(
+            socket::AddressFamily::Inet,
+            socket::SockType::Datagram,
+            socket::SockFlag::SOCK_CLOEXEC | socket::SockFlag::SOCK_NONBLOCK,
+            None,
+        )?;
+        // Owned from now on, so it is closed on errors
+        let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+
+        // Other responders, like Avahi, may already use the port
+        socket::setsockopt(fd, sockopt::ReuseAddr, &true)?;
+        socket::setsockopt(fd, sockopt::ReusePort, &true)?;
+        let bind: socket::SockaddrIn = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, PORT).into();
+        socket::bind(fd, &bind)?;
+
+        socket.join_multicast_v4(&GROUP, &addr)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+        set_multicast_interface(&socket, addr)?;
+
+        let responder = Arc::new(Responder {
+            socket: tokio::net::UdpSocket::from_std(socket.try_clone()?)?,
+            addr,
+            port,
+            services,
+            announced: Mutex::default(),
+        });
+        let task = tokio::spawn(responder.clone().run());
+        tracing::info!(%addr, port, "Announcing shared services over mDNS");
+
+        Ok(Announcer {
+            responder,
+            task,
+            socket,
+        })
+    }
+}
+
+impl Drop for Announcer {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        let records = std::mem::take(&mut self.responder.announced.lock().unwrap().records);
+        if records.is_empty() {
+            return;
+        }
+        let message = response(0, &[], &records.iter().collect::<Vec<_>>(), &[], 0);
+        if let Err(err) = self.socket.send_to(&message, (GROUP, PORT)) {
+            tracing::warn!(%err, "Cannot withdraw mDNS announcement");
+        }
+    }
+}
+
+/// Send multicast through the interface with given address, not available in `nix`
+fn set_multicast_interface(socket: &std::net::UdpSocket, addr: Ipv4Addr) -> io::Result<()> {
+    let addr = nix::libc::in_addr {
+        s_addr: u32::from(addr).to_be(),
+    };
+    let result = unsafe {
+        nix::libc::setsockopt(
+            socket.as_raw_fd(),
+            nix::libc::IPPROTO_IP,
+            nix::libc::IP_MULTICAST_IF,
+            &addr as *const _ as *const nix::libc::c_void,
+            std::mem::size_of_val(&addr) as nix::libc::socklen_t,
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests;
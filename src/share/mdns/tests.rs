@@ -0,0 +1,334 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{
+    encode_name, read_name, response, service_records, Announced, Data, Record, Responder,
+    CLASS_IN, LEGACY_TTL, MAX_LABEL, MAX_NAME, SERVICE_TYPE, TTL, TYPE_A, TYPE_ANY, TYPE_PTR,
+    TYPE_SRV, TYPE_TXT,
+};
+
+const ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+const ALIAS: &str = "app.host.local";
+
+fn a_record(name: &str) -> Record {
+    Record {
+        name: name.into(),
+        data: Data::A(ADDR),
+        unique: true,
+    }
+}
+
+/// Record read back from the wire format
+#[derive(Debug, PartialEq)]
+struct Parsed {
+    name: String,
+    kind: u16,
+    class: u16,
+    ttl: u32,
+    data: Vec<u8>,
+}
+
+/// Header counts with the records of the message, compressed names are resolved
+fn parse(message: &[u8]) -> ([u16; 4], Vec<Parsed>) {
+    let count = |index: usize| u16::from_be_bytes([message[4 + index * 2], message[5 + index * 2]]);
+    let counts = [count(0), count(1), count(2), count(3)];
+
+    let mut pos = 12;
+    for _ in 0..counts[0] {
+        pos = read_name(message, pos).unwrap().1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..counts[1] + counts[2] + counts[3] {
+        let (name, end) = read_name(message, pos).unwrap();
+        let fixed = &message[end..end + 10];
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        records.push(Parsed {
+            name,
+            kind: u16::from_be_bytes([fixed[0], fixed[1]]),
+            class: u16::from_be_bytes([fixed[2], fixed[3]]),
+            ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+            data: message[end + 10..end + 10 + len].to_vec(),
+        });
+        pos = end + 10 + len;
+    }
+    assert_eq!(pos, message.len(), "trailing data");
+
+    (counts, records)
+}
+
+fn query(id: u16, questions: &[(&str, u16)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0; 6]);
+    for (name, kind) in questions {
+        encode_name(&mut out, name).unwrap();
+        out.extend_from_slice(&kind.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+
+    out
+}
+
+#[test]
+fn records_are_encoded() {
+    assert_eq!(
+        a_record("a.local").encode(120, true).unwrap(),
+        [
+            &[1, b'a', 5][..],
+            b"local",
+            &[0, 0, 1, 0x80, 1, 0, 0, 0, 120, 0, 4, 192, 168, 1, 10],
+        ]
+        .concat()
+    );
+    // Cache flush is set only for unique records, in multicast responses
+    assert_eq!(
+        a_record("a.local").encode(0, false).unwrap()[11..13],
+        [0, 1]
+    );
+
+    let records = service_records(ALIAS, ADDR, 8443);
+    let encoded: Vec<_> = records
+        .iter()
+        .map(|record| record.encode(TTL, true).unwrap())
+        .collect();
+    let srv = &encoded[2][encoded[2].len() - 24..];
+    assert_eq!(&srv[..8], [0, 22, 0, 0, 0, 0, 0x20, 0xfb]);
+    assert_eq!(read_name(srv, 8).unwrap().0, ALIAS);
+
+    // Data of PTR follows its name, `_https._tcp.local` in 19 bytes, and the fixed fields
+    let ptr = &encoded[1];
+    assert_eq!(
+        read_name(ptr, 19 + 10),
+        Some(("app._https._tcp.local".into(), ptr.len()))
+    );
+    assert!(encoded[3].ends_with(&[0, 7, 6, b'p', b'a', b't', b'h', b'=', b'/']));
+}
+
+#[test]
+fn overlong_names_are_rejected() {
+    let label = "a".repeat(MAX_LABEL);
+    let mut out = vec![0xff];
+    assert!(encode_name(&mut out, &label).is_some());
+    assert_eq!(out.len(), 1 + 1 + MAX_LABEL + 1);
+
+    let mut out = Vec::new();
+    assert!(encode_name(&mut out, &format!("{}a.local", label)).is_none());
+    assert!(out.is_empty());
+
+    // 3 labels of 63 bytes and one of 61 take 255 bytes with their lengths and the root
+    let name = format!("{0}.{0}.{0}.{1}", label, &label[..61]);
+    assert!(encode_name(&mut out, &name).is_some());
+    assert_eq!(out.len(), MAX_NAME);
+    assert!(encode_name(&mut Vec::new(), &format!("{}a", name)).is_none());
+
+    let long = Record {
+        name: "x.local".into(),
+        data: Data::Txt(vec!["a".repeat(256)]),
+        unique: true,
+    };
+    assert!(long.encode(TTL, true).is_none());
+    let long_target = Record {
+        name: SERVICE_TYPE.into(),
+        data: Data::Ptr(format!("{}.local", "a".repeat(64))),
+        unique: false,
+    };
+    assert!(long_target.encode(TTL, true).is_none());
+
+    // Response leaves the record out, with the counts matching
+    let valid = a_record("a.local");
+    let overlong = a_record(&format!("{}.local", "a".repeat(64)));
+    let message = response(0, &[], &[&overlong, &valid], &[&overlong], TTL);
+    let (counts, records) = parse(&message);
+    assert_eq!(counts, [0, 1, 0, 0]);
+    assert_eq!(records[0].name, "a.local");
+}
+
+#[test]
+fn names_are_read_with_compression() {
+    let mut message = vec![0; 12];
+    encode_name(&mut message, "app.host.local").unwrap();
+    // `api` followed by pointer to `host.local`
+    message.extend_from_slice(&[3, b'a', b'p', b'i', 0xc0, 16]);
+    // Pointer to the whole first name
+    message.extend_from_slice(&[0xc0, 12]);
+
+    assert_eq!(read_name(&message, 12), Some(("app.host.local".into(), 28)));
+    assert_eq!(read_name(&message, 28), Some(("api.host.local".into(), 34)));
+    assert_eq!(read_name(&message, 34), Some(("app.host.local".into(), 36)));
+}
+
+#[test]
+fn malformed_names_are_rejected() {
+    // Pointer to itself
+    assert_eq!(read_name(&[0xc0, 0], 0), None);
+    // Pointers to each other
+    assert_eq!(read_name(&[0xc0, 2, 0xc0, 0], 0), None);
+    // Truncated label, pointer and missing root
+    assert_eq!(read_name(&[5, b'a', b'b'], 0), None);
+    assert_eq!(read_name(&[0xc0], 0), None);
+    assert_eq!(read_name(&[1, b'a'], 0), None);
+    // Pointer past the end
+    assert_eq!(read_name(&[0xc0, 10], 0), None);
+    // Reserved label types
+    assert_eq!(read_name(&[0x40, 0], 0), None);
+    assert_eq!(read_name(&[0x80, 0], 0), None);
+}
+
+/// Responder on the loopback with records of the shared service, and the socket of the client
+async fn responder() -> (Arc<Responder>, tokio::net::UdpSocket) {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let records = service_records(ALIAS, ADDR, 443);
+    let responder = Arc::new(Responder {
+        socket,
+        addr: ADDR,
+        port: 443,
+        services: Default::default(),
+        announced: Mutex::new(Announced {
+            records,
+            repeat: false,
+        }),
+    });
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    (responder, client)
+}
+
+/// Send query to the responder, returns its response if any
+async fn ask(
+    responder: &Responder,
+    client: &tokio::net::UdpSocket,
+    message: &[u8],
+) -> Option<Vec<u8>> {
+    let from: SocketAddr = client.local_addr().unwrap();
+    responder.answer(message, from).await;
+
+    let mut buf = vec![0; 9000];
+    let len = tokio::time::timeout(Duration::from_millis(100), client.recv(&mut buf))
+        .await
+        .ok()?
+        .unwrap();
+    buf.truncate(len);
+
+    Some(buf)
+}
+
+#[tokio::test]
+async fn one_shot_query_is_answered_directly() {
+    let (responder, client) = responder().await;
+
+    let message = query(0x1234, &[(ALIAS, TYPE_A)]);
+    let response = ask(&responder, &client, &message).await.unwrap();
+
+    assert_eq!(&response[..4], [0x12, 0x34, 0x84, 0x00]);
+    // Question is echoed for the conventional resolvers
+    assert_eq!(&response[12..message.len()], &message[12..]);
+    let (counts, records) = parse(&response);
+    assert_eq!(counts, [1, 1, 0, 0]);
+    assert_eq!(
+        records,
+        [Parsed {
+            name: ALIAS.into(),
+            kind: TYPE_A,
+            // No cache flush in the legacy responses
+            class: CLASS_IN,
+            ttl: LEGACY_TTL,
+            data: ADDR.octets().to_vec(),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn browsing_includes_records_of_the_instance() {
+    let (responder, client) = responder().await;
+
+    let response = ask(&responder, &client, &query(1, &[(SERVICE_TYPE, TYPE_PTR)]))
+        .await
+        .unwrap();
+    let (counts, records) = parse(&response);
+    assert_eq!(counts, [1, 1, 0, 3]);
+    assert_eq!(records[0].name, SERVICE_TYPE);
+    assert_eq!(records[0].kind, TYPE_PTR);
+
+    // SRV and TXT of the instance, and A of its target
+    let mut additional: Vec<_> = records[1..]
+        .iter()
+        .map(|record| (record.name.as_str(), record.kind))
+        .collect();
+    additional.sort();
+    assert_eq!(
+        additional,
+        [
+            ("app._https._tcp.local", TYPE_TXT),
+            ("app._https._tcp.local", TYPE_SRV),
+            (ALIAS, TYPE_A),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn queries_are_matched_by_name_and_type() {
+    let (responder, client) = responder().await;
+
+    // Names are case insensitive, ANY matches all types
+    let response = ask(
+        &responder,
+        &client,
+        &query(1, &[("APP.Host.Local", TYPE_ANY)]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(parse(&response).0, [1, 1, 0, 0]);
+
+    // Each question is answered, duplicates only once
+    let questions = [
+        (ALIAS, TYPE_A),
+        ("app._https._tcp.local", TYPE_SRV),
+        (ALIAS, TYPE_A),
+    ];
+    let response = ask(&responder, &client, &query(1, &questions))
+        .await
+        .unwrap();
+    let (counts, _) = parse(&response);
+    assert_eq!(counts[1], 2);
+
+    let unknown = [
+        query(1, &[("other.host.local", TYPE_A)]),
+        query(1, &[(ALIAS, TYPE_SRV)]),
+    ];
+    for message in unknown {
+        assert_eq!(ask(&responder, &client, &message).await, None);
+    }
+}
+
+#[tokio::test]
+async fn malformed_queries_are_ignored() {
+    let (responder, client) = responder().await;
+    let valid = query(1, &[(ALIAS, TYPE_A)]);
+
+    let mut response = valid.clone();
+    response[2] |= 0x80;
+    let mut missing_question = valid.clone();
+    missing_question[5] = 2;
+    let mut loop_ = valid[..12].to_vec();
+    loop_.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+
+    let malformed = [
+        Vec::new(),
+        valid[..11].to_vec(),
+        valid[..valid.len() - 1].to_vec(),
+        response,
+        missing_question,
+        loop_,
+    ];
+    for message in malformed {
+        assert_eq!(
+            ask(&responder, &client, &message).await,
+            None,
+            "{:?}",
+            message
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Connections being served, so the server can let them finish before it exits
+//!
+//! Every accepted connection, and every connection upgraded to another protocol, is tracked until
+//! its task ends. On shutdown the server stops accepting new ones and waits for the active ones to
+//! become idle, up to a timeout. HTTP connections are told to stop, so they close once their
+//! current requests are answered instead of being kept alive until the timeout.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use once_cell::sync::Lazy;
+use tokio::sync::{watch, Notify};
+
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+static IDLE: Lazy<Notify> = Lazy::new(Notify::new);
+static STOPPING: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+
+/// Marks the connection as active until dropped
+struct Guard;
+
+impl Guard {
+    fn new() -> Self {
+        ACTIVE.fetch_add(1, Ordering::SeqCst);
+        Guard
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if ACTIVE.fetch_sub(1, Ordering::SeqCst) == 1 {
+            IDLE.notify_waiters();
+        }
+    }
+}
+
+/// Track the connection served by the future, from now until it completes or is dropped
+pub fn track<F: Future>(connection: F) -> impl Future<Output = F::Output> {
+    let guard = Guard::new();
+
+    async move {
+        let _guard = guard;
+        connection.await
+    }
+}
+
+/// Amount of the connections being served right now
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Wait until no connections are served
+pub async fn idle() {
+    loop {
+        // Created before checking, so the notification sent in between is not missed
+        let notified = IDLE.notified();
+        if active() == 0 {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Ask the connections served with [`graceful`] to finish
+pub fn stop() {
+    STOPPING.send_replace(true);
+}
+
+/// Wait until the server is stopping
+///
+/// Responses streamed until the client goes away, which would keep their connections open even
+/// after the graceful shutdown, should end once it completes.
+pub async fn stopping() {
+    let mut stopping = STOPPING.subscribe();
+    while !*stopping.borrow_and_update() {
+        if stopping.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+}
+
+/// Serve the connection until it completes, calling `shutdown` on it once the server is stopping
+///
+/// Meant for HTTP connections, `shutdown` usually starts their graceful shutdown, after which
+/// they are still polled to answer the requests in progress.
+pub fn graceful<C, F>(connection: C, shutdown: F) -> Graceful<C, F>
+where
+    C: Future,
+    F: FnOnce(Pin<&mut C>),
+{
+    Graceful::new(connection, stopping(), shutdown)
+}
+
+/// Connection served by [`graceful`]
+pub struct Graceful<C, F> {
+    connection: Pin<Box<C>>,
+    stopping: Pin<Box<dyn Future<Output = ()> + Send>>,
+    shutdown: Option<F>,
+}
+
+impl<C, F> Graceful<C, F>
+where
+    F: FnOnce(Pin<&mut C>),
+{
+    fn new(
+        connection: C,
+        stopping: impl Future<Output = ()> + Send + 'static,
+        shutdown: F,
+    ) -> Self {
+        Graceful {
+            connection: Box::pin(connection),
+            stopping: Box::pin(stopping),
+            shutdown: Some(shutdown),
+        }
+    }
+}
+
+impl<C, F> Future for Graceful<C, F>
+where
+    C: Future,
+    F: FnOnce(Pin<&mut C>) + Unpin,
+{
+    type Output = C::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.shutdown.is_some() && this.stopping.as_mut().poll(cx).is_ready() {
+            let shutdown = this.shutdown.take().unwrap();
+            shutdown(this.connection.as_mut());
+        }
+
+        this.connection.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests;
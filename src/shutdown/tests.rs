@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::oneshot;
+
+use super::{active, idle, track, Graceful};
+
+#[tokio::test]
+async fn idle_waits_for_tracked_connections() {
+    let (finish, finished) = oneshot::channel::<()>();
+    let (_abort, aborted) = oneshot::channel::<()>();
+    let connection = tokio::spawn(track(finished));
+    let dropped = tokio::spawn(track(aborted));
+    // Other tests may track their connections too, so only the lower bound is known
+    assert!(active() >= 2);
+
+    let waiting = tokio::spawn(idle());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+
+    // Connection is no longer active once its task ends, also when it is cancelled
+    finish.send(()).unwrap();
+    connection.await.unwrap().unwrap();
+    dropped.abort();
+    tokio::time::timeout(Duration::from_secs(10), waiting)
+        .await
+        .expect("idle after connections finished")
+        .unwrap();
+}
+
+/// Serve HTTP/1.1 connection with keep-alive, stopped once `stopping` completes
+fn serve(
+    stopping: oneshot::Receiver<()>,
+) -> (
+    tokio::io::DuplexStream,
+    tokio::task::JoinHandle<hyper::Result<()>>,
+) {
+    let (client, server) = tokio::io::duplex(1024);
+    let service = service_fn(|_: Request<Body>| async {
+        Ok::<_, hyper::Error>(Response::new(Body::from("ok")))
+    });
+    let connection = hyper::server::conn::Http::new().serve_connection(server, service);
+    let stopping = async move {
+        stopping.await.ok();
+    };
+    let task = tokio::spawn(Graceful::new(connection, stopping, |connection| {
+        connection.graceful_shutdown()
+    }));
+
+    (client, task)
+}
+
+async fn request(client: &mut tokio::io::DuplexStream) {
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: app.localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0; 1024];
+    let len = client.read(&mut buf).await.unwrap();
+    assert!(buf[..len].starts_with(b"HTTP/1.1 200 OK\r\n"));
+}
+
+#[tokio::test]
+async fn idle_keep_alive_connection_closes_when_stopping() {
+    let (stop, stopping) = oneshot::channel();
+    let (mut client, connection) = serve(stopping);
+    request(&mut client).await;
+
+    // Kept alive for further requests until the server stops
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!connection.is_finished());
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(1), connection)
+        .await
+        .expect("connection closed")
+        .unwrap()
+        .unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn connection_is_served_without_stopping() {
+    let (_stop, stopping) = oneshot::channel();
+    let (mut client, connection) = serve(stopping);
+    request(&mut client).await;
+    request(&mut client).await;
+
+    // Closed by the client, the server was never stopping
+    drop(client);
+    connection.await.unwrap().unwrap();
+}
@@ -0,0 +1,365 @@
+//! OpenTelemetry tracing of the requests passing through the HTTP-aware proxy
+//!
+//! Each request gets a server span, which continues the trace from the W3C `traceparent` header
+//! sent by the client or starts a new one. The context of the span is passed to the application,
+//! so its spans become children of the proxy one. Spans are exported in batches to the collector
+//! using OTLP over HTTP with JSON encoding.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use http::header::{HeaderName, HeaderValue};
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+static EXPORTER: OnceCell<mpsc::Sender<SpanData>> = OnceCell::new();
+
+static TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+static TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+/// Maximal amount of spans sent in single export request
+const BATCH_SIZE: usize = 512;
+
+/// Maximal amount of spans waiting for export, newer ones are dropped
+const QUEUE_SIZE: usize = 4 * BATCH_SIZE;
+
+/// Time after which incomplete batch is exported
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// W3C trace context identifying the span
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// Whether the trace is recorded, other flags are not supported
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parse `traceparent` header value
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let (trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?);
+        // Future versions may append fields, but need to keep the ones we know
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+
+        let mut context = TraceContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: unhex::<1>(flags)?[0] & 1 == 1,
+        };
+        context.trace_id = unhex(trace_id)?;
+        context.span_id = unhex(span_id)?;
+
+        let valid = context.trace_id != [0; 16] && context.span_id != [0; 8];
+        valid.then_some(context)
+    }
+
+    /// Value of the `traceparent` header
+    pub fn header(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            u8::from(self.sampled)
+        )
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{:02x}", byte);
+        out
+    })
+}
+
+fn unhex<const N: usize>(value: &str) -> Option<[u8; N]> {
+    // Uppercase is not allowed by the specification
+    if value.len() != 2 * N
+        || !value
+            .bytes()
+            .all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[2 * index..2 * index + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Start exporting spans to the OTLP/HTTP collector at `endpoint`
+///
+/// Spans are posted to the `/v1/traces` path of the endpoint, unless it already has other path.
+pub fn init(endpoint: &str) -> std::io::Result<()> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    let uri = endpoint
+        .parse::<hyper::Uri>()
+        .map_err(|err| invalid(err.to_string()))?;
+    if uri.scheme() != Some(&http::uri::Scheme::HTTP) {
+        return Err(invalid("only http:// endpoints are supported".into()));
+    }
+
+    let mut parts = uri.into_parts();
+    if parts
+        .path_and_query
+        .as_ref()
+        .is_none_or(|path| path.path() == "/")
+    {
+        parts.path_and_query = Some(http::uri::PathAndQuery::from_static("/v1/traces"));
+    }
+    let uri = hyper::Uri::from_parts(parts).map_err(|err| invalid(err.to_string()))?;
+
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    if EXPORTER.set(sender).is_err() {
+        tracing::warn!("Tracing already initialized");
+        return Ok(());
+    }
+    tracing::info!(%uri, "Exporting traces");
+    tokio::spawn(export(uri, receiver));
+
+    Ok(())
+}
+
+async fn export(uri: hyper::Uri, mut receiver: mpsc::Receiver<SpanData>) {
+    let client = hyper::Client::new();
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+    let mut batch = Vec::new();
+
+    loop {
+        let flush = tokio::select! {
+            span = receiver.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    batch.len() >= BATCH_SIZE
+                }
+                None => return,
+            },
+            _ = interval.tick() => true,
+        };
+        if !flush || batch.is_empty() {
+            continue;
+        }
+
+        let body = encode(&batch).to_string();
+        tracing::trace!(spans = batch.len(), "Export spans");
+        batch.clear();
+
+        let request = hyper::Request::post(uri.clone())
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(body))
+            .unwrap();
+        match client.request(request).await {
+            Ok(response) if response.status().is_success() => (),
+            Ok(response) => tracing::warn!(status = %response.status(), "Spans rejected"),
+            Err(err) => tracing::warn!(%err, "Cannot export spans"),
+        }
+    }
+}
+
+/// Export request in the OTLP JSON encoding
+fn encode(spans: &[SpanData]) -> Value {
+    let nanos = |time: SystemTime| {
+        let since = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        since.as_nanos().to_string()
+    };
+
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| {
+            let mut value = json!({
+                "traceId": hex(&span.context.trace_id),
+                "spanId": hex(&span.context.span_id),
+                "name": span.name,
+                // Server
+                "kind": 2,
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.end),
+                "attributes": span.attributes.iter().map(|(key, value)| json!({
+                    "key": key,
+                    "value": match value {
+                        Attribute::String(value) => json!({ "stringValue": value }),
+                        Attribute::Int(value) => json!({ "intValue": value.to_string() }),
+                    },
+                })).collect::<Vec<_>>(),
+                "status": match span.error {
+                    Some(ref message) => json!({ "code": 2, "message": message }),
+                    None => json!({ "code": 0 }),
+                },
+            });
+            if let Some(parent) = span.parent {
+                value["parentSpanId"] = hex(&parent).into();
+            }
+            if let Some(ref state) = span.trace_state {
+                value["traceState"] = state.clone().into();
+            }
+            value
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": env!("CARGO_PKG_NAME") } },
+                    { "key": "service.version", "value": { "stringValue": env!("CARGO_PKG_VERSION") } },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[derive(Debug)]
+enum Attribute {
+    String(String),
+    Int(i64),
+}
+
+#[derive(Debug)]
+struct SpanData {
+    context: TraceContext,
+    parent: Option<[u8; 8]>,
+    trace_state: Option<String>,
+    name: String,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, Attribute)>,
+    error: Option<String>,
+}
+
+/// Span of the proxied request, ended and exported when dropped
+pub struct Span {
+    data: Option<SpanData>,
+}
+
+impl Span {
+    /// Start span for the request when tracing is enabled, and pass its context downstream
+    pub fn start<B>(
+        service: &str,
+        client: SocketAddr,
+        req: &mut hyper::Request<B>,
+    ) -> Option<Self> {
+        EXPORTER.get()?;
+
+        let headers = req.headers();
+        let header = |name: &HeaderName| headers.get(name).and_then(|value| value.to_str().ok());
+        let parent = header(&TRACEPARENT).and_then(TraceContext::parse);
+        let trace_state = header(&TRACESTATE).map(str::to_owned);
+
+        let context = TraceContext {
+            trace_id: parent.map_or_else(rand::random, |parent| parent.trace_id),
+            span_id: rand::random(),
+            sampled: parent.is_none_or(|parent| parent.sampled),
+        };
+
+        let mut attributes = vec![
+            (
+                "http.request.method",
+                Attribute::String(req.method().to_string()),
+            ),
+            ("url.scheme", Attribute::String("https".into())),
+            ("url.path", Attribute::String(req.uri().path().into())),
+            (
+                "client.address",
+                Attribute::String(client.ip().to_canonical().to_string()),
+            ),
+            ("dolores.service", Attribute::String(service.into())),
+        ];
+        if let Some(query) = req.uri().query() {
+            attributes.push(("url.query", Attribute::String(query.into())));
+        }
+        if let Some(host) = header(&http::header::HOST).or(req.uri().host()) {
+            attributes.push(("server.address", Attribute::String(host.into())));
+        }
+        if let Some(agent) = header(&http::header::USER_AGENT) {
+            attributes.push(("user_agent.original", Attribute::String(agent.into())));
+        }
+        let version = match req.version() {
+            http::Version::HTTP_10 => "1.0",
+            http::Version::HTTP_2 => "2",
+            _ => "1.1",
+        };
+        attributes.push((
+            "network.protocol.version",
+            Attribute::String(version.into()),
+        ));
+
+        let data = SpanData {
+            context,
+            parent: parent.map(|parent| parent.span_id),
+            trace_state,
+            name: req.method().to_string(),
+            start: SystemTime::now(),
+            end: SystemTime::now(),
+            attributes,
+            error: None,
+        };
+
+        // Trace state is passed as is, as we do not add any entries there
+        if let Ok(value) = HeaderValue::from_str(&context.header()) {
+            req.headers_mut().insert(&TRACEPARENT, value);
+        }
+
+        Some(Span {
+            data: context.sampled.then_some(data),
+        })
+    }
+
+    /// End the span once the response is sent, returns response that needs to be passed further
+    pub fn response(mut self, resp: hyper::Response<hyper::Body>) -> hyper::Response<hyper::Body> {
+        let status = resp.status();
+        if let Some(ref mut data) = self.data {
+            data.attributes.push((
+                "http.response.status_code",
+                Attribute::Int(status.as_u16().into()),
+            ));
+            if status.is_server_error() {
+                data.error = Some(status.to_string());
+            }
+        }
+
+        let (parts, body) = resp.into_parts();
+        if self.data.is_none() {
+            return hyper::Response::from_parts(parts, body);
+        }
+
+        // Span ends once the body is dropped
+        let body = crate::proxy::inspect_body(body, |_| (), move || drop(self));
+
+        hyper::Response::from_parts(parts, body)
+    }
+
+    /// End the span with the reason why there is no response
+    pub fn error(mut self, error: &dyn std::fmt::Display) {
+        if let Some(ref mut data) = self.data {
+            data.error = Some(error.to_string());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let (mut data, exporter) = match (self.data.take(), EXPORTER.get()) {
+            (Some(data), Some(exporter)) => (data, exporter),
+            _ => return,
+        };
+        data.end = SystemTime::now();
+
+        if exporter.try_send(data).is_err() {
+            tracing::debug!("Span dropped, export queue is full");
+        }
+    }
+}